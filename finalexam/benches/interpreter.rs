@@ -0,0 +1,111 @@
+// Interpreter benchmarks: `cargo bench --bench interpreter`. See benches/parser.rs for comparing
+// against a saved baseline.
+
+extern crate asalang;
+extern crate criterion;
+
+use asalang::{program, Runtime, Value};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const FIB: &str = "
+fn fib(n) {
+  if n < 2 {
+    return n;
+  } else {
+    let a = fib(n - 1);
+    let b = fib(n - 2);
+    return a + b;
+  }
+}
+";
+
+const ACKERMANN: &str = "
+fn ack(m, n) {
+  if m == 0 {
+    return n + 1;
+  } else if n == 0 {
+    return ack(m - 1, 1);
+  } else {
+    let inner = ack(m, n - 1);
+    return ack(m - 1, inner);
+  }
+}
+";
+
+// Strings passed through calls, compared, and matched on.
+const WORDS: &str = r#"
+fn classify(word) {
+  return match word { "apple" => "fruit", "pear" => "fruit", "carrot" => "vegetable", _ => "unknown" };
+}
+fn same(a, b) {
+  if a == b { return 1; } else { return 0; }
+}
+fn words(n) {
+  if n == 0 {
+    return "done";
+  } else {
+    let a = classify("apple");
+    let b = classify("carrot");
+    let c = classify("stone");
+    let d = same(a, b);
+    return words(n - 1);
+  }
+}
+"#;
+
+// `fn pick(x)` with an arm for each of 0 to `arms - 1`, each returning ten times `x`.
+fn if_chain(arms: usize) -> String {
+  let mut source = String::from("fn pick(x) {\n  if x == 0 { return 0; }\n");
+  for ix in 1..arms {
+    source.push_str(&format!("  else if x == {} {{ return {}; }}\n", ix, ix * 10));
+  }
+  source.push_str("  else { return 1; }\n}\n");
+  source
+}
+
+fn loaded(source: &str) -> Runtime {
+  let (rest, tree) = program(source).unwrap();
+  assert_eq!(rest, "");
+  let mut runtime = Runtime::new();
+  runtime.load(&tree).unwrap();
+  runtime
+}
+
+fn recursion(c: &mut Criterion) {
+  let mut group = c.benchmark_group("recursion");
+  let mut runtime = loaded(FIB);
+  for n in [10, 15, 20] {
+    group.bench_with_input(BenchmarkId::new("fib", n), &n, |b, n| {
+      b.iter(|| runtime.call("fib", &[Value::Number(black_box(*n))]).unwrap())
+    });
+  }
+  let mut runtime = loaded(ACKERMANN);
+  for n in [2, 4] {
+    group.bench_with_input(BenchmarkId::new("ackermann 2", n), &n, |b, n| {
+      b.iter(|| runtime.call("ack", &[Value::Number(2), Value::Number(black_box(*n))]).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn strings(c: &mut Criterion) {
+  let mut runtime = loaded(WORDS);
+  assert_eq!(runtime.call("words", &[Value::Number(10)]), Ok(Value::String("done".to_string())));
+  c.bench_function("strings", |b| b.iter(|| runtime.call("words", &[Value::Number(black_box(100))]).unwrap()));
+}
+
+fn if_chains(c: &mut Criterion) {
+  let mut group = c.benchmark_group("if chain");
+  for arms in [10, 100, 500] {
+    let mut runtime = loaded(&if_chain(arms));
+    let last = arms as i32 - 1;
+    assert_eq!(runtime.call("pick", &[Value::Number(last)]), Ok(Value::Number(last * 10)));
+    group.bench_with_input(BenchmarkId::from_parameter(arms), &last, |b, last| {
+      b.iter(|| runtime.call("pick", &[Value::Number(black_box(*last))]).unwrap())
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, recursion, strings, if_chains);
+criterion_main!(benches);
@@ -0,0 +1,174 @@
+// Lowering benchmarks: `cargo bench --bench lowering`.
+//
+// Compares the runtime, which runs functions lowered into an arena with interned names and frame
+// slots, against evaluating the syntax tree directly with frames keyed by variable name, the way the
+// runtime did before `lower` existed. The tree walker below only covers what the benchmarked programs
+// use: numbers, variables, math, comparisons, calls, `let`, `return` and if/else.
+
+extern crate asalang;
+extern crate criterion;
+
+use asalang::ast::{BinaryOp, Expr, FnDecl, Item, Stmt};
+use asalang::{program, Runtime, Value};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const FIB: &str = "
+fn fib(n) {
+  if n < 2 {
+    return n;
+  } else {
+    let a = fib(n - 1);
+    let b = fib(n - 2);
+    return a + b;
+  }
+}
+";
+
+const ACKERMANN: &str = "
+fn ack(m, n) {
+  if m == 0 {
+    return n + 1;
+  } else if n == 0 {
+    return ack(m - 1, 1);
+  } else {
+    let inner = ack(m, n - 1);
+    return ack(m - 1, inner);
+  }
+}
+";
+
+type Outcome = Result<Value, &'static str>;
+
+// The syntax tree evaluated as is: functions looked up by name and variables in a map per call.
+struct TreeWalker {
+  functions: HashMap<String, Rc<FnDecl>>,
+  stack: Vec<HashMap<String, Value>>,
+}
+
+impl TreeWalker {
+  fn new(source: &str) -> TreeWalker {
+    let (_, tree) = program(source).unwrap();
+    let functions = tree.items.iter().filter_map(|item| match item {
+      Item::Fn(function) => Some((function.name.clone(), Rc::new(function.clone()))),
+      _ => None,
+    }).collect();
+    TreeWalker { functions, stack: vec![] }
+  }
+
+  fn call(&mut self, name: &str, args: Vec<Value>) -> Outcome {
+    let function = self.functions.get(name).cloned().ok_or("Undefined function")?;
+    if function.params.len() != args.len() {
+      return Err("Wrong number of arguments");
+    }
+    self.stack.push(function.params.iter().cloned().zip(args).collect());
+    let mut result = Err("Undefined function");
+    for stmt in &function.body {
+      result = self.statement(stmt);
+      if result.is_err() {
+        break;
+      }
+    }
+    self.stack.pop();
+    result
+  }
+
+  fn statement(&mut self, stmt: &Stmt) -> Outcome {
+    match stmt {
+      Stmt::Let { name, value } => {
+        let value = self.expression(value)?;
+        self.stack.last_mut().unwrap().insert(name.clone(), value.clone());
+        Ok(value)
+      },
+      Stmt::Return(value) | Stmt::Expr(value) => self.expression(value),
+      Stmt::If(if_) => {
+        let mut arms = vec![(Some(&if_.cond), &if_.then)];
+        arms.extend(if_.elifs.iter().map(|(cond, body)| (Some(cond), body)));
+        arms.extend(if_.else_.iter().map(|body| (None, body)));
+        for (cond, body) in arms {
+          if let Some(cond) = cond {
+            if self.expression(cond)? != Value::Bool(true) {
+              continue;
+            }
+          }
+          for stmt in body {
+            let value = self.statement(stmt)?;
+            if let Stmt::Return(_) = stmt {
+              return Ok(value);
+            }
+          }
+          return Ok(Value::Bool(true));
+        }
+        Ok(Value::Bool(true))
+      },
+      _ => Err("Not benchmarked"),
+    }
+  }
+
+  fn expression(&mut self, expr: &Expr) -> Outcome {
+    match expr {
+      Expr::Number(value) => Ok(Value::Number(*value)),
+      Expr::Identifier(name) => self.stack.last().and_then(|frame| frame.get(name)).cloned().ok_or("Undefined variable"),
+      Expr::Binary { op, lhs, rhs } => {
+        let (lhs, rhs) = match (self.expression(lhs)?, self.expression(rhs)?) {
+          (Value::Number(lhs), Value::Number(rhs)) => (lhs, rhs),
+          _ => return Err("Cannot do math on String or Bool"),
+        };
+        let number = |value: Option<i32>| value.map(Value::Number).ok_or("Number out of range");
+        match op {
+          BinaryOp::Add => number(lhs.checked_add(rhs)),
+          BinaryOp::Sub => number(lhs.checked_sub(rhs)),
+          BinaryOp::Mul => number(lhs.checked_mul(rhs)),
+          BinaryOp::Eq => Ok(Value::Bool(lhs == rhs)),
+          BinaryOp::Lt => Ok(Value::Bool(lhs < rhs)),
+          _ => Err("Not benchmarked"),
+        }
+      },
+      Expr::Call { name, args } => {
+        let mut values = vec![];
+        for arg in args {
+          values.push(self.expression(arg)?);
+        }
+        self.call(name, values)
+      },
+      _ => Err("Not benchmarked"),
+    }
+  }
+}
+
+fn loaded(source: &str) -> Runtime {
+  let (_, tree) = program(source).unwrap();
+  let mut runtime = Runtime::new();
+  runtime.load(&tree).unwrap();
+  runtime
+}
+
+fn compare(c: &mut Criterion, group: &str, source: &str, function: &str, inputs: &[Vec<i32>]) {
+  let mut group = c.benchmark_group(group);
+  let mut runtime = loaded(source);
+  let mut walker = TreeWalker::new(source);
+  for args in inputs {
+    let args: Vec<Value> = args.iter().map(|arg| Value::Number(*arg)).collect();
+    let label = format!("{:?}", args);
+    assert_eq!(walker.call(function, args.clone()), runtime.call(function, &args));
+    group.bench_with_input(BenchmarkId::new("syntax tree", &label), &args, |b, args| {
+      b.iter(|| walker.call(function, black_box(args.clone())).unwrap())
+    });
+    group.bench_with_input(BenchmarkId::new("lowered", &label), &args, |b, args| {
+      b.iter(|| runtime.call(function, black_box(args)).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn fib(c: &mut Criterion) {
+  compare(c, "fib", FIB, "fib", &[vec![15], vec![20]]);
+}
+
+fn ackermann(c: &mut Criterion) {
+  compare(c, "ackermann", ACKERMANN, "ack", &[vec![2, 4]]);
+}
+
+criterion_group!(benches, fib, ackermann);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+// Parser benchmarks: `cargo bench --bench parser`.
+//
+// Criterion keeps the last run under target/criterion and reports the change against it. To guard a
+// change, save a baseline first with `cargo bench -- --save-baseline before` and compare with
+// `cargo bench -- --baseline before`.
+
+extern crate asalang;
+extern crate criterion;
+
+use asalang::{program, program_with_recovery};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// `count` functions, each calling the one before it.
+fn many_functions(count: usize) -> String {
+  let mut source = String::from("fn f0(a) { return a + 1; }\n");
+  for ix in 1..count {
+    source.push_str(&format!("fn f{}(a) {{\n  let b = f{}(a);\n  if b > 10 {{ return b - 10; }} else {{ return b * 2; }}\n}}\n", ix, ix - 1));
+  }
+  source
+}
+
+// A sum nested `depth` parentheses deep: `((1 + 1) + 1)` for a depth of 2. Each level should be parsed
+// once, so the time grows linearly with the depth.
+fn nested_parens(depth: usize) -> String {
+  format!("fn main() {{ return {}1{}; }}", "(".repeat(depth), " + 1)".repeat(depth))
+}
+
+fn functions(c: &mut Criterion) {
+  let mut group = c.benchmark_group("parse functions");
+  for count in [100, 1000, 5000] {
+    let source = many_functions(count);
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_with_input(BenchmarkId::new("program", count), &source, |b, source| {
+      b.iter(|| program(black_box(source)).unwrap())
+    });
+    group.bench_with_input(BenchmarkId::new("program_with_recovery", count), &source, |b, source| {
+      b.iter(|| program_with_recovery(black_box(source)))
+    });
+  }
+  group.finish();
+}
+
+fn parentheses(c: &mut Criterion) {
+  let mut group = c.benchmark_group("parse nested parentheses");
+  for depth in [1, 4, 16, 64] {
+    let source = nested_parens(depth);
+    group.bench_with_input(BenchmarkId::from_parameter(depth), &source, |b, source| {
+      b.iter(|| program(black_box(source)).unwrap())
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, functions, parentheses);
+criterion_main!(benches);
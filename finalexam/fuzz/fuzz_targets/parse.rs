@@ -0,0 +1,19 @@
+// Any input, valid or not, has to come back from both parsers and the formatter as a tree, syntax
+// errors or a `FormatError`, never as a panic.
+
+#![no_main]
+
+use asalang::{format_source, program, program_with_recovery, warnings};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let source = match std::str::from_utf8(data) {
+    Ok(source) => source,
+    Err(_) => return,
+  };
+  if let Ok((_, tree)) = program(source) {
+    let _ = warnings(&tree);
+  }
+  let _ = program_with_recovery(source);
+  let _ = format_source(source);
+});
@@ -0,0 +1,34 @@
+// Any program that parses has to run to a value or an error, optimized or not, never to a panic. Its
+// I/O stays in memory, and a step limit ends programs that would otherwise run forever.
+
+#![no_main]
+
+use asalang::stdlib::MemoryIo;
+use asalang::{optimize, program, Debugger, Runtime, Stmt};
+use libfuzzer_sys::fuzz_target;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct StepLimit(usize);
+
+impl Debugger for StepLimit {
+  fn before_statement(&mut self, _runtime: &Runtime, _statement: &Stmt) -> Result<(), &'static str> {
+    self.0 = self.0.checked_sub(1).ok_or("Step limit reached")?;
+    Ok(())
+  }
+}
+
+fuzz_target!(|data: &[u8]| {
+  let source = match std::str::from_utf8(data) {
+    Ok(source) => source,
+    Err(_) => return,
+  };
+  if let Ok((_, tree)) = program(source) {
+    for tree in [optimize(&tree), tree] {
+      let mut runtime = Runtime::new();
+      runtime.set_io(Rc::new(RefCell::new(MemoryIo::default())));
+      runtime.set_debugger(Box::new(StepLimit(10_000)));
+      let _ = runtime.start(&tree);
+    }
+  }
+});
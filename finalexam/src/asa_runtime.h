@@ -0,0 +1,173 @@
+/* The runtime every C file from codegen_c starts with: tagged values, the math and comparisons
+ * with the interpreter's errors, and printing. */
+
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+typedef enum { ASA_UNSET, ASA_NUMBER, ASA_BOOL, ASA_STRING, ASA_ERROR } asa_tag;
+
+/* A string or error points at a literal, so values never own memory. */
+typedef struct {
+  asa_tag tag;
+  int64_t number;
+  const char *string;
+} asa_value;
+
+enum { ASA_ADD, ASA_SUB, ASA_MUL, ASA_DIV, ASA_POW, ASA_EQ, ASA_NE, ASA_LT, ASA_GT, ASA_LE, ASA_GE };
+
+/* Active calls, against ASA_MAX_CALL_DEPTH, which the generated code defines. */
+static int asa_depth = 0;
+
+static inline asa_value asa_number(int64_t number) {
+  asa_value value = { ASA_NUMBER, number, NULL };
+  return value;
+}
+
+static inline asa_value asa_bool(int b) {
+  asa_value value = { ASA_BOOL, b != 0, NULL };
+  return value;
+}
+
+static inline asa_value asa_string(const char *string) {
+  asa_value value = { ASA_STRING, 0, string };
+  return value;
+}
+
+static inline asa_value asa_error(const char *message) {
+  asa_value value = { ASA_ERROR, 0, message };
+  return value;
+}
+
+static inline asa_value asa_unset(void) {
+  asa_value value = { ASA_UNSET, 0, NULL };
+  return value;
+}
+
+static inline int asa_is_error(asa_value value) {
+  return value.tag == ASA_ERROR;
+}
+
+static inline int asa_is_bool(asa_value value, int b) {
+  return value.tag == ASA_BOOL && value.number == (b != 0);
+}
+
+static inline asa_value asa_read(asa_value slot) {
+  return slot.tag == ASA_UNSET ? asa_error("Undefined variable") : slot;
+}
+
+/* Numbers are 32-bit; a result outside that range is an error rather than a wrapped value. */
+static inline asa_value asa_checked(int64_t number) {
+  if (number < INT32_MIN || number > INT32_MAX) {
+    return asa_error("Number out of range");
+  }
+  return asa_number(number);
+}
+
+static inline asa_value asa_pow(int64_t base, int64_t power) {
+  int64_t result = 1;
+  if (power < 0) {
+    return asa_number(1);
+  }
+  if (base == 0 || base == 1) {
+    return asa_number(power == 0 ? 1 : base);
+  }
+  if (base == -1) {
+    return asa_number(power % 2 == 0 ? 1 : -1);
+  }
+  /* Any other base leaves the 32-bit range within 32 steps. */
+  while (power-- > 0) {
+    result *= base;
+    if (result < INT32_MIN || result > INT32_MAX) {
+      return asa_error("Number out of range");
+    }
+  }
+  return asa_number(result);
+}
+
+static inline asa_value asa_math(int op, asa_value lhs, asa_value rhs) {
+  if (lhs.tag != ASA_NUMBER || rhs.tag != ASA_NUMBER) {
+    return asa_error("Cannot do math on String or Bool");
+  }
+  switch (op) {
+    case ASA_ADD: return asa_checked(lhs.number + rhs.number);
+    case ASA_SUB: return asa_checked(lhs.number - rhs.number);
+    case ASA_MUL: return asa_checked(lhs.number * rhs.number);
+    case ASA_DIV: return rhs.number == 0 ? asa_error("Division by zero") : asa_checked(lhs.number / rhs.number);
+    case ASA_POW: return asa_pow(lhs.number, rhs.number);
+    default: return asa_error("Undefined operator");
+  }
+}
+
+/* Errors are values here too: `==` between two failed evaluations holds if they failed the same way. */
+static inline int asa_equal(asa_value lhs, asa_value rhs) {
+  if (lhs.tag != rhs.tag) {
+    return 0;
+  }
+  if (lhs.tag == ASA_STRING || lhs.tag == ASA_ERROR) {
+    return strcmp(lhs.string, rhs.string) == 0;
+  }
+  return lhs.number == rhs.number;
+}
+
+static inline asa_value asa_compare(int op, asa_value lhs, asa_value rhs) {
+  if (op == ASA_EQ) {
+    return asa_bool(asa_equal(lhs, rhs));
+  }
+  if (op == ASA_NE) {
+    return asa_bool(!asa_equal(lhs, rhs));
+  }
+  if (lhs.tag != ASA_NUMBER || rhs.tag != ASA_NUMBER) {
+    switch (op) {
+      case ASA_GT: return asa_error("Unsuccesful interpreting > comparison");
+      case ASA_LT: return asa_error("Unsuccesful interpreting < comparison");
+      case ASA_GE: return asa_error("Unsuccesful interpreting >= comparison");
+      case ASA_LE: return asa_error("Unsuccesful interpreting <= comparison");
+      default: return asa_error("Invalid operator");
+    }
+  }
+  switch (op) {
+    case ASA_GT: return asa_bool(lhs.number > rhs.number);
+    case ASA_LT: return asa_bool(lhs.number < rhs.number);
+    case ASA_GE: return asa_bool(lhs.number >= rhs.number);
+    case ASA_LE: return asa_bool(lhs.number <= rhs.number);
+    default: return asa_error("Invalid operator");
+  }
+}
+
+/* How `print` shows a value: strings without quotes. */
+static inline void asa_write(FILE *out, asa_value value) {
+  switch (value.tag) {
+    case ASA_NUMBER: fprintf(out, "%lld", (long long) value.number); break;
+    case ASA_BOOL: fputs(value.number ? "true" : "false", out); break;
+    default: fputs(value.string, out); break;
+  }
+}
+
+/* print(...) and println(...): the values separated by spaces. */
+static inline asa_value asa_print(int count, const asa_value *args, int newline) {
+  int i;
+  for (i = 0; i < count; i++) {
+    if (i > 0) {
+      fputc(' ', stdout);
+    }
+    asa_write(stdout, args[i]);
+  }
+  if (newline) {
+    fputc('\n', stdout);
+  }
+  return asa_bool(1);
+}
+
+/* Ends the program the way `asa run` does: the value main returned on stdout, or the error on stderr. */
+static inline int asa_finish(asa_value result) {
+  switch (result.tag) {
+    case ASA_NUMBER: printf("Number(%lld)\n", (long long) result.number); return 0;
+    case ASA_BOOL: printf("Bool(%s)\n", result.number ? "true" : "false"); return 0;
+    case ASA_STRING: printf("String(\"%s\")\n", result.string); return 0;
+    default:
+      fflush(stdout);
+      fprintf(stderr, "runtime error: %s\n", result.string);
+      return 1;
+  }
+}
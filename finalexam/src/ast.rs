@@ -0,0 +1,301 @@
+// The syntax tree of an Asa program, as both parsers build it and the runtime runs it.
+//
+// A program is a list of items. Functions hold statements, and statements hold expressions, which
+// nest through `Box`. Parentheses leave nothing behind: `(1 + 2) * 3` is a `Binary` whose left side is
+// another `Binary`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+  pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+  // `import utils;` has no path and loads utils.asa; `import "lib/utils.asa";` is named after the file.
+  Import { name: String, path: Option<String> },
+  Struct { name: String, fields: Vec<String> },
+  Enum { name: String, variants: Vec<VariantDecl> },
+  // The methods of a struct: functions whose first parameter is the value they're called on.
+  Impl { name: String, methods: Vec<FnDecl> },
+  Fn(FnDecl),
+  // `test "adds numbers" { ... }`, which only `asa test` runs. `test` is not a reserved word; it only
+  // starts a test in front of a string.
+  Test { name: String, body: Vec<Stmt> },
+  // A top-level `if` runs while the program loads; any other statement, or an expression, becomes
+  // the body of `main`.
+  Stmt(Stmt),
+  Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantDecl {
+  pub name: String,
+  pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnDecl {
+  // The `///` comments in front of the function, without the slashes.
+  pub doc: String,
+  pub name: String,
+  pub params: Vec<String>,
+  pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+  Let { name: String, value: Expr },
+  // `p.x = 1`; the target is a `Field`.
+  Assign { target: Expr, value: Expr },
+  Return(Expr),
+  Throw(Expr),
+  // A function call on its own, `print(x);`.
+  Expr(Expr),
+  If(If),
+  Match(Match),
+  Try { body: Vec<Stmt>, variable: String, handler: Vec<Stmt> },
+}
+
+impl Stmt {
+  // Whether the runtime calls its debugger before this statement. Match and try statements only hold
+  // other statements, so the debugger stops at those instead.
+  pub fn is_step(&self) -> bool {
+    !matches!(self, Stmt::Match(_) | Stmt::Try { .. })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+  pub cond: Expr,
+  pub then: Vec<Stmt>,
+  // `else if` arms, in order.
+  pub elifs: Vec<(Expr, Vec<Stmt>)>,
+  pub else_: Option<Vec<Stmt>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+  pub scrutinee: Expr,
+  pub arms: Vec<MatchArm>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+  // Alternatives, `1 | 2`; the arm is taken if any of them matches.
+  pub patterns: Vec<Pattern>,
+  pub guard: Option<Expr>,
+  pub body: ArmBody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArmBody {
+  Expr(Expr),
+  Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Number(i32),
+  Bool(bool),
+  String(String),
+  Identifier(String),
+  Binary { op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr> },
+  // A function, `utils.helper(x)` in an imported module, or a method, `p.dist()`.
+  Call { name: String, args: Vec<Expr> },
+  // `Point { x: 1, y: 2 }`
+  Struct { name: String, fields: Vec<(String, Expr)> },
+  // `p.x`
+  Field { object: Box<Expr>, field: String },
+  // `Shape::Circle(2)`
+  Variant { name: String, variant: String, args: Vec<Expr> },
+  Match(Box<Match>),
+  Throw(Box<Expr>),
+  // `let pattern = value` as the condition of an if; true if the value matches.
+  Let { pattern: Pattern, value: Box<Expr> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Pow,
+  Eq,
+  Ne,
+  Lt,
+  Gt,
+  Le,
+  Ge,
+}
+
+impl BinaryOp {
+  pub fn from_symbol(symbol: &str) -> Option<BinaryOp> {
+    Some(match symbol {
+      "+" => BinaryOp::Add,
+      "-" => BinaryOp::Sub,
+      "*" => BinaryOp::Mul,
+      "/" => BinaryOp::Div,
+      "^" => BinaryOp::Pow,
+      "==" => BinaryOp::Eq,
+      "!=" => BinaryOp::Ne,
+      "<" => BinaryOp::Lt,
+      ">" => BinaryOp::Gt,
+      "<=" => BinaryOp::Le,
+      ">=" => BinaryOp::Ge,
+      _ => return None,
+    })
+  }
+
+  pub fn symbol(self) -> &'static str {
+    match self {
+      BinaryOp::Add => "+",
+      BinaryOp::Sub => "-",
+      BinaryOp::Mul => "*",
+      BinaryOp::Div => "/",
+      BinaryOp::Pow => "^",
+      BinaryOp::Eq => "==",
+      BinaryOp::Ne => "!=",
+      BinaryOp::Lt => "<",
+      BinaryOp::Gt => ">",
+      BinaryOp::Le => "<=",
+      BinaryOp::Ge => ">=",
+    }
+  }
+
+  pub fn is_comparison(self) -> bool {
+    self.precedence() == 0
+  }
+
+  // Binding strength, matching the parser's levels: comparisons, then l1 (`+ -`), l2 (`* /`) and
+  // l3 (`^`). Every level is left-associative.
+  pub fn precedence(self) -> u8 {
+    match self {
+      BinaryOp::Add | BinaryOp::Sub => 1,
+      BinaryOp::Mul | BinaryOp::Div => 2,
+      BinaryOp::Pow => 3,
+      _ => 0,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+  Wildcard,
+  Number(i32),
+  Bool(bool),
+  String(String),
+  // A name, which matches anything and binds it.
+  Binding(String),
+  // `Shape::Rect(w, _)`
+  Variant { name: String, variant: String, fields: Vec<Pattern> },
+}
+
+// A read-only walk over a tree. `visit_stmt` and `visit_expr` see every statement and expression,
+// outer ones before the ones inside them, in source order.
+pub trait Visitor<'a> {
+  fn visit_stmt(&mut self, _stmt: &'a Stmt) {}
+  fn visit_expr(&mut self, _expr: &'a Expr) {}
+}
+
+pub fn walk_program<'a>(program: &'a Program, visitor: &mut impl Visitor<'a>) {
+  for item in &program.items {
+    walk_item(item, visitor);
+  }
+}
+
+pub fn walk_item<'a>(item: &'a Item, visitor: &mut impl Visitor<'a>) {
+  match item {
+    Item::Fn(function) => walk_stmts(&function.body, visitor),
+    Item::Test { body, .. } => walk_stmts(body, visitor),
+    Item::Impl { methods, .. } => {
+      for method in methods {
+        walk_stmts(&method.body, visitor);
+      }
+    },
+    Item::Stmt(stmt) => walk_stmt(stmt, visitor),
+    Item::Expr(expr) => walk_expr(expr, visitor),
+    Item::Import { .. } | Item::Struct { .. } | Item::Enum { .. } => (),
+  }
+}
+
+pub fn walk_stmts<'a>(stmts: &'a [Stmt], visitor: &mut impl Visitor<'a>) {
+  for stmt in stmts {
+    walk_stmt(stmt, visitor);
+  }
+}
+
+pub fn walk_stmt<'a>(stmt: &'a Stmt, visitor: &mut impl Visitor<'a>) {
+  visitor.visit_stmt(stmt);
+  match stmt {
+    Stmt::Let { value, .. } | Stmt::Return(value) | Stmt::Throw(value) | Stmt::Expr(value) => walk_expr(value, visitor),
+    Stmt::Assign { target, value } => {
+      walk_expr(target, visitor);
+      walk_expr(value, visitor);
+    },
+    Stmt::If(if_) => {
+      walk_expr(&if_.cond, visitor);
+      walk_stmts(&if_.then, visitor);
+      for (cond, body) in &if_.elifs {
+        walk_expr(cond, visitor);
+        walk_stmts(body, visitor);
+      }
+      if let Some(body) = &if_.else_ {
+        walk_stmts(body, visitor);
+      }
+    },
+    Stmt::Match(match_) => walk_match(match_, visitor),
+    Stmt::Try { body, handler, .. } => {
+      walk_stmts(body, visitor);
+      walk_stmts(handler, visitor);
+    },
+  }
+}
+
+pub fn walk_expr<'a>(expr: &'a Expr, visitor: &mut impl Visitor<'a>) {
+  visitor.visit_expr(expr);
+  match expr {
+    Expr::Number(_) | Expr::Bool(_) | Expr::String(_) | Expr::Identifier(_) => (),
+    Expr::Binary { lhs, rhs, .. } => {
+      walk_expr(lhs, visitor);
+      walk_expr(rhs, visitor);
+    },
+    Expr::Call { args, .. } | Expr::Variant { args, .. } => {
+      for arg in args {
+        walk_expr(arg, visitor);
+      }
+    },
+    Expr::Struct { fields, .. } => {
+      for (_, value) in fields {
+        walk_expr(value, visitor);
+      }
+    },
+    Expr::Field { object, .. } => walk_expr(object, visitor),
+    Expr::Match(match_) => walk_match(match_, visitor),
+    Expr::Throw(value) | Expr::Let { value, .. } => walk_expr(value, visitor),
+  }
+}
+
+// The statements the runtime calls its debugger at, in the order a walk finds them.
+pub struct Steps<'a>(pub Vec<&'a Stmt>);
+
+impl<'a> Visitor<'a> for Steps<'a> {
+  fn visit_stmt(&mut self, stmt: &'a Stmt) {
+    if stmt.is_step() {
+      self.0.push(stmt);
+    }
+  }
+}
+
+fn walk_match<'a>(match_: &'a Match, visitor: &mut impl Visitor<'a>) {
+  walk_expr(&match_.scrutinee, visitor);
+  for arm in &match_.arms {
+    if let Some(guard) = &arm.guard {
+      walk_expr(guard, visitor);
+    }
+    match &arm.body {
+      ArmBody::Expr(expr) => walk_expr(expr, visitor),
+      ArmBody::Block(stmts) => walk_stmts(stmts, visitor),
+    }
+  }
+}
@@ -0,0 +1,14 @@
+extern crate asalang;
+extern crate lsp_server;
+
+use lsp_server::Connection;
+use std::error::Error;
+
+// Language server for Asa over stdio; point an editor's LSP client at this binary.
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+  let (connection, io_threads) = Connection::stdio();
+  asalang::lsp::serve(&connection)?;
+  drop(connection);
+  io_threads.join()?;
+  Ok(())
+}
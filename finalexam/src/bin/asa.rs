@@ -0,0 +1,283 @@
+extern crate asalang;
+
+use asalang::{codegen_c, codegen_wasm};
+use asalang::debugger::StepDebugger;
+use asalang::interpreter::THROWN;
+use asalang::stdlib::StdIo;
+use asalang::{format_source, program, program_with_recovery, Item, RunError, Runtime, Tracer, Value};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::exit;
+use std::rc::Rc;
+
+const USAGE: &str = "usage:
+  asa run [--profile] [--trace <out.json>] <file> [-- <args>...]
+  asa debug <file>                 (commands on stdin; `help` lists them)
+  asa test <file>                  (runs the file's `test \"name\" { ... }` blocks)
+  asa fmt [--check] [<file>...]    (no files: format stdin to stdout)
+  asa compile --target c <file>    (writes the compiled program to stdout)
+  asa compile --target wasm <file> (writes the .wasm module to stdout)";
+
+fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let code = match args.first().map(String::as_str) {
+    Some("run") => match RunOptions::parse(&args[1..]) {
+      Some(options) => run(options),
+      None => {
+        eprintln!("{}", USAGE);
+        2
+      }
+    },
+    Some("debug") if args.len() == 2 => run(RunOptions { path: &args[1], debug: true, profile: false, trace: None, args: vec![] }),
+    Some("fmt") => fmt(&args[1..]),
+    Some("test") if args.len() == 2 => test(&args[1]),
+    Some("compile") if args.len() == 4 && args[1] == "--target" => compile(&args[2], &args[3]),
+    _ => {
+      eprintln!("{}", USAGE);
+      2
+    }
+  };
+  exit(code);
+}
+
+fn read(path: &str) -> Result<String, i32> {
+  std::fs::read_to_string(path).map_err(|error| {
+    eprintln!("{}: {}", path, error);
+    1
+  })
+}
+
+struct RunOptions<'a> {
+  path: &'a str,
+  debug: bool,
+  // Print a flat profile to stderr when the program finishes.
+  profile: bool,
+  // Write a Chrome trace-event file here.
+  trace: Option<&'a str>,
+  // What the script's `args()` returns.
+  args: Vec<String>,
+}
+
+impl<'a> RunOptions<'a> {
+  fn parse(args: &'a [String]) -> Option<RunOptions<'a>> {
+    let mut options = RunOptions { path: "", debug: false, profile: false, trace: None, args: vec![] };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--" => {
+          options.args = args.by_ref().cloned().collect();
+        },
+        "--profile" => options.profile = true,
+        "--trace" => options.trace = Some(args.next()?),
+        path if options.path.is_empty() && !path.starts_with("--") => options.path = path,
+        _ => return None,
+      }
+    }
+    match options.path.is_empty() {
+      true => None,
+      false => Some(options),
+    }
+  }
+}
+
+fn run(options: RunOptions) -> i32 {
+  let path = options.path;
+  let source = match read(path) {
+    Ok(source) => source,
+    Err(code) => return code,
+  };
+  match program(&source) {
+    Ok(("", tree)) => {
+      let mut runtime = Runtime::new();
+      runtime.set_io(Rc::new(RefCell::new(StdIo { args: options.args.clone() })));
+      if options.debug {
+        let stdin = std::io::stdin().lock();
+        runtime.set_debugger(Box::new(StepDebugger::new(&source, &tree, stdin, std::io::stdout())));
+      }
+      if options.profile || options.trace.is_some() {
+        runtime.set_tracer(Tracer::new());
+      }
+      let result = runtime.run_file(&tree, Path::new(path));
+      if let Some(tracer) = runtime.tracer() {
+        if options.profile {
+          eprint!("{}", tracer.profile_table());
+        }
+        if let Some(trace) = options.trace {
+          if let Err(error) = std::fs::write(trace, tracer.chrome_trace()) {
+            eprintln!("{}: {}", trace, error);
+          }
+        }
+      }
+      match result {
+        Ok(value) => {
+          println!("{:?}", value);
+          0
+        },
+        Err(RunError::Module(error)) => {
+          eprintln!("{}", error);
+          1
+        },
+        Err(RunError::Runtime(report)) => {
+          eprintln!("{}: {}", path, report);
+          1
+        },
+      }
+    },
+    _ => syntax_errors(path, &source),
+  }
+}
+
+fn syntax_errors(path: &str, source: &str) -> i32 {
+  let errors = program_with_recovery(source).1;
+  if errors.is_empty() {
+    eprintln!("{}: could not parse program", path);
+  }
+  for error in errors {
+    eprintln!("{}:{}", path, error);
+  }
+  1
+}
+
+// Runs each test block of the file in a fresh runtime, printing a line for each and then the counts.
+// Exits with 1 if any test fails.
+fn test(path: &str) -> i32 {
+  let source = match read(path) {
+    Ok(source) => source,
+    Err(code) => return code,
+  };
+  let tree = match program(&source) {
+    Ok(("", tree)) => tree,
+    _ => return syntax_errors(path, &source),
+  };
+  let names = tree.items.iter().filter_map(|item| match item {
+    Item::Test { name, .. } => Some(name),
+    _ => None,
+  });
+  let (mut passed, mut failed) = (0, 0);
+  for name in names {
+    let mut runtime = Runtime::new();
+    let result = match runtime.load_imports(&tree, Path::new(path)) {
+      Ok(()) => runtime.load(&tree).and_then(|_| runtime.run_test(&tree, name)).map_err(|error| failure(&runtime, error)),
+      Err(error) => Err(error.to_string()),
+    };
+    match result {
+      Ok(_) => {
+        println!("test {} ... ok", name);
+        passed += 1;
+      },
+      Err(failure) => {
+        println!("test {} ... FAILED", name);
+        for line in failure.lines() {
+          println!("  {}", line);
+        }
+        failed += 1;
+      },
+    }
+  }
+  println!();
+  println!("{} passed, {} failed", passed, failed);
+  (failed > 0) as i32
+}
+
+// Why a test failed: the kind and message of a thrown `Error`, such as a failed `assert_eq`, or the
+// value thrown or runtime error, followed by where it was raised.
+fn failure(runtime: &Runtime, error: &str) -> String {
+  let mut failure = match runtime.thrown() {
+    Some(Value::Struct { name, fields }) if error == THROWN && name == "Error" => {
+      let fields: Vec<String> = fields.iter().map(|(_, value)| value.to_string()).collect();
+      fields.join(": ")
+    },
+    Some(value) if error == THROWN => format!("uncaught error: {}", value),
+    _ => format!("runtime error: {}", error),
+  };
+  for function in runtime.error_stack().iter().rev() {
+    failure += &format!("\n  in {}", function);
+  }
+  failure
+}
+
+fn compile(target: &str, path: &str) -> i32 {
+  let source = match read(path) {
+    Ok(source) => source,
+    Err(code) => return code,
+  };
+  let tree = match program(&source) {
+    Ok(("", tree)) => tree,
+    _ => return syntax_errors(path, &source),
+  };
+  let compiled = match target {
+    "c" => codegen_c::compile(&tree).map(String::into_bytes).map_err(|error| error.to_string()),
+    "wasm" => codegen_wasm::compile(&tree).map_err(|error| error.to_string()),
+    _ => {
+      eprintln!("{}", USAGE);
+      return 2;
+    },
+  };
+  match compiled {
+    Ok(compiled) => match std::io::stdout().write_all(&compiled) {
+      Ok(()) => 0,
+      Err(error) => {
+        eprintln!("{}", error);
+        1
+      },
+    },
+    Err(error) => {
+      eprintln!("{}: {}", path, error);
+      1
+    },
+  }
+}
+
+// Rewrites each file in place, or with --check only lists the files that aren't formatted. Exits with
+// 1 if any file has errors or, under --check, needs formatting.
+fn fmt(args: &[String]) -> i32 {
+  let check = args.iter().any(|arg| arg == "--check");
+  let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+  if paths.is_empty() {
+    let mut source = String::new();
+    if std::io::stdin().read_to_string(&mut source).is_err() {
+      eprintln!("<stdin>: could not read input");
+      return 1;
+    }
+    return match format_source(&source) {
+      Ok(formatted) if check => (formatted != source) as i32,
+      Ok(formatted) => {
+        print!("{}", formatted);
+        0
+      },
+      Err(error) => {
+        eprintln!("<stdin>: {}", error);
+        1
+      },
+    };
+  }
+  let mut code = 0;
+  for path in paths {
+    let source = match read(path) {
+      Ok(source) => source,
+      Err(error) => {
+        code = error;
+        continue;
+      }
+    };
+    match format_source(&source) {
+      Ok(formatted) if formatted == source => (),
+      Ok(_) if check => {
+        println!("{}", path);
+        code = 1;
+      },
+      Ok(formatted) => {
+        if let Err(error) = std::fs::write(path, formatted) {
+          eprintln!("{}: {}", path, error);
+          code = 1;
+        }
+      },
+      Err(error) => {
+        eprintln!("{}: {}", path, error);
+        code = 1;
+      },
+    }
+  }
+  code
+}
@@ -0,0 +1,341 @@
+// The C backend: compiles a program ahead of time into one standalone C file.
+//
+// Each function is lowered the way the runtime runs it, and every node becomes C that computes the
+// same value or fails with the same error. Values stay dynamically typed: `asa_runtime.h`, which
+// starts every file, tags each one as a number, bool, string or error and checks the tags the way the
+// interpreter does. Numbers are `int64_t` held to the 32-bit range. Asa has no loops, so recursion
+// is the only repetition and compiles to C recursion, with the runtime's call depth limit.
+//
+// The compiled program prints what `main` returns, or exits with 1 and prints the error to stderr,
+// like `asa run`. Functions, numbers, bools, strings, math, comparisons, `let`, `return`, if/else and
+// `print`/`println` compile; structs, enums, `match`, `throw`/`try`, modules, a top-level `if` and
+// the other builtins don't. `test` blocks are left out.
+
+use crate::ast::{BinaryOp, FnDecl, Item, Program, Stmt};
+use crate::interpreter::{Runtime, MAX_CALL_DEPTH};
+use crate::lower::{self, Function, Node, NodeId, Symbols};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub const RUNTIME_HEADER: &str = include_str!("asa_runtime.h");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+  // A feature the C backend has no translation for.
+  Unsupported(&'static str),
+}
+
+impl fmt::Display for CodegenError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CodegenError::Unsupported(feature) => write!(f, "the C backend does not support {}", feature),
+    }
+  }
+}
+
+pub fn compile(program: &Program) -> Result<String, CodegenError> {
+  // As when the runtime loads a program, a later function of the same name replaces an earlier one, and
+  // a top-level statement or expression becomes the body of `main`.
+  let mut order: Vec<String> = vec![];
+  let mut decls: HashMap<String, Rc<FnDecl>> = HashMap::new();
+  for item in &program.items {
+    let decl = match item {
+      Item::Fn(function) => function.clone(),
+      Item::Expr(expr) => main(vec![Stmt::Return(expr.clone())]),
+      Item::Stmt(Stmt::If(_)) => return Err(CodegenError::Unsupported("a top-level if")),
+      Item::Stmt(stmt) => main(vec![stmt.clone()]),
+      Item::Test { .. } => continue,
+      Item::Import { .. } => return Err(CodegenError::Unsupported("imports")),
+      Item::Struct { .. } | Item::Impl { .. } => return Err(CodegenError::Unsupported("structs")),
+      Item::Enum { .. } => return Err(CodegenError::Unsupported("enums")),
+    };
+    if !decls.contains_key(&decl.name) {
+      order.push(decl.name.clone());
+    }
+    decls.insert(decl.name.clone(), Rc::new(decl));
+  }
+  let arities: HashMap<String, usize> = decls.iter().map(|(name, decl)| (name.clone(), decl.params.len())).collect();
+  let mut symbols = Symbols::default();
+  let mut out = format!("{}\n#define ASA_MAX_CALL_DEPTH {}\n\n", RUNTIME_HEADER, MAX_CALL_DEPTH);
+  for name in &order {
+    out += &format!("asa_value {};\n", signature(name, decls[name].params.len()));
+  }
+  let builtins = Runtime::new();
+  for name in &order {
+    let function = lower::function(name, decls[name].clone(), &mut symbols);
+    let mut generator = Generator { function: &function, symbols: &symbols, arities: &arities, builtins: &builtins, body: String::new(), indent: 1, temps: 0, labels: 0 };
+    out += "\n";
+    out += &generator.function(name)?;
+  }
+  let result = match arities.get("main") {
+    Some(0) => "asa_fn_main()",
+    Some(_) => "asa_error(\"Wrong number of arguments\")",
+    None => "asa_error(\"Undefined function\")",
+  };
+  out += &format!("\nint main(void) {{\n  return asa_finish({});\n}}\n", result);
+  Ok(out)
+}
+
+fn main(body: Vec<Stmt>) -> FnDecl {
+  FnDecl { doc: String::new(), name: "main".to_string(), params: vec![], body }
+}
+
+fn signature(name: &str, params: usize) -> String {
+  let params: Vec<String> = (0..params).map(|ix| format!("asa_value a{}", ix)).collect();
+  match params.is_empty() {
+    true => format!("asa_fn_{}(void)", name),
+    false => format!("asa_fn_{}({})", name, params.join(", ")),
+  }
+}
+
+// A C string literal.
+fn c_string(text: &str) -> String {
+  let mut literal = String::from("\"");
+  for c in text.chars() {
+    match c {
+      '"' | '\\' => literal += &format!("\\{}", c),
+      ' '..='~' => literal.push(c),
+      _ => literal += &format!("\\x{:02x}", c as u32),
+    }
+  }
+  literal + "\""
+}
+
+fn c_op(op: BinaryOp) -> &'static str {
+  match op {
+    BinaryOp::Add => "ASA_ADD",
+    BinaryOp::Sub => "ASA_SUB",
+    BinaryOp::Mul => "ASA_MUL",
+    BinaryOp::Div => "ASA_DIV",
+    BinaryOp::Pow => "ASA_POW",
+    BinaryOp::Eq => "ASA_EQ",
+    BinaryOp::Ne => "ASA_NE",
+    BinaryOp::Lt => "ASA_LT",
+    BinaryOp::Gt => "ASA_GT",
+    BinaryOp::Le => "ASA_LE",
+    BinaryOp::Ge => "ASA_GE",
+  }
+}
+
+// Writes the C of one function. Variables live in `s`, indexed by slot, and every intermediate value
+// in a temporary of `t`. An error in a statement stores it in `result` and jumps to `done`, ending the
+// call; inside an expression it is a value like any other, so that `==` can compare it.
+struct Generator<'a> {
+  function: &'a Function,
+  symbols: &'a Symbols,
+  arities: &'a HashMap<String, usize>,
+  // Only consulted to tell the runtime's builtins from undefined functions.
+  builtins: &'a Runtime,
+  body: String,
+  indent: usize,
+  temps: usize,
+  labels: usize,
+}
+
+impl<'a> Generator<'a> {
+  fn function(&mut self, name: &str) -> Result<String, CodegenError> {
+    for stmt in &self.function.body {
+      let value = self.statement(*stmt)?;
+      self.line(&format!("result = {};", value));
+    }
+    let slots = self.function.slots.len();
+    let mut out = format!("asa_value {} {{\n", signature(name, self.function.params.len()));
+    if slots > 0 {
+      out += &format!("  asa_value s[{}];\n  int i;\n", slots);
+    }
+    out += &format!("  asa_value t[{}];\n", self.temps.max(1));
+    out += "  asa_value result = asa_error(\"Undefined function\");\n";
+    out += "  if (asa_depth >= ASA_MAX_CALL_DEPTH) {\n    return asa_error(\"Call stack too deep\");\n  }\n";
+    out += "  asa_depth++;\n";
+    if slots > 0 {
+      out += &format!("  for (i = 0; i < {}; i++) {{\n    s[i] = asa_unset();\n  }}\n", slots);
+    }
+    for (ix, slot) in self.function.params.iter().enumerate() {
+      out += &format!("  s[{}] = a{};\n", slot, ix);
+    }
+    out += &self.body;
+    out += "done:\n  asa_depth--;\n  return result;\n}\n";
+    Ok(out)
+  }
+
+  fn line(&mut self, text: &str) {
+    self.body += &"  ".repeat(self.indent);
+    self.body += text;
+    self.body += "\n";
+  }
+
+  fn temp(&mut self) -> String {
+    self.temps += 1;
+    format!("t[{}]", self.temps - 1)
+  }
+
+  fn label(&mut self, what: &str) -> String {
+    self.labels += 1;
+    format!("{}_{}", what, self.labels)
+  }
+
+  // Stop the call if `value` is an error.
+  fn check(&mut self, value: &str) {
+    self.line(&format!("if (asa_is_error({})) {{", value));
+    self.line(&format!("  result = {};", value));
+    self.line("  goto done;");
+    self.line("}");
+  }
+
+  // Run a statement, returning the temporary that holds its value.
+  fn statement(&mut self, id: NodeId) -> Result<String, CodegenError> {
+    let value = self.temp();
+    match &self.function.code[id] {
+      Node::Let { var, value: expr } => {
+        let expr = self.expression(*expr, false)?;
+        self.line(&format!("{} = {};", value, expr));
+        self.check(&value);
+        if let Some(slot) = var.slot {
+          self.line(&format!("s[{}] = {};", slot, value));
+        }
+      },
+      Node::Return(expr) | Node::Expr(expr) => {
+        let expr = self.expression(*expr, false)?;
+        self.line(&format!("{} = {};", value, expr));
+        self.check(&value);
+      },
+      // The first arm whose condition holds runs. An arm that ends in `false` lets the arms after it be
+      // tried too, and a first condition that isn't a bool is the value of the whole statement.
+      Node::If { arms } => {
+        let end = self.label("if_end");
+        self.line(&format!("{} = asa_bool(1);", value));
+        for (ix, (condition, body)) in arms.iter().enumerate() {
+          let mut opened = false;
+          if let Some(condition) = condition {
+            let test = self.temp();
+            let expr = self.expression(*condition, true)?;
+            self.line(&format!("{} = {};", test, expr));
+            self.line(&format!("if ({}.tag != ASA_BOOL) {{", test));
+            self.indent += 1;
+            match ix {
+              0 => {
+                self.line(&format!("{} = {};", value, test));
+                self.check(&value);
+                self.line(&format!("goto {};", end));
+              },
+              _ => {
+                self.line("result = asa_error(\"Not a boolean value\");");
+                self.line("goto done;");
+              },
+            }
+            self.indent -= 1;
+            self.line("}");
+            self.line(&format!("if ({}.number) {{", test));
+            self.indent += 1;
+            opened = true;
+          }
+          let arm = self.arm(body)?;
+          self.line(&format!("if (!asa_is_bool({}, 0)) {{", arm));
+          self.line(&format!("  {} = {};", value, arm));
+          self.line(&format!("  goto {};", end));
+          self.line("}");
+          if opened {
+            self.indent -= 1;
+            self.line("}");
+          }
+        }
+        self.line(&format!("{}:;", end));
+      },
+      Node::Assign { .. } => return Err(CodegenError::Unsupported("structs")),
+      Node::Match { .. } => return Err(CodegenError::Unsupported("match")),
+      Node::Try { .. } | Node::Throw(_) => return Err(CodegenError::Unsupported("throw and try")),
+      _ => return Err(CodegenError::Unsupported("this statement")),
+    }
+    Ok(value)
+  }
+
+  // Run the statements of an if arm up to the first `return`, whose value is the arm's; otherwise the
+  // arm's value is `true`.
+  fn arm(&mut self, body: &[NodeId]) -> Result<String, CodegenError> {
+    let value = self.temp();
+    self.line(&format!("{} = asa_bool(1);", value));
+    for stmt in body {
+      let result = self.statement(*stmt)?;
+      if let Node::Return(_) = self.function.code[*stmt] {
+        self.line(&format!("{} = {};", value, result));
+        break;
+      }
+    }
+    Ok(value)
+  }
+
+  // A C expression for the value of a node. In the `condition` of an if, `==` and `!=` may also compare
+  // to a bool or string.
+  fn expression(&mut self, id: NodeId, condition: bool) -> Result<String, CodegenError> {
+    let code = &self.function.code;
+    Ok(match &code[id] {
+      Node::Number(value) => format!("asa_number({})", value),
+      Node::Bool(value) => format!("asa_bool({})", *value as i32),
+      Node::String(value) => format!("asa_string({})", c_string(value)),
+      Node::Var(var) => match var.slot {
+        Some(slot) => format!("asa_read(s[{}])", slot),
+        // A compiled program has no globals.
+        None => "asa_error(\"Undefined variable\")".to_string(),
+      },
+      // As in the runtime, what a comparison compares is checked before either side is evaluated, and
+      // both sides are evaluated even if one fails.
+      Node::Binary { op, lhs, rhs } if op.is_comparison() => {
+        if !matches!(code[*lhs], Node::Number(_) | Node::Var(_) | Node::Field { .. } | Node::Binary { .. }) {
+          return Ok("asa_error(\"Invalid expression\")".to_string());
+        }
+        let comparable = match &code[*rhs] {
+          Node::Number(_) | Node::Var(_) | Node::Field { .. } | Node::Variant { .. } => true,
+          Node::Binary { op, .. } => !op.is_comparison(),
+          Node::Bool(_) | Node::String(_) => condition && matches!(op, BinaryOp::Eq | BinaryOp::Ne),
+          _ => false,
+        };
+        if !comparable {
+          return Ok("asa_error(\"Invalid expression - can only compare numbers to numbers\")".to_string());
+        }
+        let (op, lhs, rhs) = (*op, *lhs, *rhs);
+        let (lhs_temp, rhs_temp) = (self.temp(), self.temp());
+        let lhs = self.expression(lhs, false)?;
+        let rhs = self.expression(rhs, false)?;
+        format!("({} = {}, {} = {}, asa_compare({}, {}, {}))", lhs_temp, lhs, rhs_temp, rhs, c_op(op), lhs_temp, rhs_temp)
+      },
+      Node::Binary { op, lhs, rhs } => {
+        let (op, lhs, rhs) = (*op, *lhs, *rhs);
+        let (lhs_temp, rhs_temp) = (self.temp(), self.temp());
+        let lhs = self.expression(lhs, false)?;
+        let rhs = self.expression(rhs, false)?;
+        format!("({l} = {}, asa_is_error({l}) ? {l} : ({r} = {}, asa_is_error({r}) ? {r} : asa_math({}, {l}, {r})))", lhs, rhs, c_op(op), l = lhs_temp, r = rhs_temp)
+      },
+      Node::Call { callee, args } => {
+        if callee.method.is_some() {
+          return Err(CodegenError::Unsupported("method and module calls"));
+        }
+        let name = self.symbols.name(callee.name);
+        let args = args.clone();
+        let temps: Vec<String> = args.iter().map(|_| self.temp()).collect();
+        let mut call = match self.arities.get(name) {
+          Some(arity) if *arity != args.len() => "asa_error(\"Wrong number of arguments\")".to_string(),
+          Some(_) => format!("asa_fn_{}({})", name, temps.join(", ")),
+          None if name == "print" || name == "println" => match temps.is_empty() {
+            true => format!("asa_print(0, NULL, {})", (name == "println") as i32),
+            false => format!("asa_print({}, (asa_value[]) {{ {} }}, {})", temps.len(), temps.join(", "), (name == "println") as i32),
+          },
+          None if self.builtins.function(name).is_some() => return Err(CodegenError::Unsupported("builtins besides print and println")),
+          // An undefined function fails before its arguments are evaluated.
+          None => return Ok("asa_error(\"Undefined function\")".to_string()),
+        };
+        // The arguments are evaluated in order, and the first to fail is the call's value.
+        for (arg, temp) in args.iter().zip(&temps).rev() {
+          let arg = self.expression(*arg, false)?;
+          call = format!("({t} = {}, asa_is_error({t}) ? {t} : {})", arg, call, t = temp);
+        }
+        call
+      },
+      Node::Struct { .. } | Node::Field { .. } => return Err(CodegenError::Unsupported("structs")),
+      Node::Variant { .. } | Node::IfLet { .. } => return Err(CodegenError::Unsupported("enums")),
+      Node::Match { .. } => return Err(CodegenError::Unsupported("match")),
+      Node::Throw(_) => return Err(CodegenError::Unsupported("throw and try")),
+      _ => return Err(CodegenError::Unsupported("this expression")),
+    })
+  }
+}
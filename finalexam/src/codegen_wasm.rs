@@ -0,0 +1,723 @@
+// The WebAssembly backend: compiles the numeric and bool part of Asa to a binary `.wasm` module.
+//
+// As in the C backend, each function is lowered the way the runtime runs it, and every node becomes
+// code with the same result. Values are untyped `i64`s: a number is itself, sign-extended from 32
+// bits, and anything else sits above that range, tagged in the upper half (a bool, an unset variable,
+// or an error with its message's index in `ERRORS`). Math and comparisons go through helper functions
+// at the start of the module that check the tags the way the interpreter does; `^` is a loop.
+//
+// Every script function takes and returns `i64`s, and is exported under its name and placed in the
+// exported table `TABLE`, in the order the program defines them; `decode` turns a result back into a
+// `Value`. Functions, numbers, bools, math, comparisons, `let`, `return` and if/else compile. Strings,
+// structs, enums, `match`, `throw`/`try`, modules, a top-level `if` and the builtins don't. `test`
+// blocks are left out.
+
+use crate::ast::{BinaryOp, FnDecl, Item, Program, Stmt};
+use crate::interpreter::{Runtime, Value, MAX_CALL_DEPTH};
+use crate::lower::{self, Function, Node, NodeId, Symbols};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+  // A feature the wasm backend has no translation for.
+  Unsupported(&'static str),
+}
+
+impl fmt::Display for CodegenError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CodegenError::Unsupported(feature) => write!(f, "the wasm backend does not support {}", feature),
+    }
+  }
+}
+
+// The name the function table is exported under. No Asa name has a `$` in it, so no script function's
+// export can clash with it.
+pub const TABLE: &str = "$table";
+
+const FALSE: i64 = 1 << 32;
+const TRUE: i64 = FALSE | 1;
+const UNSET: i64 = 2 << 32;
+const ERROR: i64 = 3 << 32;
+
+// The runtime errors a compiled function can fail with.
+pub const ERRORS: [&str; 16] = [
+  "Division by zero",
+  "Number out of range",
+  "Cannot do math on String or Bool",
+  "Undefined operator",
+  "Undefined variable",
+  "Undefined function",
+  "Wrong number of arguments",
+  "Call stack too deep",
+  "Not a boolean value",
+  "Invalid expression",
+  "Invalid expression - can only compare numbers to numbers",
+  "Unsuccesful interpreting > comparison",
+  "Unsuccesful interpreting < comparison",
+  "Unsuccesful interpreting >= comparison",
+  "Unsuccesful interpreting <= comparison",
+  "Invalid operator",
+];
+
+fn error(message: &str) -> i64 {
+  ERROR | ERRORS.iter().position(|error| *error == message).expect("an error listed in ERRORS") as i64
+}
+
+// What an `i64` a compiled function returned stands for.
+pub fn decode(value: i64) -> Result<Value, &'static str> {
+  if let Ok(number) = i32::try_from(value) {
+    return Ok(Value::Number(number));
+  }
+  match (value >> 32, value & 0xffff_ffff) {
+    (1, b) => Ok(Value::Bool(b == 1)),
+    (3, ix) => Err(ERRORS.get(ix as usize).copied().unwrap_or("Undefined operator")),
+    _ => Err("Undefined variable"),
+  }
+}
+
+pub fn compile(program: &Program) -> Result<Vec<u8>, CodegenError> {
+  // As when the runtime loads a program, a later function of the same name replaces an earlier one, and
+  // a top-level statement or expression becomes the body of `main`.
+  let mut order: Vec<String> = vec![];
+  let mut decls: HashMap<String, Rc<FnDecl>> = HashMap::new();
+  for item in &program.items {
+    let decl = match item {
+      Item::Fn(function) => function.clone(),
+      Item::Expr(expr) => main(vec![Stmt::Return(expr.clone())]),
+      Item::Stmt(Stmt::If(_)) => return Err(CodegenError::Unsupported("a top-level if")),
+      Item::Stmt(stmt) => main(vec![stmt.clone()]),
+      Item::Test { .. } => continue,
+      Item::Import { .. } => return Err(CodegenError::Unsupported("imports")),
+      Item::Struct { .. } | Item::Impl { .. } => return Err(CodegenError::Unsupported("structs")),
+      Item::Enum { .. } => return Err(CodegenError::Unsupported("enums")),
+    };
+    if !decls.contains_key(&decl.name) {
+      order.push(decl.name.clone());
+    }
+    decls.insert(decl.name.clone(), Rc::new(decl));
+  }
+  let indices: HashMap<String, (u32, usize)> = order.iter().enumerate()
+    .map(|(ix, name)| (name.clone(), (HELPERS + ix as u32, decls[name].params.len())))
+    .collect();
+  let mut module = Module::default();
+  module.helpers();
+  let mut symbols = Symbols::default();
+  let builtins = Runtime::new();
+  for name in &order {
+    let function = lower::function(name, decls[name].clone(), &mut symbols);
+    let mut generator = Generator { function: &function, symbols: &symbols, indices: &indices, builtins: &builtins, code: Code::default(), locals: 0, depth: 0, done: 0 };
+    let (locals, code) = generator.function()?;
+    let ty = module.ty(&vec![I64; function.params.len()], &[I64]);
+    module.functions.push((ty, vec![(locals, I64)], code));
+  }
+  Ok(module.encode(&order))
+}
+
+fn main(body: Vec<Stmt>) -> FnDecl {
+  FnDecl { doc: String::new(), name: "main".to_string(), params: vec![], body }
+}
+
+// ---- Encoding ----
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const EMPTY: u8 = 0x40;
+
+fn uleb(out: &mut Vec<u8>, mut n: u64) {
+  loop {
+    let byte = (n & 0x7f) as u8;
+    n >>= 7;
+    match n {
+      0 => return out.push(byte),
+      _ => out.push(byte | 0x80),
+    }
+  }
+}
+
+fn sleb(out: &mut Vec<u8>, mut n: i64) {
+  loop {
+    let byte = (n & 0x7f) as u8;
+    n >>= 7;
+    if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+      return out.push(byte);
+    }
+    out.push(byte | 0x80);
+  }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+  uleb(out, name.len() as u64);
+  out.extend_from_slice(name.as_bytes());
+}
+
+// A vector of `items`, each already encoded.
+fn vector(items: &[Vec<u8>]) -> Vec<u8> {
+  let mut out = vec![];
+  uleb(&mut out, items.len() as u64);
+  for item in items {
+    out.extend_from_slice(item);
+  }
+  out
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+  out.push(id);
+  uleb(out, contents.len() as u64);
+  out.extend_from_slice(contents);
+}
+
+// The instructions of a function body.
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+  fn op(&mut self, op: u8) -> &mut Code {
+    self.0.push(op);
+    self
+  }
+
+  fn op_index(&mut self, op: u8, index: u32) -> &mut Code {
+    self.0.push(op);
+    uleb(&mut self.0, index as u64);
+    self
+  }
+
+  fn i32(&mut self, n: i32) -> &mut Code {
+    self.0.push(0x41);
+    sleb(&mut self.0, n as i64);
+    self
+  }
+
+  fn i64(&mut self, n: i64) -> &mut Code {
+    self.0.push(0x42);
+    sleb(&mut self.0, n);
+    self
+  }
+
+  fn get(&mut self, local: u32) -> &mut Code {
+    self.op_index(0x20, local)
+  }
+
+  fn set(&mut self, local: u32) -> &mut Code {
+    self.op_index(0x21, local)
+  }
+
+  fn tee(&mut self, local: u32) -> &mut Code {
+    self.op_index(0x22, local)
+  }
+
+  fn call(&mut self, function: u32) -> &mut Code {
+    self.op_index(0x10, function)
+  }
+
+  fn block(&mut self, op: u8, ty: u8) -> &mut Code {
+    self.0.extend_from_slice(&[op, ty]);
+    self
+  }
+
+  // An i32 bool on the stack to the tagged `i64`.
+  fn boolean(&mut self) -> &mut Code {
+    self.op(I64_EXTEND_U).i64(FALSE).op(I64_OR)
+  }
+}
+
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const SELECT: u8 = 0x1b;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_GE_S: u8 = 0x4e;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const I64_EQZ: u8 = 0x50;
+const I64_EQ: u8 = 0x51;
+const I64_NE: u8 = 0x52;
+const I64_LT_S: u8 = 0x53;
+const I64_GT_S: u8 = 0x55;
+const I64_LE_S: u8 = 0x57;
+const I64_GE_S: u8 = 0x59;
+const I64_ADD: u8 = 0x7c;
+const I64_SUB: u8 = 0x7d;
+const I64_MUL: u8 = 0x7e;
+const I64_DIV_S: u8 = 0x7f;
+const I64_AND: u8 = 0x83;
+const I64_OR: u8 = 0x84;
+const I64_SHR_S: u8 = 0x87;
+const I32_WRAP: u8 = 0xa7;
+const I64_EXTEND_S: u8 = 0xac;
+const I64_EXTEND_U: u8 = 0xad;
+
+// The helper functions, which come before the script's.
+const IS_ERROR: u32 = 0;
+const IS_NUMBER: u32 = 1;
+const CHECKED: u32 = 2;
+const READ: u32 = 3;
+const POW: u32 = 4;
+const MATH: u32 = 5;
+const COMPARE: u32 = 6;
+const HELPERS: u32 = 7;
+
+// The global holding the number of active calls.
+const DEPTH: u32 = 0;
+
+fn op_code(op: BinaryOp) -> i32 {
+  op as i32
+}
+
+// A function's type, its locals past the parameters as counts of each type, and its code.
+type Body = (u32, Vec<(u32, u8)>, Code);
+
+#[derive(Default)]
+struct Module {
+  types: Vec<Vec<u8>>,
+  functions: Vec<Body>,
+}
+
+impl Module {
+  // The index of the function type from `params` to `results`.
+  fn ty(&mut self, params: &[u8], results: &[u8]) -> u32 {
+    let mut ty = vec![0x60];
+    uleb(&mut ty, params.len() as u64);
+    ty.extend_from_slice(params);
+    uleb(&mut ty, results.len() as u64);
+    ty.extend_from_slice(results);
+    match self.types.iter().position(|known| *known == ty) {
+      Some(ix) => ix as u32,
+      None => {
+        self.types.push(ty);
+        self.types.len() as u32 - 1
+      },
+    }
+  }
+
+  fn helper(&mut self, ty: u32, locals: Vec<(u32, u8)>, code: Code) {
+    self.functions.push((ty, locals, code));
+  }
+
+  fn helpers(&mut self) {
+    let test = self.ty(&[I64], &[I32]);
+    let unary = self.ty(&[I64], &[I64]);
+    let binary = self.ty(&[I64, I64], &[I64]);
+    let operator = self.ty(&[I32, I64, I64], &[I64]);
+
+    // is_error(value): whether the value is an error.
+    let mut code = Code::default();
+    code.get(0).i64(32).op(I64_SHR_S).i64(3).op(I64_EQ);
+    self.helper(test, vec![], code);
+
+    // is_number(value): whether the value is a number, i.e. it fits in 32 bits.
+    let mut code = Code::default();
+    code.get(0).op(I32_WRAP).op(I64_EXTEND_S).get(0).op(I64_EQ);
+    self.helper(test, vec![], code);
+
+    // checked(number): the number, or an error if it's outside the 32-bit range.
+    let mut code = Code::default();
+    code.get(0).call(IS_NUMBER).block(IF, I64).get(0).op(ELSE).i64(error("Number out of range")).op(END);
+    self.helper(unary, vec![], code);
+
+    // read(slot): the value of a variable, or an error if it's unset.
+    let mut code = Code::default();
+    code.get(0).i64(UNSET).op(I64_EQ).block(IF, I64).i64(error("Undefined variable")).op(ELSE).get(0).op(END);
+    self.helper(unary, vec![], code);
+
+    // pow(base, power): a negative power is 1; any base but 0, 1 and -1 leaves the 32-bit range within 32
+    // steps of the loop.
+    let mut code = Code::default();
+    code.get(1).i64(0).op(I64_LT_S).block(IF, EMPTY).i64(1).op(RETURN).op(END);
+    code.get(0).i64(0).op(I64_EQ).get(0).i64(1).op(I64_EQ).op(I32_OR).block(IF, EMPTY);
+    code.get(1).op(I64_EQZ).block(IF, I64).i64(1).op(ELSE).get(0).op(END).op(RETURN).op(END);
+    code.get(0).i64(-1).op(I64_EQ).block(IF, EMPTY);
+    code.i64(1).i64(-1).get(1).i64(1).op(I64_AND).op(I64_EQZ).op(SELECT).op(RETURN).op(END);
+    code.i64(1).set(2);
+    code.block(BLOCK, EMPTY).block(LOOP, EMPTY);
+    code.get(1).op(I64_EQZ).op_index(BR_IF, 1);
+    code.get(2).get(0).op(I64_MUL).tee(2).call(IS_NUMBER).op(I32_EQZ);
+    code.block(IF, EMPTY).i64(error("Number out of range")).op(RETURN).op(END);
+    code.get(1).i64(1).op(I64_SUB).set(1);
+    code.op_index(BR, 0).op(END).op(END);
+    code.get(2);
+    self.helper(binary, vec![(1, I64)], code);
+
+    // math(operator, lhs, rhs)
+    let mut code = Code::default();
+    code.get(1).call(IS_NUMBER).get(2).call(IS_NUMBER).op(I32_AND).op(I32_EQZ);
+    code.block(IF, EMPTY).i64(error("Cannot do math on String or Bool")).op(RETURN).op(END);
+    for (op, instruction) in [(BinaryOp::Add, I64_ADD), (BinaryOp::Sub, I64_SUB), (BinaryOp::Mul, I64_MUL)] {
+      code.get(0).i32(op_code(op)).op(I32_EQ).block(IF, EMPTY);
+      code.get(1).get(2).op(instruction).call(CHECKED).op(RETURN).op(END);
+    }
+    code.get(0).i32(op_code(BinaryOp::Div)).op(I32_EQ).block(IF, EMPTY);
+    code.get(2).op(I64_EQZ).block(IF, EMPTY).i64(error("Division by zero")).op(RETURN).op(END);
+    code.get(1).get(2).op(I64_DIV_S).call(CHECKED).op(RETURN).op(END);
+    code.get(0).i32(op_code(BinaryOp::Pow)).op(I32_EQ).block(IF, EMPTY);
+    code.get(1).get(2).call(POW).op(RETURN).op(END);
+    code.i64(error("Undefined operator"));
+    self.helper(operator, vec![], code);
+
+    // compare(operator, lhs, rhs): `==` and `!=` compare any two values, errors included; the others
+    // only numbers.
+    let mut code = Code::default();
+    for (op, instruction) in [(BinaryOp::Eq, I64_EQ), (BinaryOp::Ne, I64_NE)] {
+      code.get(0).i32(op_code(op)).op(I32_EQ).block(IF, EMPTY);
+      code.get(1).get(2).op(instruction).boolean().op(RETURN).op(END);
+    }
+    let ordered = [(BinaryOp::Gt, I64_GT_S, ">"), (BinaryOp::Lt, I64_LT_S, "<"), (BinaryOp::Ge, I64_GE_S, ">="), (BinaryOp::Le, I64_LE_S, "<=")];
+    code.get(1).call(IS_NUMBER).get(2).call(IS_NUMBER).op(I32_AND).op(I32_EQZ).block(IF, EMPTY);
+    for (op, _, symbol) in ordered {
+      let message = format!("Unsuccesful interpreting {} comparison", symbol);
+      code.get(0).i32(op_code(op)).op(I32_EQ).block(IF, EMPTY).i64(error(&message)).op(RETURN).op(END);
+    }
+    code.i64(error("Invalid operator")).op(RETURN).op(END);
+    for (op, instruction, _) in ordered {
+      code.get(0).i32(op_code(op)).op(I32_EQ).block(IF, EMPTY);
+      code.get(1).get(2).op(instruction).boolean().op(RETURN).op(END);
+    }
+    code.i64(error("Invalid operator"));
+    self.helper(operator, vec![], code);
+  }
+
+  fn encode(&self, exports: &[String]) -> Vec<u8> {
+    let mut out = b"\0asm\x01\0\0\0".to_vec();
+    section(&mut out, 1, &vector(&self.types));
+    let functions: Vec<Vec<u8>> = self.functions.iter().map(|(ty, _, _)| {
+      let mut out = vec![];
+      uleb(&mut out, *ty as u64);
+      out
+    }).collect();
+    section(&mut out, 3, &vector(&functions));
+    // table: funcref, with at least as many entries as the script has functions
+    let mut table = vec![0x70, 0x00];
+    uleb(&mut table, exports.len() as u64);
+    section(&mut out, 4, &vector(&[table]));
+    // global: the call depth, a mutable i32 starting at 0
+    section(&mut out, 6, &vector(&[vec![I32, 0x01, 0x41, 0x00, END]]));
+    let mut entries: Vec<Vec<u8>> = exports.iter().enumerate().map(|(ix, export)| {
+      let mut entry = vec![];
+      name(&mut entry, export);
+      entry.push(0x00);
+      uleb(&mut entry, HELPERS as u64 + ix as u64);
+      entry
+    }).collect();
+    let mut table = vec![];
+    name(&mut table, TABLE);
+    table.extend_from_slice(&[0x01, 0x00]);
+    entries.push(table);
+    section(&mut out, 7, &vector(&entries));
+    // element: the script's functions, in order, from the start of the table
+    let indices: Vec<Vec<u8>> = (0..exports.len()).map(|ix| {
+      let mut out = vec![];
+      uleb(&mut out, HELPERS as u64 + ix as u64);
+      out
+    }).collect();
+    let mut element = vec![0x00, 0x41, 0x00, END];
+    element.extend_from_slice(&vector(&indices));
+    section(&mut out, 9, &vector(&[element]));
+    let bodies: Vec<Vec<u8>> = self.functions.iter().map(|(_, locals, code)| {
+      let locals: Vec<Vec<u8>> = locals.iter().map(|(count, ty)| {
+        let mut out = vec![];
+        uleb(&mut out, *count as u64);
+        out.push(*ty);
+        out
+      }).collect();
+      let mut body = vector(&locals);
+      body.extend_from_slice(&code.0);
+      body.push(END);
+      let mut out = vec![];
+      uleb(&mut out, body.len() as u64);
+      out.extend_from_slice(&body);
+      out
+    }).collect();
+    section(&mut out, 10, &vector(&bodies));
+    out
+  }
+}
+
+// ---- Functions ----
+
+// Writes the code of one function. Its parameters are the first locals, then come a local for each
+// variable slot, `result`, and a temporary for every intermediate value. An error in a statement
+// stores it in `result` and breaks out of the block around the body, ending the call; inside an
+// expression it is a value like any other, so that `==` can compare it.
+struct Generator<'a> {
+  function: &'a Function,
+  symbols: &'a Symbols,
+  // The function index and arity of each script function.
+  indices: &'a HashMap<String, (u32, usize)>,
+  // Only consulted to tell the runtime's builtins from undefined functions.
+  builtins: &'a Runtime,
+  code: Code,
+  // Locals past the parameters.
+  locals: u32,
+  // Blocks open at the current instruction.
+  depth: u32,
+  // The depth the block around the body was opened at.
+  done: u32,
+}
+
+impl<'a> Generator<'a> {
+  // The number of locals past the parameters, and the code.
+  fn function(&mut self) -> Result<(u32, Code), CodegenError> {
+    let params = self.function.params.len() as u32;
+    let slots = self.function.slots.len() as u32;
+    self.locals = slots;
+    let result = self.local();
+    self.code.op_index(GLOBAL_GET, DEPTH).i32(MAX_CALL_DEPTH as i32).op(I32_GE_S);
+    self.code.block(IF, EMPTY).i64(error("Call stack too deep")).op(RETURN).op(END);
+    self.code.op_index(GLOBAL_GET, DEPTH).i32(1).op(I32_ADD).op_index(GLOBAL_SET, DEPTH);
+    for slot in 0..slots {
+      self.code.i64(UNSET).set(params + slot);
+    }
+    for (ix, slot) in self.function.params.iter().enumerate() {
+      self.code.get(ix as u32).set(params + *slot as u32);
+    }
+    self.code.i64(error("Undefined function")).set(result);
+    self.done = self.open(BLOCK);
+    for stmt in &self.function.body {
+      let value = self.statement(*stmt)?;
+      self.code.get(value).set(result);
+    }
+    self.close();
+    self.code.op_index(GLOBAL_GET, DEPTH).i32(1).op(I32_SUB).op_index(GLOBAL_SET, DEPTH);
+    self.code.get(result);
+    Ok((self.locals, std::mem::take(&mut self.code)))
+  }
+
+  fn local(&mut self) -> u32 {
+    self.locals += 1;
+    self.function.params.len() as u32 + self.locals - 1
+  }
+
+  fn result(&self) -> u32 {
+    self.function.params.len() as u32 + self.function.slots.len() as u32
+  }
+
+  fn slot(&self, slot: usize) -> u32 {
+    self.function.params.len() as u32 + slot as u32
+  }
+
+  // Open a block without a value, returning the depth to break out of it with.
+  fn open(&mut self, op: u8) -> u32 {
+    self.code.block(op, EMPTY);
+    self.depth += 1;
+    self.depth - 1
+  }
+
+  fn close(&mut self) {
+    self.code.op(END);
+    self.depth -= 1;
+  }
+
+  fn br(&mut self, block: u32) {
+    let relative = self.depth - 1 - block;
+    self.code.op_index(BR, relative);
+  }
+
+  // Stop the call if the value in `local` is an error.
+  fn check(&mut self, local: u32) {
+    self.code.get(local).call(IS_ERROR);
+    self.open(IF);
+    let result = self.result();
+    self.code.get(local).set(result);
+    self.br(self.done);
+    self.close();
+  }
+
+  // Run a statement, returning the local that holds its value.
+  fn statement(&mut self, id: NodeId) -> Result<u32, CodegenError> {
+    let value = self.local();
+    match &self.function.code[id] {
+      Node::Let { var, value: expr } => {
+        self.expression(*expr, false)?;
+        self.code.set(value);
+        self.check(value);
+        if let Some(slot) = var.slot {
+          let slot = self.slot(slot);
+          self.code.get(value).set(slot);
+        }
+      },
+      Node::Return(expr) | Node::Expr(expr) => {
+        self.expression(*expr, false)?;
+        self.code.set(value);
+        self.check(value);
+      },
+      // The first arm whose condition holds runs. An arm that ends in `false` lets the arms after it be
+      // tried too, and a first condition that isn't a bool is the value of the whole statement.
+      Node::If { arms } => {
+        self.code.i64(TRUE).set(value);
+        let end = self.open(BLOCK);
+        for (ix, (condition, body)) in arms.iter().enumerate() {
+          let mut opened = false;
+          if let Some(condition) = condition {
+            let test = self.local();
+            self.expression(*condition, true)?;
+            self.code.tee(test).i64(32).op(I64_SHR_S).i64(1).op(I64_NE);
+            self.open(IF);
+            match ix {
+              0 => {
+                self.code.get(test).set(value);
+                self.check(value);
+                self.br(end);
+              },
+              _ => {
+                let result = self.result();
+                self.code.i64(error("Not a boolean value")).set(result);
+                self.br(self.done);
+              },
+            }
+            self.close();
+            self.code.get(test).i64(TRUE).op(I64_EQ);
+            self.open(IF);
+            opened = true;
+          }
+          let arm = self.arm(body)?;
+          self.code.get(arm).i64(FALSE).op(I64_NE);
+          self.open(IF);
+          self.code.get(arm).set(value);
+          self.br(end);
+          self.close();
+          if opened {
+            self.close();
+          }
+        }
+        self.close();
+      },
+      Node::Assign { .. } => return Err(CodegenError::Unsupported("structs")),
+      Node::Match { .. } => return Err(CodegenError::Unsupported("match")),
+      Node::Try { .. } | Node::Throw(_) => return Err(CodegenError::Unsupported("throw and try")),
+      _ => return Err(CodegenError::Unsupported("this statement")),
+    }
+    Ok(value)
+  }
+
+  // Run the statements of an if arm up to the first `return`, whose value is the arm's; otherwise the
+  // arm's value is `true`.
+  fn arm(&mut self, body: &[NodeId]) -> Result<u32, CodegenError> {
+    let value = self.local();
+    self.code.i64(TRUE).set(value);
+    for stmt in body {
+      let result = self.statement(*stmt)?;
+      if let Node::Return(_) = self.function.code[*stmt] {
+        self.code.get(result).set(value);
+        break;
+      }
+    }
+    Ok(value)
+  }
+
+  // Push the value of a node. In the `condition` of an if, `==` and `!=` may also compare to a bool.
+  fn expression(&mut self, id: NodeId, condition: bool) -> Result<(), CodegenError> {
+    let code = &self.function.code;
+    match &code[id] {
+      Node::Number(value) => {
+        self.code.i64(*value as i64);
+      },
+      Node::Bool(value) => {
+        self.code.i64(if *value { TRUE } else { FALSE });
+      },
+      Node::Var(var) => match var.slot {
+        Some(slot) => {
+          let slot = self.slot(slot);
+          self.code.get(slot).call(READ);
+        },
+        // A compiled program has no globals.
+        None => {
+          self.code.i64(error("Undefined variable"));
+        },
+      },
+      // As in the runtime, what a comparison compares is checked before either side is evaluated, and
+      // both sides are evaluated even if one fails.
+      Node::Binary { op, lhs, rhs } if op.is_comparison() => {
+        // The runtime compares strings where this would report an error, so neither side may be one.
+        if matches!(code[*lhs], Node::String(_)) || matches!(code[*rhs], Node::String(_)) {
+          return Err(CodegenError::Unsupported("strings"));
+        }
+        if !matches!(code[*lhs], Node::Number(_) | Node::Var(_) | Node::Field { .. } | Node::Binary { .. }) {
+          self.code.i64(error("Invalid expression"));
+          return Ok(());
+        }
+        let comparable = match &code[*rhs] {
+          Node::Number(_) | Node::Var(_) | Node::Field { .. } | Node::Variant { .. } => true,
+          Node::Binary { op, .. } => !op.is_comparison(),
+          Node::Bool(_) => condition && matches!(op, BinaryOp::Eq | BinaryOp::Ne),
+          _ => false,
+        };
+        if !comparable {
+          self.code.i64(error("Invalid expression - can only compare numbers to numbers"));
+          return Ok(());
+        }
+        let (op, lhs, rhs) = (*op, *lhs, *rhs);
+        self.code.i32(op_code(op));
+        self.expression(lhs, false)?;
+        self.expression(rhs, false)?;
+        self.code.call(COMPARE);
+      },
+      Node::Binary { op, lhs, rhs } => {
+        let (op, lhs, rhs) = (*op, *lhs, *rhs);
+        let (lhs_local, rhs_local) = (self.local(), self.local());
+        self.expression(lhs, false)?;
+        self.code.tee(lhs_local).call(IS_ERROR).block(IF, I64).get(lhs_local).op(ELSE);
+        self.expression(rhs, false)?;
+        self.code.tee(rhs_local).call(IS_ERROR).block(IF, I64).get(rhs_local).op(ELSE);
+        self.code.i32(op_code(op)).get(lhs_local).get(rhs_local).call(MATH);
+        self.code.op(END).op(END);
+      },
+      Node::Call { callee, args } => {
+        if callee.method.is_some() {
+          return Err(CodegenError::Unsupported("method and module calls"));
+        }
+        let name = self.symbols.name(callee.name);
+        let call = match self.indices.get(name) {
+          Some((index, arity)) if *arity == args.len() => Some(*index),
+          Some(_) => None,
+          None if self.builtins.function(name).is_some() => return Err(CodegenError::Unsupported("builtins")),
+          // An undefined function fails before its arguments are evaluated.
+          None => {
+            self.code.i64(error("Undefined function"));
+            return Ok(());
+          },
+        };
+        // The arguments are evaluated in order, and the first to fail is the call's value.
+        let args = args.clone();
+        let locals: Vec<u32> = args.iter().map(|_| self.local()).collect();
+        for (arg, local) in args.iter().zip(&locals) {
+          self.expression(*arg, false)?;
+          self.code.tee(*local).call(IS_ERROR).block(IF, I64).get(*local).op(ELSE);
+        }
+        match call {
+          Some(index) => {
+            for local in &locals {
+              self.code.get(*local);
+            }
+            self.code.call(index);
+          },
+          None => {
+            self.code.i64(error("Wrong number of arguments"));
+          },
+        }
+        for _ in &locals {
+          self.code.op(END);
+        }
+      },
+      Node::String(_) => return Err(CodegenError::Unsupported("strings")),
+      Node::Struct { .. } | Node::Field { .. } => return Err(CodegenError::Unsupported("structs")),
+      Node::Variant { .. } | Node::IfLet { .. } => return Err(CodegenError::Unsupported("enums")),
+      Node::Match { .. } => return Err(CodegenError::Unsupported("match")),
+      Node::Throw(_) => return Err(CodegenError::Unsupported("throw and try")),
+      _ => return Err(CodegenError::Unsupported("this expression")),
+    }
+    Ok(())
+  }
+}
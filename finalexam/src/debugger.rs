@@ -0,0 +1,210 @@
+// An interactive step debugger, driven by commands read from `input` (stdin in `asa debug`).
+//
+// It hooks into the runtime through the `Debugger` trait. The syntax tree doesn't record positions, so
+// the debugger pairs each function's statements with the lines `recovery::statement_lines` reports for
+// them, and finds the statement it is handed by its place among them, which the runtime's `step` gives.
+
+use crate::ast::{walk_item, walk_stmts, Item, Program, Steps, Stmt};
+use crate::interpreter::{test_function, Debugger, Runtime};
+use crate::recovery::statement_lines;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "commands:
+  break <line> | break <function>   stop at a line, or on entering a function (b)
+  delete                            remove all breakpoints
+  step                              run to the next statement (s)
+  next                              run to the next statement in this function or its caller (n)
+  finish                            run until this function returns (f)
+  continue                          run to the next breakpoint (c)
+  print [<name>]                    show the variables of the current call (p)
+  backtrace                         show the active function calls (bt)
+  quit                              stop the program (q)";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+  Line(usize),
+  Function(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+  Step,
+  // Stop at a statement at most this many calls deep.
+  Next(usize),
+  Finish(usize),
+  Continue,
+}
+
+pub struct StepDebugger<R: BufRead, W: Write> {
+  input: R,
+  output: W,
+  source_lines: Vec<String>,
+  // Line of each statement of each function, in pre-order.
+  lines: HashMap<String, Vec<usize>>,
+  pub breakpoints: Vec<Breakpoint>,
+  mode: Mode,
+  // Function and current line of each active call, outermost first.
+  frames: Vec<(String, Option<usize>)>,
+  // Call depth at the previous statement.
+  depth: usize,
+}
+
+impl<R: BufRead, W: Write> StepDebugger<R, W> {
+  // A debugger for `tree`, parsed from `source`. It stops before the first statement.
+  pub fn new(source: &str, tree: &Program, input: R, output: W) -> StepDebugger<R, W> {
+    let mut all_lines = statement_lines(source).into_iter();
+    let mut lines = HashMap::new();
+    for item in &tree.items {
+      // Methods run as functions named `Struct::method`, each with steps of its own.
+      if let Item::Impl { name, methods } = item {
+        for method in methods {
+          let mut steps = Steps(vec![]);
+          walk_stmts(&method.body, &mut steps);
+          lines.insert(format!("{}::{}", name, method.name), all_lines.by_ref().take(steps.0.len()).collect());
+        }
+        continue;
+      }
+      let mut steps = Steps(vec![]);
+      walk_item(item, &mut steps);
+      // The runtime turns a top-level expression into a `return` in `main`, which is a step of its own.
+      let count = steps.0.len() + matches!(item, Item::Expr(_)) as usize;
+      let item_lines: Vec<usize> = all_lines.by_ref().take(count).collect();
+      match item {
+        Item::Fn(function) => {
+          lines.insert(function.name.clone(), item_lines);
+        },
+        Item::Test { name, .. } => {
+          lines.insert(test_function(name), item_lines);
+        },
+        // The runtime turns a top-level statement or expression into the body of `main`.
+        Item::Stmt(stmt) if !matches!(stmt, Stmt::If(_)) => {
+          lines.insert("main".to_string(), item_lines);
+        },
+        Item::Expr(_) => {
+          lines.insert("main".to_string(), item_lines);
+        },
+        _ => (),
+      }
+    }
+    StepDebugger {
+      input,
+      output,
+      source_lines: source.lines().map(|line| line.trim().to_string()).collect(),
+      lines,
+      breakpoints: vec![],
+      mode: Mode::Step,
+      frames: vec![],
+      depth: 0,
+    }
+  }
+
+  fn line_of(&self, runtime: &Runtime, function: &str) -> Option<usize> {
+    self.lines.get(function)?.get(runtime.step()?).copied()
+  }
+
+  fn location(&self, function: &str, line: Option<usize>) -> String {
+    match line {
+      Some(line) => {
+        let text = self.source_lines.get(line - 1).map(String::as_str).unwrap_or("");
+        format!("{} at line {}: {}", function, line, text)
+      },
+      None => format!("{} at an unknown line", function),
+    }
+  }
+
+  // Read and run commands until one resumes the program.
+  fn prompt(&mut self, runtime: &Runtime) -> Result<(), &'static str> {
+    let depth = self.depth;
+    loop {
+      let _ = write!(self.output, "(asa) ");
+      let _ = self.output.flush();
+      let mut command = String::new();
+      if self.input.read_line(&mut command).unwrap_or(0) == 0 {
+        // Out of commands: let the program run to the end.
+        self.breakpoints.clear();
+        self.mode = Mode::Continue;
+        return Ok(());
+      }
+      let words: Vec<&str> = command.split_whitespace().collect();
+      match words.as_slice() {
+        ["step"] | ["s"] => self.mode = Mode::Step,
+        ["next"] | ["n"] => self.mode = Mode::Next(depth),
+        ["finish"] | ["f"] => self.mode = Mode::Finish(depth),
+        ["continue"] | ["c"] => self.mode = Mode::Continue,
+        ["quit"] | ["q"] => return Err("Stopped by debugger"),
+        ["break", target] | ["b", target] => {
+          let breakpoint = match target.parse() {
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => Breakpoint::Function(target.to_string()),
+          };
+          let _ = writeln!(self.output, "breakpoint {}: {}", self.breakpoints.len() + 1, match &breakpoint {
+            Breakpoint::Line(line) => format!("line {}", line),
+            Breakpoint::Function(name) => format!("function {}", name),
+          });
+          self.breakpoints.push(breakpoint);
+          continue;
+        },
+        ["delete"] => {
+          self.breakpoints.clear();
+          continue;
+        },
+        ["print"] | ["p"] => {
+          let mut bindings: Vec<_> = runtime.frame().map(|frame| frame.into_iter().collect()).unwrap_or_default();
+          bindings.sort_by(|a, b| a.0.cmp(b.0));
+          for (name, value) in bindings {
+            let _ = writeln!(self.output, "{} = {:?}", name, value);
+          }
+          continue;
+        },
+        ["print", name] | ["p", name] => {
+          let _ = match runtime.frame().and_then(|frame| frame.get(*name).copied()) {
+            Some(value) => writeln!(self.output, "{} = {:?}", name, value),
+            None => writeln!(self.output, "no variable `{}` in this call", name),
+          };
+          continue;
+        },
+        ["backtrace"] | ["bt"] => {
+          for (ix, (function, line)) in self.frames.iter().rev().enumerate() {
+            let _ = writeln!(self.output, "#{} {}", ix, self.location(function, *line));
+          }
+          continue;
+        },
+        [] => continue,
+        _ => {
+          let _ = writeln!(self.output, "{}", HELP);
+          continue;
+        },
+      }
+      return Ok(());
+    }
+  }
+}
+
+impl<R: BufRead, W: Write> Debugger for StepDebugger<R, W> {
+  fn before_statement(&mut self, runtime: &Runtime, _statement: &Stmt) -> Result<(), &'static str> {
+    let function = runtime.backtrace().last().cloned().unwrap_or_else(|| "<top level>".to_string());
+    let depth = runtime.backtrace().len();
+    let entered = depth > self.depth;
+    self.depth = depth;
+    let line = self.line_of(runtime, &function);
+    self.frames.truncate(depth.max(1) - 1);
+    self.frames.push((function.clone(), line));
+
+    let stepped = match self.mode {
+      Mode::Step => true,
+      Mode::Next(limit) => depth <= limit,
+      Mode::Finish(limit) => depth < limit,
+      Mode::Continue => false,
+    };
+    let hit = self.breakpoints.iter().any(|breakpoint| match breakpoint {
+      Breakpoint::Line(at) => line == Some(*at),
+      Breakpoint::Function(name) => entered && *name == function,
+    });
+    if !stepped && !hit {
+      return Ok(());
+    }
+    let _ = writeln!(self.output, "{}", self.location(&function, line));
+    self.prompt(runtime)
+  }
+}
@@ -0,0 +1,669 @@
+// Pretty-printer for Asa: turns a parse tree back into canonically formatted source.
+//
+// Layout is fixed: two-space indentation, one space around binary operators and after commas, a blank
+// line between top-level functions, and parentheses only where the l1-l4 precedence levels of the parser
+// need them. The tree doesn't keep ordinary comments, so `format_source` walks the original text
+// alongside the output, token by token, and carries over every comment it passes.
+
+use crate::ast::{ArmBody, Expr, FnDecl, If, Item, Match, Pattern, Program, Stmt};
+use crate::parser;
+use crate::recovery::{program_with_recovery, SyntaxError};
+use std::fmt;
+
+const INDENT: &str = "  ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+  // The source doesn't parse.
+  Syntax(Vec<SyntaxError>),
+  // A comment couldn't be matched up with the formatted output, so formatting would drop it.
+  LostComments,
+  // Reparsing the formatted output gave a different program.
+  ChangedMeaning,
+}
+
+impl fmt::Display for FormatError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FormatError::Syntax(errors) => {
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", messages.join("\n"))
+      },
+      FormatError::LostComments => write!(f, "could not keep every comment in place; file left unformatted"),
+      FormatError::ChangedMeaning => write!(f, "formatting would change the meaning of the program; file left unformatted"),
+    }
+  }
+}
+
+// Format a parse tree on its own. Doc comments are kept; other comments aren't part of the tree.
+pub fn format_program(program: &Program) -> String {
+  let mut printer = Printer::new(None);
+  printer.program(program);
+  printer.finish()
+}
+
+// Format source text, keeping its comments. The result is checked by parsing it again: if that doesn't
+// give back the same program, the source is reported as unformattable rather than silently changed.
+pub fn format_source(source: &str) -> Result<String, FormatError> {
+  let tree = parse(source)?;
+  let mut printer = Printer::new(Some(source));
+  printer.program(&tree);
+  let formatted = printer.finish();
+  if printer.comments_written != count_comments(source) {
+    return Err(FormatError::LostComments);
+  }
+  match parse(&formatted) {
+    Ok(reparsed) if reparsed == tree => Ok(formatted),
+    _ => Err(FormatError::ChangedMeaning),
+  }
+}
+
+fn parse(source: &str) -> Result<Program, FormatError> {
+  match parser::program(source) {
+    Ok(("", tree)) => Ok(tree),
+    _ => {
+      let (_, errors) = program_with_recovery(source);
+      match errors.is_empty() {
+        true => Err(FormatError::Syntax(vec![SyntaxError { message: "could not parse program".to_string(), offset: 0, line: 1, column: 1 }])),
+        false => Err(FormatError::Syntax(errors)),
+      }
+    }
+  }
+}
+
+// Binding strength of an expression; higher binds tighter. Matches the parser's l1-l4 levels, with
+// comparisons and match expressions below all of them.
+fn precedence(expr: &Expr) -> u8 {
+  match expr {
+    Expr::Binary { op, .. } => op.precedence(),
+    Expr::Match(_) => 0,
+    _ => 4,
+  }
+}
+
+struct Comment {
+  text: String,
+  // The comment started on a line of its own rather than after code.
+  own_line: bool,
+  // There was an empty line in front of the comment.
+  blank_before: bool,
+}
+
+struct Printer<'a> {
+  out: String,
+  indent: usize,
+  // The part of the original source not yet matched against the output, if comments are being kept.
+  source: Option<&'a str>,
+  at_file_start: bool,
+  // Newlines requested but not yet written: 1 for a line break, 2 for a blank line.
+  pending_newlines: usize,
+  // Comments that belong at the end of the current line.
+  trailing: Vec<String>,
+  comments_written: usize,
+}
+
+impl<'a> Printer<'a> {
+  fn new(source: Option<&'a str>) -> Printer<'a> {
+    Printer { out: String::new(), indent: 0, source, at_file_start: true, pending_newlines: 0, trailing: vec![], comments_written: 0 }
+  }
+
+  fn at_line_start(&self) -> bool {
+    self.out.is_empty() || self.out.ends_with('\n')
+  }
+
+  fn newline(&mut self) {
+    self.pending_newlines = self.pending_newlines.max(1);
+  }
+
+  fn blank_line(&mut self) {
+    self.pending_newlines = 2;
+  }
+
+  fn space(&mut self) {
+    if self.pending_newlines == 0 && !self.at_line_start() {
+      self.out.push(' ');
+    }
+  }
+
+  fn flush_newlines(&mut self) {
+    for comment in std::mem::take(&mut self.trailing) {
+      self.out.push(' ');
+      self.out.push_str(&comment);
+    }
+    if !self.out.is_empty() {
+      for _ in 0..self.pending_newlines {
+        self.out.push('\n');
+      }
+    }
+    self.pending_newlines = 0;
+  }
+
+  fn write_indented(&mut self, text: &str) {
+    if self.at_line_start() {
+      self.out.push_str(&INDENT.repeat(self.indent));
+    }
+    self.out.push_str(text);
+  }
+
+  fn write_comments(&mut self, comments: Vec<Comment>) {
+    for comment in comments {
+      self.comments_written += 1;
+      if !comment.own_line && !self.at_file_start {
+        if comment.text.starts_with("/*") && self.pending_newlines == 0 {
+          self.space();
+          self.write_indented(&comment.text);
+        } else {
+          self.trailing.push(comment.text);
+        }
+        continue;
+      }
+      if comment.blank_before && !self.out.is_empty() {
+        self.blank_line();
+      } else if !self.at_line_start() {
+        self.newline();
+      }
+      self.flush_newlines();
+      self.write_indented(&comment.text);
+      self.newline();
+    }
+  }
+
+  fn token(&mut self, text: &str) {
+    let (comments, blank_before) = self.align(text, false);
+    self.write_comments(comments);
+    if blank_before && self.pending_newlines > 0 {
+      self.blank_line();
+    }
+    self.flush_newlines();
+    self.write_indented(text);
+    self.at_file_start = false;
+  }
+
+  // A token the output always has but the source may leave out, like the comma after a match arm.
+  fn optional_token(&mut self, text: &str) {
+    self.align(text, true);
+    self.write_indented(text);
+  }
+
+  // Advance through the source to `text`, returning the comments passed on the way and whether there
+  // was a blank line right before it. Parentheses and commas the output doesn't have are skipped; any
+  // other mismatch stops comment tracking, which `format_source` then reports.
+  fn align(&mut self, text: &str, optional: bool) -> (Vec<Comment>, bool) {
+    let mut rest = match self.source {
+      Some(rest) => rest,
+      None => return (vec![], false),
+    };
+    let mut comments = vec![];
+    loop {
+      let (after, found, blank_before) = scan_trivia(rest, self.at_file_start && comments.is_empty());
+      comments.extend(found);
+      rest = after;
+      if let Some(after) = rest.strip_prefix(text) {
+        self.source = Some(after);
+        return (comments, blank_before);
+      }
+      if optional {
+        return (vec![], false);
+      }
+      if rest.starts_with(['(', ')', ',']) {
+        rest = &rest[1..];
+        continue;
+      }
+      self.source = None;
+      return (comments, false);
+    }
+  }
+
+  fn finish(&mut self) -> String {
+    if let Some(rest) = self.source {
+      let (_, comments, _) = scan_trivia(rest, self.at_file_start);
+      self.write_comments(comments);
+    }
+    self.newline();
+    self.flush_newlines();
+    std::mem::take(&mut self.out)
+  }
+
+  // ---- Tree walking ----
+
+  fn program(&mut self, program: &Program) {
+    let items = &program.items;
+    for (ix, item) in items.iter().enumerate() {
+      if ix > 0 {
+        let is_function = |item: &Item| matches!(item, Item::Fn(_) | Item::Test { .. } | Item::Struct { .. } | Item::Enum { .. } | Item::Impl { .. });
+        match is_function(item) || is_function(&items[ix - 1]) {
+          true => self.blank_line(),
+          false => self.newline(),
+        }
+      }
+      self.item(item);
+    }
+  }
+
+  fn item(&mut self, item: &Item) {
+    match item {
+      Item::Fn(function) => self.function(function),
+      Item::Test { name, body } => {
+        self.token("test");
+        self.space();
+        self.token(&format!("\"{}\"", name));
+        self.space();
+        self.block(body);
+      },
+      Item::Expr(expr) => self.expression(expr, 0),
+      Item::Stmt(stmt) => self.statement(stmt),
+      Item::Import { name, path } => {
+        self.token("import");
+        self.space();
+        match path {
+          Some(path) => self.token(&format!("\"{}\"", path)),
+          None => self.token(name),
+        }
+        self.token(";");
+      },
+      Item::Struct { name, fields } => {
+        self.token("struct");
+        self.space();
+        self.token(name);
+        self.space();
+        self.token("{");
+        for (ix, field) in fields.iter().enumerate() {
+          if ix > 0 {
+            self.token(",");
+          }
+          self.space();
+          self.token(field);
+        }
+        if !fields.is_empty() {
+          self.space();
+        }
+        self.token("}");
+      },
+      Item::Enum { name, variants } => {
+        self.token("enum");
+        self.space();
+        self.token(name);
+        self.space();
+        self.token("{");
+        for (ix, variant) in variants.iter().enumerate() {
+          if ix > 0 {
+            self.token(",");
+          }
+          self.space();
+          self.token(&variant.name);
+          if !variant.fields.is_empty() {
+            self.token("(");
+            for (ix, field) in variant.fields.iter().enumerate() {
+              if ix > 0 {
+                self.token(",");
+                self.space();
+              }
+              self.token(field);
+            }
+            self.token(")");
+          }
+        }
+        if !variants.is_empty() {
+          self.space();
+        }
+        self.token("}");
+      },
+      Item::Impl { name, methods } => {
+        self.token("impl");
+        self.space();
+        self.token(name);
+        self.space();
+        self.token("{");
+        if !methods.is_empty() {
+          self.indent += 1;
+          for (ix, method) in methods.iter().enumerate() {
+            match ix {
+              0 => self.newline(),
+              _ => self.blank_line(),
+            }
+            self.function(method);
+          }
+          self.indent -= 1;
+          self.newline();
+        }
+        self.token("}");
+      },
+    }
+  }
+
+  fn function(&mut self, function: &FnDecl) {
+    // With the source available, doc comments come across with the other comments.
+    if self.source.is_none() && !function.doc.is_empty() {
+      for line in function.doc.split('\n') {
+        match line.is_empty() {
+          true => self.token("///"),
+          false => self.token(&format!("/// {}", line)),
+        }
+        self.newline();
+      }
+    }
+    self.token("fn");
+    self.space();
+    self.token(&function.name);
+    self.token("(");
+    for (ix, param) in function.params.iter().enumerate() {
+      if ix > 0 {
+        self.token(",");
+        self.space();
+      }
+      self.token(param);
+    }
+    self.token(")");
+    self.space();
+    self.block(&function.body);
+  }
+
+  fn block(&mut self, statements: &[Stmt]) {
+    self.token("{");
+    self.indent += 1;
+    for statement in statements {
+      self.newline();
+      self.statement(statement);
+    }
+    self.indent -= 1;
+    self.newline();
+    self.token("}");
+  }
+
+  fn statement(&mut self, stmt: &Stmt) {
+    match stmt {
+      Stmt::Return(value) => {
+        self.token("return");
+        self.space();
+        self.expression(value, 0);
+        self.token(";");
+      },
+      Stmt::Throw(value) => {
+        self.token("throw");
+        self.space();
+        self.expression(value, 0);
+        self.token(";");
+      },
+      Stmt::Try { body, variable, handler } => {
+        self.token("try");
+        self.space();
+        self.block(body);
+        self.space();
+        self.token("catch");
+        self.space();
+        self.token(variable);
+        self.space();
+        self.block(handler);
+      },
+      Stmt::Assign { target, value } => {
+        self.expression(target, 4);
+        self.space();
+        self.token("=");
+        self.space();
+        self.expression(value, 0);
+        self.token(";");
+      },
+      Stmt::Let { name, value } => {
+        self.token("let");
+        self.space();
+        self.token(name);
+        self.space();
+        self.token("=");
+        self.space();
+        self.expression(value, 0);
+        self.token(";");
+      },
+      Stmt::Expr(expr) => {
+        self.expression(expr, 0);
+        self.token(";");
+      },
+      Stmt::If(if_) => self.if_expression(if_),
+      Stmt::Match(match_) => self.match_expression(match_),
+    }
+  }
+
+  fn if_expression(&mut self, if_: &If) {
+    self.token("if");
+    self.space();
+    self.condition(&if_.cond);
+    self.space();
+    self.block(&if_.then);
+    for (cond, body) in &if_.elifs {
+      self.space();
+      self.token("else");
+      self.space();
+      self.token("if");
+      self.space();
+      self.condition(cond);
+      self.space();
+      self.block(body);
+    }
+    if let Some(body) = &if_.else_ {
+      self.space();
+      self.token("else");
+      self.space();
+      self.block(body);
+    }
+  }
+
+  fn match_expression(&mut self, match_: &Match) {
+    self.token("match");
+    self.space();
+    self.expression(&match_.scrutinee, 0);
+    self.space();
+    self.token("{");
+    self.indent += 1;
+    for arm in &match_.arms {
+      self.newline();
+      for (ix, alternative) in arm.patterns.iter().enumerate() {
+        if ix > 0 {
+          self.space();
+          self.token("|");
+          self.space();
+        }
+        self.pattern(alternative);
+      }
+      if let Some(guard) = &arm.guard {
+        self.space();
+        self.token("if");
+        self.space();
+        self.condition(guard);
+      }
+      self.space();
+      self.token("=>");
+      self.space();
+      match &arm.body {
+        ArmBody::Expr(body) => {
+          self.expression(body, 0);
+          self.optional_token(",");
+        },
+        ArmBody::Block(statements) => self.block(statements),
+      }
+    }
+    self.indent -= 1;
+    self.newline();
+    self.token("}");
+  }
+
+  fn pattern(&mut self, pattern: &Pattern) {
+    match pattern {
+      Pattern::Wildcard => self.token("_"),
+      Pattern::Number(value) => self.token(&value.to_string()),
+      Pattern::Bool(value) => self.token(&value.to_string()),
+      Pattern::String(value) => self.token(&format!("\"{}\"", value)),
+      Pattern::Binding(name) => self.token(name),
+      Pattern::Variant { name, variant, fields } => {
+        self.token(name);
+        self.token("::");
+        self.token(variant);
+        if !fields.is_empty() {
+          self.token("(");
+          for (ix, field) in fields.iter().enumerate() {
+            if ix > 0 {
+              self.token(",");
+              self.space();
+            }
+            self.pattern(field);
+          }
+          self.token(")");
+        }
+      },
+    }
+  }
+
+  fn comma_separated(&mut self, exprs: &[Expr]) {
+    for (ix, expr) in exprs.iter().enumerate() {
+      if ix > 0 {
+        self.token(",");
+        self.space();
+      }
+      self.expression(expr, 0);
+    }
+  }
+
+  // Print the condition of an if or a match guard. Only a `let`, a comparison, a boolean or a variable
+  // can go there as it is; anything else keeps the parentheses it needs to parse.
+  fn condition(&mut self, cond: &Expr) {
+    match cond {
+      Expr::Let { .. } | Expr::Bool(_) | Expr::Identifier(_) => self.expression(cond, 0),
+      Expr::Binary { op, .. } if op.is_comparison() => self.expression(cond, 0),
+      _ => {
+        self.token("(");
+        self.expression(cond, 0);
+        self.token(")");
+      },
+    }
+  }
+
+  // Print an expression, parenthesised if it binds more loosely than `min_precedence`.
+  fn expression(&mut self, expr: &Expr, min_precedence: u8) {
+    let parens = precedence(expr) < min_precedence;
+    if parens {
+      self.token("(");
+    }
+    match expr {
+      Expr::Binary { op, lhs, rhs } if op.is_comparison() => {
+        self.expression(lhs, 1);
+        self.space();
+        self.token(op.symbol());
+        self.space();
+        self.expression(rhs, 1);
+      },
+      Expr::Binary { op, lhs, rhs } => {
+        let level = op.precedence();
+        self.expression(lhs, level);
+        self.space();
+        self.token(op.symbol());
+        self.space();
+        // Every level is left-associative, so an equal-precedence right operand needs parentheses.
+        self.expression(rhs, level + 1);
+      },
+      Expr::Match(match_) => self.match_expression(match_),
+      Expr::Call { name, args } => {
+        self.token(name);
+        self.token("(");
+        self.comma_separated(args);
+        self.token(")");
+      },
+      Expr::Struct { name, fields } => {
+        self.token(name);
+        self.space();
+        self.token("{");
+        for (ix, (field, value)) in fields.iter().enumerate() {
+          if ix > 0 {
+            self.token(",");
+          }
+          self.space();
+          self.token(field);
+          self.token(":");
+          self.space();
+          self.expression(value, 0);
+        }
+        if !fields.is_empty() {
+          self.space();
+        }
+        self.token("}");
+      },
+      Expr::Variant { name, variant, args } => {
+        self.token(name);
+        self.token("::");
+        self.token(variant);
+        if !args.is_empty() {
+          self.token("(");
+          self.comma_separated(args);
+          self.token(")");
+        }
+      },
+      Expr::Let { pattern, value } => {
+        self.token("let");
+        self.space();
+        self.pattern(pattern);
+        self.space();
+        self.token("=");
+        self.space();
+        self.expression(value, 0);
+      },
+      Expr::Field { object, field } => {
+        self.expression(object, 4);
+        self.token(".");
+        self.token(field);
+      },
+      Expr::Throw(value) => {
+        self.token("throw");
+        self.space();
+        self.expression(value, 0);
+      },
+      Expr::Number(value) => self.token(&value.to_string()),
+      Expr::Bool(value) => self.token(&value.to_string()),
+      Expr::String(value) => self.token(&format!("\"{}\"", value)),
+      Expr::Identifier(name) => self.token(name),
+    }
+    if parens {
+      self.token(")");
+    }
+  }
+}
+
+// Skip whitespace and comments at the start of `input`. Returns the rest of the input, the comments
+// found and whether a blank line separates the last of them (or the start) from what follows.
+fn scan_trivia(input: &str, at_file_start: bool) -> (&str, Vec<Comment>, bool) {
+  let mut rest = input;
+  let mut comments = vec![];
+  let mut newlines = 0;
+  loop {
+    let whitespace = rest.len() - rest.trim_start().len();
+    newlines += rest[..whitespace].matches('\n').count();
+    rest = &rest[whitespace..];
+    let comment = if rest.starts_with("//") {
+      rest.split('\n').next().unwrap_or(rest)
+    } else if let Ok((_, comment)) = parser::block_comment(rest) {
+      comment
+    } else {
+      return (rest, comments, newlines >= 2);
+    };
+    comments.push(Comment {
+      text: comment.trim_end().to_string(),
+      own_line: newlines > 0 || at_file_start,
+      blank_before: newlines >= 2,
+    });
+    rest = &rest[comment.len()..];
+    newlines = 0;
+  }
+}
+
+fn count_comments(source: &str) -> usize {
+  let mut count = 0;
+  let mut rest = source;
+  while !rest.is_empty() {
+    let (after, comments, _) = scan_trivia(rest, false);
+    count += comments.len();
+    rest = after;
+    // Step over one token, treating string literals as a unit so comment markers inside them don't count.
+    let step = match parser::string(rest) {
+      Ok((after, _)) => rest.len() - after.len(),
+      Err(_) => rest.chars().next().map(|c| c.len_utf8()).unwrap_or(0),
+    };
+    rest = &rest[step..];
+  }
+  count
+}
@@ -0,0 +1,186 @@
+// Host (Rust) functions callable from Asa, and conversions between `Value` and Rust types.
+//
+// Register a function on a `Runtime` either with `register_fn`, which hands it the raw argument values,
+// or with `register_typed`, which converts arguments and the result through `FromValue`/`IntoValue` and
+// takes the arity from the closure's parameter list:
+//
+//   runtime.register_fn("now", Arity::Exact(0), |_| Ok(Value::Number(42)));
+//   runtime.register_typed("add", |a: i64, b: i64| a + b);
+
+use crate::interpreter::Value;
+use std::rc::Rc;
+
+// Errors are static messages, the same as everywhere else in the runtime.
+pub type RuntimeError = &'static str;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+  Exact(usize),
+  // Any number of arguments.
+  Variadic,
+}
+
+impl Arity {
+  pub fn accepts(&self, count: usize) -> bool {
+    match self {
+      Arity::Exact(n) => *n == count,
+      Arity::Variadic => true,
+    }
+  }
+}
+
+type NativeFn = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
+
+#[derive(Clone)]
+pub struct HostFunction {
+  pub arity: Arity,
+  function: Rc<NativeFn>,
+}
+
+impl HostFunction {
+  pub fn new<F>(arity: Arity, function: F) -> HostFunction
+  where F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static {
+    HostFunction { arity, function: Rc::new(function) }
+  }
+
+  pub fn call(&self, args: &[Value]) -> Result<Value, RuntimeError> {
+    if !self.arity.accepts(args.len()) {
+      return Err("Wrong number of arguments");
+    }
+    (self.function)(args)
+  }
+}
+
+pub trait FromValue: Sized {
+  fn from_value(value: &Value) -> Result<Self, RuntimeError>;
+}
+
+pub trait IntoValue {
+  fn into_value(self) -> Result<Value, RuntimeError>;
+}
+
+impl FromValue for Value {
+  fn from_value(value: &Value) -> Result<Value, RuntimeError> {
+    Ok(value.clone())
+  }
+}
+
+impl IntoValue for Value {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    Ok(self)
+  }
+}
+
+impl FromValue for i32 {
+  fn from_value(value: &Value) -> Result<i32, RuntimeError> {
+    match value {
+      Value::Number(n) => Ok(*n),
+      _ => Err("Expected a number"),
+    }
+  }
+}
+
+impl IntoValue for i32 {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(self))
+  }
+}
+
+// Asa numbers are 32-bit, so an i64 result has to fit in an i32.
+impl FromValue for i64 {
+  fn from_value(value: &Value) -> Result<i64, RuntimeError> {
+    i32::from_value(value).map(i64::from)
+  }
+}
+
+impl IntoValue for i64 {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    i32::try_from(self).map(Value::Number).map_err(|_| "Number out of range")
+  }
+}
+
+impl FromValue for bool {
+  fn from_value(value: &Value) -> Result<bool, RuntimeError> {
+    match value {
+      Value::Bool(b) => Ok(*b),
+      _ => Err("Expected a boolean"),
+    }
+  }
+}
+
+impl IntoValue for bool {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    Ok(Value::Bool(self))
+  }
+}
+
+impl FromValue for String {
+  fn from_value(value: &Value) -> Result<String, RuntimeError> {
+    match value {
+      Value::String(s) => Ok(s.clone()),
+      _ => Err("Expected a string"),
+    }
+  }
+}
+
+impl IntoValue for String {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    Ok(Value::String(self))
+  }
+}
+
+impl IntoValue for &str {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    Ok(Value::String(self.to_string()))
+  }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+  fn from_value(value: &Value) -> Result<Vec<T>, RuntimeError> {
+    match value {
+      Value::List(items) => items.iter().map(T::from_value).collect(),
+      _ => Err("Expected a list"),
+    }
+  }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    self.into_iter().map(T::into_value).collect::<Result<Vec<Value>, RuntimeError>>().map(Value::List)
+  }
+}
+
+// A fallible host function reports its error to the script.
+impl<T: IntoValue> IntoValue for Result<T, RuntimeError> {
+  fn into_value(self) -> Result<Value, RuntimeError> {
+    self.and_then(T::into_value)
+  }
+}
+
+// Closures whose parameters and result convert to and from `Value`. `Args` is the tuple of parameter
+// types; it only serves to tell the implementations apart.
+pub trait TypedFunction<Args> {
+  fn into_host_function(self) -> HostFunction;
+}
+
+macro_rules! typed_function {
+  ($count:expr $(, $arg:ident)*) => (
+    impl<F, R, $($arg),*> TypedFunction<($($arg,)*)> for F
+    where F: Fn($($arg),*) -> R + 'static, R: IntoValue, $($arg: FromValue),* {
+      #[allow(non_snake_case, unused_variables, unused_mut)]
+      fn into_host_function(self) -> HostFunction {
+        HostFunction::new(Arity::Exact($count), move |args: &[Value]| {
+          let mut args = args.iter();
+          $(let $arg = $arg::from_value(args.next().ok_or("Wrong number of arguments")?)?;)*
+          (self)($($arg),*).into_value()
+        })
+      }
+    }
+  )
+}
+
+typed_function!(0);
+typed_function!(1, A);
+typed_function!(2, A, B);
+typed_function!(3, A, B, C);
+typed_function!(4, A, B, C, D);
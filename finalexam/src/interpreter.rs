@@ -1,617 +1,970 @@
-use crate::parser::Node;
+use crate::ast::{BinaryOp, Expr, FnDecl, Item, Program, Stmt};
+use crate::host::{Arity, HostFunction, RuntimeError, TypedFunction};
+use crate::lower::{self, ArmCode, Callee, Function, Node, NodeId, Pat, Symbol, Symbols, Var};
+use crate::modules::{self, Import, Module, ModuleError};
+use crate::stdlib::{self, Io, StdIo, EXITED};
+use crate::tracer::Tracer;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
   String(String),
   Number(i32),
   Bool(bool),
+  // Asa has no list syntax yet; lists come from host functions and can be passed back to them.
+  List(Vec<Value>),
+  // A value of a declared struct type, with its fields in declaration order.
+  Struct { name: String, fields: Vec<(String, Value)> },
+  // A variant of a declared enum, with its values in declaration order.
+  Variant { name: String, variant: String, values: Vec<Value> },
 }
 
+// How `print` shows a value: strings without quotes, lists in brackets.
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::String(s) => write!(f, "{}", s),
+      Value::Number(n) => write!(f, "{}", n),
+      Value::Bool(b) => write!(f, "{}", b),
+      Value::List(items) => {
+        let items: Vec<String> = items.iter().map(quoted).collect();
+        write!(f, "[{}]", items.join(", "))
+      },
+      Value::Struct { name, fields } if fields.is_empty() => write!(f, "{} {{}}", name),
+      Value::Struct { name, fields } => {
+        let fields: Vec<String> = fields.iter().map(|(field, value)| format!("{}: {}", field, quoted(value))).collect();
+        write!(f, "{} {{ {} }}", name, fields.join(", "))
+      },
+      Value::Variant { name, variant, values } if values.is_empty() => write!(f, "{}::{}", name, variant),
+      Value::Variant { name, variant, values } => {
+        let values: Vec<String> = values.iter().map(quoted).collect();
+        write!(f, "{}::{}({})", name, variant, values.join(", "))
+      },
+    }
+  }
+}
+
+
+// Strings inside a list, struct or variant are shown quoted.
+pub(crate) fn quoted(value: &Value) -> String {
+  match value {
+    Value::String(s) => format!("{:?}", s),
+    other => other.to_string(),
+  }
+}
+
+// The error a `throw` that nothing catches stops the program with; `Runtime::thrown` has the value.
+pub const THROWN: &str = "Uncaught error";
+
+// How many script function calls can be active at once. Every call also nests a few Rust calls, so
+// without a limit a runaway recursion would overflow the native stack and abort the process.
+pub const MAX_CALL_DEPTH: usize = 400;
+
+// The name the body of `test "adds numbers" { ... }` runs under. No script can call it.
+pub fn test_function(name: &str) -> String {
+  format!("test \"{}\"", name)
+}
+
+// Why a program `Runtime::run_file` ran didn't finish.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunError {
+  Module(ModuleError),
+  // The runtime error or the uncaught value, followed by the calls it was raised in, innermost first,
+  // one `  in` line each.
+  Runtime(String),
+}
+
+impl fmt::Display for RunError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RunError::Module(error) => write!(f, "{}", error),
+      RunError::Runtime(report) => write!(f, "{}", report),
+    }
+  }
+}
+
+// A hook into a running program, e.g. a step debugger. `before_statement` is called with the runtime
+// and the statement about to run; returning an error stops the program with that error.
+pub trait Debugger {
+  fn before_statement(&mut self, runtime: &Runtime, statement: &Stmt) -> Result<(), &'static str>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+  pub name: String,
+  // Parameter names; empty for host functions, which only declare an arity.
+  pub params: Vec<String>,
+  pub arity: Arity,
+  pub host: bool,
+}
+
+// The variables of a function call.
+struct Frame {
+  function: Rc<Function>,
+  // The value of each of the function's variables, by slot; empty until it's bound.
+  slots: Vec<Option<Value>>,
+}
 
 pub struct Runtime {
-  functions: HashMap<String, Vec<Node>>,
-  stack: Vec<HashMap<String, Value>>,
+  // Names of functions, variables and modules, interned.
+  symbols: Symbols,
+  functions: HashMap<Symbol, Rc<Function>>,
+  host_functions: HashMap<Symbol, HostFunction>,
+  globals: HashMap<String, Value>,
+  stack: Vec<Frame>,
+  // Names of the functions currently being called, outermost first.
+  calls: Vec<Symbol>,
+  debugger: Option<Box<dyn Debugger>>,
+  // The index, among the steps of its function, of the statement the debugger was last handed.
+  step: Option<usize>,
+  // Set when the debugger stops the program; every later statement fails with the same error.
+  halted: Option<&'static str>,
+  tracer: Option<Tracer>,
+  // Field names of each declared struct type.
+  structs: HashMap<String, Vec<String>>,
+  // Each declared enum's variants and how many values each one holds.
+  enums: HashMap<String, Vec<(String, usize)>>,
+  // The value of the `throw` being propagated.
+  thrown: Option<Value>,
+  // Where a host function puts the value it throws before failing with `THROWN`.
+  host_thrown: Rc<RefCell<Option<Value>>>,
+  // The active calls where the error being propagated was raised.
+  error_stack: Vec<String>,
+  // Modules parsed so far, by canonical path.
+  modules: HashMap<PathBuf, Rc<Module>>,
 }
 
 impl Runtime {
 
+  // A runtime with the standard prelude, doing real I/O.
   pub fn new() -> Runtime {
-    Runtime {
+    let mut runtime = Runtime {
+      symbols: Symbols::default(),
       functions: HashMap::new(),
+      host_functions: HashMap::new(),
+      globals: HashMap::new(),
       stack: Vec::new(),
+      calls: Vec::new(),
+      debugger: None,
+      step: None,
+      halted: None,
+      tracer: None,
+      structs: HashMap::new(),
+      enums: HashMap::new(),
+      thrown: None,
+      host_thrown: Rc::new(RefCell::new(None)),
+      error_stack: Vec::new(),
+      modules: HashMap::new(),
+    };
+    // Runtime errors are caught as `Error` values; scripts can build and throw their own.
+    runtime.structs.insert("Error".to_string(), vec!["kind".to_string(), "message".to_string()]);
+    runtime.set_io(Rc::new(RefCell::new(StdIo::default())));
+    runtime
+  }
+
+  // Route the prelude's I/O (print, read_line, files, ...) through `io`.
+  pub fn set_io(&mut self, io: Rc<RefCell<dyn Io>>) {
+    stdlib::register(self, io);
+  }
+
+  pub fn set_debugger(&mut self, debugger: Box<dyn Debugger>) {
+    self.debugger = Some(debugger);
+  }
+
+  // Make a Rust function callable from Asa. Functions defined in the script take precedence.
+  pub fn register_fn<F>(&mut self, name: &str, arity: Arity, function: F)
+  where F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static {
+    let name = self.symbols.intern(name);
+    self.host_functions.insert(name, HostFunction::new(arity, function));
+  }
+
+  // Like `register_fn`, but arguments and result go through `FromValue`/`IntoValue` and the arity is
+  // that of the closure.
+  pub fn register_typed<F, Args>(&mut self, name: &str, function: F)
+  where F: TypedFunction<Args> {
+    let name = self.symbols.intern(name);
+    self.host_functions.insert(name, function.into_host_function());
+  }
+
+  // Record function timings and node counts from now on.
+  pub fn set_tracer(&mut self, tracer: Tracer) {
+    self.tracer = Some(tracer);
+  }
+
+  pub fn tracer(&self) -> Option<&Tracer> {
+    self.tracer.as_ref()
+  }
+
+  // The active function calls, outermost first.
+  pub fn backtrace(&self) -> Vec<String> {
+    self.calls.iter().map(|name| self.symbols.name(*name).to_string()).collect()
+  }
+
+  // The variables of the innermost call that have been bound.
+  pub fn frame(&self) -> Option<HashMap<&str, &Value>> {
+    let frame = self.stack.last()?;
+    let names = frame.function.slots.iter().map(|name| self.symbols.name(*name));
+    Some(names.zip(&frame.slots).filter_map(|(name, value)| Some((name, value.as_ref()?))).collect())
+  }
+
+  // The statements of a defined function.
+  pub fn function_body(&self, name: &str) -> Option<&[Stmt]> {
+    let function = self.functions.get(&self.symbols.get(name)?)?;
+    Some(&function.decl.body)
+  }
+
+  // Which statement a `Debugger` is looking at: its index among the statements of the innermost
+  // call's function that the debugger is called at, in the order `ast::Steps` finds them.
+  pub fn step(&self) -> Option<usize> {
+    self.step
+  }
+
+  fn count(&mut self) {
+    if let Some(tracer) = &mut self.tracer {
+      tracer.node();
     }
   }
 
-  // Define the `run` method of the `Runtime` struct.
-  pub fn run(&mut self, node: &Node) -> Result<Value, &'static str> {
-    // Match the type of the input `Node`.
-    match node {
-        // If the `Node` is a `Program`, evaluate each of its children in sequence.
-        Node::Program { children } => {
-            for n in children {
-                match n {
-                    // If the child node is a `FunctionDefine`, add it to the list of functions.
-                    Node::FunctionDefine { .. } => {
-                        let result = self.run(n);
-                        match &result {
-                            Err(_) => {
-                                return result;
-                            }
-                            _ => (),
-                        }
-                    },
-                    // If the child node is an `Expression`, add it as the body of a new `main` function.
-                    Node::Expression { .. } => {
-                        self.functions.insert("main".to_string(), vec![Node::FunctionReturn { children: vec![n.clone()] }]);
-                    },
-                    // If the child node is a `Statement`, add it as the body of a new `main` function.
-                    Node::Statement { .. } => {
-                        self.functions.insert("main".to_string(), vec![n.clone()]);
-                    }
-                    Node::IfExpression { .. } => {
-                        let result = self.run(n);
-                        match &result {
-                            Err(_) => {
-                                return result;
-                            }
-                            _ => (),
-                        }
-                    }
-                    // Ignore any other type of child node.
-                    _ => (),
-                }
-            }
-            // Return `Value::Bool(true)` wrapped in a `Result`.
-            Ok(Value::Bool(true))
-        },
-        // If the `Node` is a `MathExpression`, evaluate it.
-        Node::MathExpression { name, children } => {
-            // Evaluate the left and right children of the `MathExpression`.
-            match (self.run(&children[0]), self.run(&children[1])) {
-                // If both children are `Number` values, extract their values and evaluate the expression.
-                (Ok(Value::Number(lhs)), Ok(Value::Number(rhs))) => {
-                    match name.as_ref() {
-                        // If the operator is `+`, add the values.
-                        "+" => Ok(Value::Number(lhs + rhs)),
-                        // If the operator is `-`, subtract the values.
-                        "-" => Ok(Value::Number(lhs - rhs)),
-                        // If the operator is `*`, multiply the values.
-                        "*" => Ok(Value::Number(lhs * rhs)),
-                        // If the operator is `/`, divide the values.
-                        "/" => Ok(Value::Number(lhs / rhs)),
-                        // If the operator is `^`, raise the left value to the power of the right value.
-                        "^" => {
-                            let mut result = 1;
-                            for _i in 0..rhs {
-                                result = result * lhs;
-                            }
-                            Ok(Value::Number(result))
+  // Evaluate an expression outside of any function call.
+  pub fn eval(&mut self, expr: &Expr) -> Result<Value, &'static str> {
+    let (function, id) = lower::expression(expr, &mut self.symbols);
+    self.value(&function, id)
+  }
+
+  // Evaluate the condition of an if outside of any function call.
+  pub(crate) fn condition(&mut self, expr: &Expr) -> Result<Value, &'static str> {
+    let (function, id) = lower::expression(expr, &mut self.symbols);
+    self.test(&function, id)
+  }
+
+  // Evaluate an expression of `function` to its value.
+  fn value(&mut self, function: &Function, id: NodeId) -> Result<Value, &'static str> {
+    self.count();
+    match &function.code[id] {
+        // If the node is a literal, wrap its value in a `Value` and return it.
+        Node::Number(value) => Ok(Value::Number(*value)),
+        Node::Bool(value) => Ok(Value::Bool(*value)),
+        Node::String(value) => Ok(Value::String(value.clone())),
+        // If the node is a `Var`, look up its value in the current frame, then in the globals.
+        Node::Var(var) => self.variable(*var).cloned().ok_or("Undefined variable"),
+        // If the node is a comparison, check what it compares before evaluating it.
+        Node::Binary { op, lhs, rhs } if op.is_comparison() => self.compare(function, *op, *lhs, *rhs, false),
+        // If the node is math, evaluate both sides, stopping at the first error.
+        Node::Binary { op, lhs, rhs } => {
+            let lhs = self.value(function, *lhs)?;
+            let rhs = self.value(function, *rhs)?;
+            match (lhs, rhs) {
+                // If both sides are `Number` values, evaluate the expression.
+                (Value::Number(lhs), Value::Number(rhs)) => {
+                    let result = match op {
+                        BinaryOp::Add => lhs.checked_add(rhs),
+                        BinaryOp::Sub => lhs.checked_sub(rhs),
+                        BinaryOp::Mul => lhs.checked_mul(rhs),
+                        BinaryOp::Div if rhs == 0 => return Err("Division by zero"),
+                        BinaryOp::Div => lhs.checked_div(rhs),
+                        // Raise the left value to the power of the right value; a negative power is 1.
+                        BinaryOp::Pow => match u32::try_from(rhs) {
+                            Ok(rhs) => lhs.checked_pow(rhs),
+                            Err(_) => Some(1),
                         },
-                        // If the operator is not recognized, return an error message.
-                        _ => Err("Undefined operator"),
-                    }
-                }
-                // If either child is not a `Number` value, return an error message.
+                        _ => return Err("Undefined operator"),
+                    };
+                    // Overflowing the 32-bit range is an error rather than a wrapped result.
+                    result.map(Value::Number).ok_or("Number out of range")
+                },
+                // If either side is not a `Number` value, return an error message.
                 _ => Err("Cannot do math on String or Bool"),
             }
         },
-        // If the `Node` is a `FunctionCall`, evaluate it.
-        Node::FunctionCall { name, children } => {
-            // Extract the input arguments.
-            let in_args = if children.len() > 0 {
-                match &children[0] {
-                    Node::FunctionArguments { children } => {
-                        children
-                    },
-                    _ => children,
+        // If the node is a `Call`, evaluate the arguments in the caller's frame, then call the function with them.
+        Node::Call { callee, args } => {
+            // `p.dist()` calls the method `dist` on the struct in `p`.
+            if let Some((receiver, method)) = self.method(callee)? {
+                let method = self.symbols.get(&method).filter(|method| self.functions.contains_key(method)).ok_or("Undefined method")?;
+                let mut values = vec![receiver];
+                for arg in args {
+                    values.push(self.value(function, *arg)?);
                 }
-            } else {
-                children
-            };
-            // Create a new frame for local variables.
-            let mut new_frame = HashMap::new();
-            // Initialize the result to an error message.
-            let mut result: Result<Value, &'static str> = Err("Undefined function");
-            // Save a raw pointer to the `Runtime` instance for use in the nested closure.
-            let rt = self as *mut Runtime;
-            // Find the named function and evaluate its body.
-            match self.functions.get(name) {
-                Some(statements) => {
-                    {
-                        // If the function has input arguments, bind their values to the corresponding parameters.
-                        match statements[0].clone() {
-                            Node::FunctionArguments { children } => {
-                                for (ix, arg) in children.iter().enumerate() {
-                                    // Use unsafe Rust code to call `run` on the input argument and handle any errors.
-                                    unsafe {
-                                        let result = (*rt).run(&in_args[ix])?;
-                                        match arg {
-                                            Node::Expression { children } => {
-                                                match &children[0] {
-                                                    Node::Identifier { value } => {
-                                                        new_frame.insert(value.clone(), result);
-                                                    },
-                                                    _ => (),
-                                                }
-                                            }
-                                            _ => (),
-                                        }
-                                    }
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                    // Push the new frame onto the stack.
-                    self.stack.push(new_frame);
-                    // Evaluate each statement in the function body.
-                    for n in statements.clone() {
-                        result = self.run(&n);
-                    }
-                    // Pop the frame off the stack.
-                    self.stack.pop();
-                },
-                None => (),
-            };
-            // Return the result of evaluating the function.
-            result
+                return self.invoke(method, &values);
+            }
+            let name = self.resolve(callee);
+            if !self.functions.contains_key(&name) && !self.host_functions.contains_key(&name) {
+                return Err("Undefined function");
+            }
+            let mut values = vec![];
+            for arg in args {
+                values.push(self.value(function, *arg)?);
+            }
+            self.invoke(name, &values)
         },
-        // If the `Node` is a `FunctionDefine`, add it to the list of functions.
-        Node::FunctionDefine { children } => {
-            let (head, tail) = children.split_at(1);
-            match &head[0] {
-                Node::Identifier { value } => {
-                    self.functions.insert(value.to_string(), tail.to_vec());
-                },
-                _ => (),
+        // If the node is a `Struct`, check its fields against the struct's and build the value.
+        Node::Struct { name, fields } => {
+            let declared = self.structs.get(name).cloned().ok_or("Undefined struct")?;
+            let mut given = HashMap::new();
+            for (field, value) in fields {
+                if !declared.contains(field) {
+                    return Err("Unknown field");
+                }
+                let value = self.value(function, *value)?;
+                if given.insert(field.clone(), value).is_some() {
+                    return Err("Field given twice");
+                }
             }
-            Ok(Value::Bool(true))
+            let mut fields = vec![];
+            for field in declared {
+                let value = given.remove(&field).ok_or("Missing field")?;
+                fields.push((field, value));
+            }
+            Ok(Value::Struct { name: name.clone(), fields })
         },
-        // If the `Node` is a `FunctionReturn`, evaluate its child node.
-        Node::FunctionReturn { children } => {
-            self.run(&children[0])
+        // If the node is a `Field`, evaluate the struct and look up the field.
+        Node::Field { object, field } => {
+            let object = self.value(function, *object)?;
+            field_of(&object, field).cloned()
         },
-        // If the `Node` is an `Identifier`, look up its value in the current frame.
-        Node::Identifier { value } => {
-            let last = self.stack.len() - 1;
-            match self.stack[last].get(value) {
-                Some(id_value) => Ok(id_value.clone()),
-                None => Err("Undefined variable"),
+        // If the node is a `Variant`, check it against the enum and build the value.
+        Node::Variant { name, variant, args } => {
+            let variants = self.enums.get(name).ok_or("Undefined enum")?;
+            let arity = variants.iter().find(|(declared, _)| declared == variant).map(|(_, arity)| *arity).ok_or("Undefined variant")?;
+            if arity != args.len() {
+                return Err("Wrong number of arguments");
             }
-        },
-        // Final exam - If Expression
-        Node::IfExpression { children} => {
-            for c in children {
-                let result = self.run(c);
-                match result {
-                    Ok(Value::Bool(true)) => {
-                        return result;
-                    }
-                    Ok(Value::Bool(false)) => (),
-                    _ => return result,
-                }
+            let mut values = vec![];
+            for arg in args {
+                values.push(self.value(function, *arg)?);
             }
-            Ok(Value::Bool(true))
+            Ok(Value::Variant { name: name.clone(), variant: variant.clone(), values })
         },
-        // Evaluate it's condition; if true, run the statements
-        Node::IfBlock { condition, children } => {
-            let result = self.run(&condition[0]);
-            match result {
-                Ok(Value::Bool(true)) => {
-                    for c in children {
-                        match c {
-                            Node::Statement{ children: func_return } => {
-                                match func_return[0] {
-                                    Node::FunctionReturn{..} => {
-                                        return self.run(&func_return[0]);
-                                    }
-                                    _ => (),
-                                }
-                            }
-                            _ => (),
-                        }
-                        let result_2 = self.run(c);
-                        match &result_2 {
-                            Err(_) => {
-                                return result_2;
-                            }
-                            _ => (),
-                        }
-                    }
-                    return Ok(Value::Bool(true));
+        // If the node is a `Match`, evaluate the scrutinee, then the body of the first arm whose pattern
+        // matches and whose guard holds.
+        Node::Match { scrutinee, arms } => {
+            let value = self.value(function, *scrutinee)?;
+            for arm in arms {
+                let mut bindings = vec![];
+                if !arm.patterns.iter().any(|pattern| pattern_matches(pattern, &value, &mut bindings)) {
+                    continue;
                 }
-                Ok(Value::Bool(false)) => (),
-                _ => {
+                // The arm's bindings have slots of their own, which are emptied again once its guard
+                // fails or its body ends.
+                let slots: Vec<usize> = bindings.iter().map(|(slot, _)| *slot).collect();
+                self.bind(bindings);
+                let guard = match arm.guard {
+                    Some(guard) => self.test(function, guard),
+                    None => Ok(Value::Bool(true)),
+                };
+                let result = match guard {
+                    Ok(Value::Bool(true)) => Some(match &arm.body {
+                        ArmCode::Expr(expr) => self.value(function, *expr),
+                        ArmCode::Block(stmts) => self.run_block(function, stmts),
+                    }),
+                    Ok(Value::Bool(false)) => None,
+                    Ok(_) => Some(Err("Match guard must be a boolean")),
+                    Err(error) => Some(Err(error)),
+                };
+                self.unbind(&slots);
+                if let Some(result) = result {
                     return result;
                 }
             }
-            return Ok(Value::Bool(false));
+            Err("No match arm matched")
         },
-        // Evaluate it's condition; if true, run the statements
-        Node::ElseIfBlock { condition, children } => {
-            match self.run(&condition[0]) {
-                Ok(Value::Bool(true)) => {
-                    for c in children {
-                        match c {
-                            Node::Statement{ children: func_return } => {
-                                match func_return[0] {
-                                    Node::FunctionReturn{..} => {
-                                        return self.run(&func_return[0]);
-                                    }
-                                    _ => (),
-                                }
-                            }
-                            _ => (),
-                        }
-                        let result = self.run(c);
-                        match &result {
-                            Err(_) => {
-                                return result;
-                            }
-                            _ => (),
-                        }
-                    }
-                    return Ok(Value::Bool(true));
-                }
-                Ok(Value::Bool(false)) => (),
-                _ => {
-                    return Err("Not a boolean value");
-                }
-            }
-            return Ok(Value::Bool(false));
+        // If the node is a `Throw`, evaluate the value and raise it.
+        Node::Throw(value) => {
+            let value = self.value(function, *value)?;
+            self.thrown = Some(value);
+            Err(THROWN)
         },
-        // No condition; run statements
-        Node::ElseBlock { children } => {
-            for c in children {
-                match c {
-                    Node::Statement{ children: func_return } => {
-                        match func_return[0] {
-                            Node::FunctionReturn{..} => {
-                                return self.run(&func_return[0]);
-                            }
-                            _ => (),
-                        }
-                    }
-                    _ => (),
-                }
-                let result = self.run(c);
-                match &result {
-                    Err(_) => {
-                        return result;
-                    }
-                    _ => (),
-                }
+        // If the node is an `IfLet`, match the value against the pattern, binding its variables.
+        Node::IfLet { pattern, value } => {
+            let value = self.value(function, *value)?;
+            let mut bindings = vec![];
+            if !pattern_matches(pattern, &value, &mut bindings) {
+                return Ok(Value::Bool(false));
             }
+            self.bind(bindings);
             Ok(Value::Bool(true))
         },
-        // If the `Node` is a `Statement`, evaluate its child node.
-        Node::Statement { children } => {
-            match &children[0] {
-                Node::VariableDefine { .. } |
-                Node::FunctionReturn { .. } | 
-                Node::FunctionCall {..} => { self.run(&children[0]) },
-                _ => Err("Unknown Statement"),
+        // Anything else is a statement.
+        _ => self.exec(function, id),
+    }
+  }
+
+  // Evaluate the condition of an if or a match guard, where `==` and `!=` may also compare to a bool or
+  // string.
+  fn test(&mut self, function: &Function, id: NodeId) -> Result<Value, &'static str> {
+    match &function.code[id] {
+      Node::Binary { op, lhs, rhs } if op.is_comparison() => {
+        self.count();
+        self.compare(function, *op, *lhs, *rhs, true)
+      },
+      _ => self.value(function, id),
+    }
+  }
+
+  // Only numbers, variables, fields and math are compared; anything else on either side is an error.
+  fn compare(&mut self, function: &Function, op: BinaryOp, lhs: NodeId, rhs: NodeId, condition: bool) -> Result<Value, &'static str> {
+    if !matches!(function.code[lhs], Node::Number(_) | Node::Var(_) | Node::Field { .. } | Node::Binary { .. }) {
+      return Err("Invalid expression");
+    }
+    let comparable = match &function.code[rhs] {
+      Node::Number(_) | Node::Var(_) | Node::Field { .. } | Node::Variant { .. } => true,
+      Node::Binary { op, .. } => !op.is_comparison(),
+      Node::Bool(_) | Node::String(_) => condition && matches!(op, BinaryOp::Eq | BinaryOp::Ne),
+      _ => false,
+    };
+    if !comparable {
+      return Err("Invalid expression - can only compare numbers to numbers");
+    }
+    let lhs = self.value(function, lhs);
+    let rhs = self.value(function, rhs);
+    let (lhs, rhs) = match (op, lhs, rhs) {
+      (BinaryOp::Eq, lhs, rhs) => return Ok(Value::Bool(lhs == rhs)),
+      (BinaryOp::Ne, lhs, rhs) => return Ok(Value::Bool(lhs != rhs)),
+      (_, Ok(Value::Number(lhs)), Ok(Value::Number(rhs))) => (lhs, rhs),
+      (BinaryOp::Gt, ..) => return Err("Unsuccesful interpreting > comparison"),
+      (BinaryOp::Lt, ..) => return Err("Unsuccesful interpreting < comparison"),
+      (BinaryOp::Ge, ..) => return Err("Unsuccesful interpreting >= comparison"),
+      (BinaryOp::Le, ..) => return Err("Unsuccesful interpreting <= comparison"),
+      _ => return Err("Invalid operator"),
+    };
+    match op {
+      BinaryOp::Gt => Ok(Value::Bool(lhs > rhs)),
+      BinaryOp::Lt => Ok(Value::Bool(lhs < rhs)),
+      BinaryOp::Ge => Ok(Value::Bool(lhs >= rhs)),
+      BinaryOp::Le => Ok(Value::Bool(lhs <= rhs)),
+      _ => Err("Invalid operator"),
+    }
+  }
+
+  // Run a statement of `function`, giving the debugger a look first.
+  fn exec(&mut self, function: &Function, id: NodeId) -> Result<Value, &'static str> {
+    let node = &function.code[id];
+    if node.is_step() {
+      if let Some(error) = self.halted {
+        return Err(error);
+      }
+      if self.debugger.is_some() {
+        self.debug(function, id)?;
+      }
+    }
+    match node {
+        // If the statement is a `Let`, evaluate its value and bind it in the current frame.
+        Node::Let { var, value } => {
+            self.count();
+            let value = self.value(function, *value)?;
+            match (var.slot, self.stack.last_mut()) {
+                (Some(slot), Some(frame)) => frame.slots[slot] = Some(value.clone()),
+                _ => {
+                    self.globals.insert(self.symbols.name(var.name).to_string(), value.clone());
+                },
             }
+            Ok(value)
         },
-        // If the `Node` is a `VariableDefine`, evaluate its expression and bind the result to a new variable.
-        Node::VariableDefine { children } => {
-            // Extract the variable name.
-            let name: String = match &children[0] {
-                Node::Identifier { value } => value.clone(),
-                _ => "".to_string(),
+        // If the statement is an `Assign`, update the field of the variable the target starts from.
+        Node::Assign { target, value } => {
+            self.count();
+            let value = self.value(function, *value)?;
+            let mut fields = vec![];
+            let mut target = &function.code[*target];
+            while let Node::Field { object, field } = target {
+                fields.push(field.as_str());
+                target = &function.code[*object];
+            }
+            fields.reverse();
+            let var = match target {
+                Node::Var(var) => *var,
+                _ => return Err("Can only assign to fields of a variable"),
+            };
+            let slot = match (var.slot, self.stack.last_mut()) {
+                (Some(slot), Some(frame)) => frame.slots[slot].as_mut(),
+                _ => None,
             };
-            // Evaluate the expression.
-            let value = self.run(&children[1])?;
-            // Add the variable to the current frame.
-            let last = self.stack.len() - 1;
-            self.stack[last].insert(name, value.clone());
-            // Return the value.
+            let object = match slot {
+                Some(object) => Some(object),
+                None => self.globals.get_mut(self.symbols.name(var.name)),
+            };
+            set_field(object.ok_or("Undefined variable")?, &fields, value.clone())?;
             Ok(value)
         },
-        // If the `Node` is an `Expression`, evaluate its child node.
-        Node::Expression { children } => {
-            match &children[0] {
-                Node::MathExpression { .. } |
-                Node::Number { .. } |
-                Node::FunctionCall { .. } |
-                Node::String { .. } |
-                Node::Bool { .. } |
-                Node::Identifier { .. } => {
-                    self.run(&children[0])
-                },
-                Node::ComparisonOperator { operator, children } => { // Handles comparison operators in VarDefs.
-                    match operator.as_ref() {
-                        "==" => {
-                            let node_1 = &children[0];
-                            let node_2 = &children[1];
-                            match &node_1 {
-                                Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                                    match &node_2 {
-                                        Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                            Ok(Value::Bool(self.run(&node_1) == self.run(&node_2)))
-                                        },
-                                        _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                                    }
-                                }
-                                _ => {
-                                    Err("Invalid expression")
-                                }
-                            }
-                        },
-                        "!=" => {
-                            let node_1 = &children[0];
-                            let node_2 = &children[1];
-                            match &node_1 {
-                                Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                                    match &node_2 {
-                                        Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                            Ok(Value::Bool(self.run(&node_1) != self.run(&node_2)))
-                                        },
-                                        _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                                    }
-                                }
-                                _ => {
-                                    Err("Invalid expression")
-                                }
-                            }
-                        },
-                        ">" => {
-                            let node_1 = &children[0];
-                            let node_2 = &children[1];
-                            match &node_1 {
-                                Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                                    match &node_2 {
-                                        Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                            match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                                (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                                    Ok(Value::Bool(val_1 > val_2))
-                                                }
-                                                _ => Err("Unsuccesful interpreting > comparison"),
-                                            }
-                                        },
-                                        _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                                    }
-                                }
-                                _ => {
-                                    Err("Invalid expression")
-                                }
-                            }
-                        },
-                        "<" => {
-                            let node_1 = &children[0];
-                            let node_2 = &children[1];
-                            match &node_1 {
-                                Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                                    match &node_2 {
-                                        Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                            match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                                (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                                    Ok(Value::Bool(val_1 < val_2))
-                                                }
-                                                _ => Err("Unsuccesful interpreting < comparison"),
-                                            }
-                                        },
-                                        _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                                    }
-                                }
-                                _ => {
-                                    Err("Invalid expression3")
-                                }
-                            }
-                        },
-                        ">=" => {
-                            let node_1 = &children[0];
-                            let node_2 = &children[1];
-                            match &node_1 {
-                                Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                                    match &node_2 {
-                                        Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                            match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                                (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                                    Ok(Value::Bool(val_1 >= val_2))
-                                                }
-                                                _ => Err("Unsuccesful interpreting >= comparison"),
-                                            }
-                                        },
-                                        _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                                    }
-                                }
-                                _ => {
-                                    Err("Invalid expression")
-                                }
-                            }
-                        }
-                        "<=" => {
-                            let node_1 = &children[0];
-                            let node_2 = &children[1];
-                            match &node_1 {
-                                Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} | Node::Identifier{..} => {
-                                    match &node_2 {
-                                        Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                            match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                                (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                                    Ok(Value::Bool(val_1 <= val_2))
-                                                }
-                                                _ => Err("Unsuccesful interpreting <= comparison"),
-                                            }
-                                        },
-                                        _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                                    }
-                                }
-                                _ => {
-                                    Err("Invalid expression")
-                                }
-                            }
-                        }
-                        _ => {
-                            return Err("Invalid operator");
-                        }
-                    }
-                }
-                _ => Err("Unknown Expression"),
-            }
+        Node::Return(value) | Node::Expr(value) => {
+            self.count();
+            self.value(function, *value)
         },
-        Node::ComparisonOperator { operator, children } => { // Handles comparison operators in if statements
-            match operator.as_ref() {
-                "==" => {
-                    let node_1 = &children[0];
-                    let node_2 = &children[1];
-                    match &node_1 {
-                        Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} | Node::Identifier{..} => {
-                            match &node_2 {
-                                Node::Bool{..} | Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                    Ok(Value::Bool(self.run(&node_1) == self.run(&node_2)))
-                                },
-                                _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                            }
-                        }
-                        _ => {
-                            Err("Invalid expression")
-                        }
-                    }
-                },
-                "!=" => {
-                    let node_1 = &children[0];
-                    let node_2 = &children[1];
-                    match &node_1 {
-                        Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                            match &node_2 {
-                                Node::Bool{..} | Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                    Ok(Value::Bool(self.run(&node_1) != self.run(&node_2)))
-                                },
-                                _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                            }
-                        }
-                        _ => {
-                            Err("Invalid expression3")
-                        }
-                    }
-                },
-                ">" => {
-                    let node_1 = &children[0];
-                    let node_2 = &children[1];
-                    match &node_1 {
-                        Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                            match &node_2 {
-                                Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                    match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                        (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                            Ok(Value::Bool(val_1 > val_2))
-                                        }
-                                        _ => Err("Unsuccesful interpreting > comparison"),
-                                    }
-                                },
-                                _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                            }
-                        }
-                        _ => {
-                            Err("Invalid expression3")
-                        }
-                    }
-                },
-                "<" => {
-                    let node_1 = &children[0];
-                    let node_2 = &children[1];
-                    match &node_1 {
-                        Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                            match &node_2 {
-                                Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                    match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                        (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                            Ok(Value::Bool(val_1 < val_2))
-                                        }
-                                        _ => Err("Unsuccesful interpreting < comparison"),
-                                    }
-                                },
-                                _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                            }
-                        }
-                        _ => {
-                            Err("Invalid expression")
-                        }
-                    }
-                },
-                ">=" => {
-                    let node_1 = &children[0];
-                    let node_2 = &children[1];
-                    match &node_1 {
-                        Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                            match &node_2 {
-                                Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                    match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                        (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                            Ok(Value::Bool(val_1 >= val_2))
-                                        }
-                                        _ => Err("Unsuccesful interpreting >= comparison"),
-                                    }
-                                },
-                                _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                            }
-                        }
-                        _ => {
-                            Err("Invalid expression")
-                        }
-                    }
-                }
-                "<=" => {
-                    let node_1 = &children[0];
-                    let node_2 = &children[1];
-                    match &node_1 {
-                        Node::Number{..} | Node::MathExpression{..} | Node::Expression{..} => {
-                            match &node_2 {
-                                Node::Number{..} | Node::Identifier {..} | Node::MathExpression {..} => {
-                                    match (self.run(&node_1), self.run(&node_2)) { // Gets the i32 values from the Number nodes
-                                        (Ok(Value::Number(val_1)), Ok(Value::Number(val_2))) => {
-                                            Ok(Value::Bool(val_1 <= val_2))
-                                        }
-                                        _ => Err("Unsuccesful interpreting <= comparison"),
-                                    }
-                                },
-                                _ => {Err("Invalid expression - can only compare numbers to numbers")}
-                            }
-                        }
-                        _ => {
-                            Err("Invalid expression")
-                        }
+        // If the statement is an `If`, run the first arm whose condition holds. An arm that returns `false`
+        // lets the arms after it be tried too.
+        Node::If { arms } => {
+            self.count();
+            for (ix, (condition, body)) in arms.iter().enumerate() {
+                if let Some(condition) = condition {
+                    match self.test(function, *condition) {
+                        Ok(Value::Bool(true)) => (),
+                        Ok(Value::Bool(false)) => continue,
+                        result if ix == 0 => return result,
+                        _ => return Err("Not a boolean value"),
                     }
                 }
-                _ => {
-                    return Err("Invalid operator");
+                match self.run_arm(function, body) {
+                    Ok(Value::Bool(false)) => (),
+                    result => return result,
                 }
             }
+            Ok(Value::Bool(true))
         },
-        // If the `Node` is a `Number`, wrap its value in a `Value::Number` and return it.
-        Node::Number { value } => {
-            Ok(Value::Number(*value))
+        // If the statement is a `Try`, run the block; if it fails, bind the error and run the handler.
+        Node::Try { body, slot, handler } => {
+            self.count();
+            let error = match self.run_block(function, body) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            // Errors that stop the whole program, like `exit` or quitting the debugger, can't be caught.
+            if self.halted.is_some() {
+                return Err(error);
+            }
+            let value = match self.thrown.take() {
+                Some(value) if error == THROWN => value,
+                _ => error_value(error),
+            };
+            self.error_stack.clear();
+            self.bind(vec![(*slot, value)]);
+            self.run_block(function, handler)
         },
-        // If the `Node` is a `String`, wrap its value in a `Value::String` and return it.
-        Node::String { value } => {
-            Ok(Value::String(value.clone()))
+        // A match or throw statement is evaluated like the expression.
+        _ => self.value(function, id),
+    }
+  }
+
+  // Hand the debugger the statement about to run.
+  fn debug(&mut self, function: &Function, id: NodeId) -> Result<(), &'static str> {
+    let step = match function.step(id) {
+      Some(step) => step,
+      None => return Ok(()),
+    };
+    let mut debugger = match self.debugger.take() {
+      Some(debugger) => debugger,
+      None => return Ok(()),
+    };
+    self.step = Some(step);
+    let result = debugger.before_statement(self, function.statement(step));
+    self.debugger = Some(debugger);
+    if let Err(error) = result {
+      self.halted = Some(error);
+      return Err(error);
+    }
+    Ok(())
+  }
+
+  // Run the statements of an if arm, stopping at the first `return`, whose value is the arm's.
+  fn run_arm(&mut self, function: &Function, body: &[NodeId]) -> Result<Value, &'static str> {
+    for stmt in body {
+      if let Node::Return(_) = function.code[*stmt] {
+        return self.exec(function, *stmt);
+      }
+      self.exec(function, *stmt)?;
+    }
+    Ok(Value::Bool(true))
+  }
+
+  // Run a block of statements, returning the value of the first `return` or else of the last statement.
+  fn run_block(&mut self, function: &Function, body: &[NodeId]) -> Result<Value, &'static str> {
+    let mut result = Value::Bool(true);
+    for stmt in body {
+      if let Node::Return(_) = function.code[*stmt] {
+        return self.exec(function, *stmt);
+      }
+      result = self.exec(function, *stmt)?;
+    }
+    Ok(result)
+  }
+
+  // Run a program: define its functions, then call `main`.
+  pub fn start(&mut self, program: &Program) -> Result<Value, &'static str> {
+    let result = self.load(program);
+    match result {
+      Err(_) | Ok(_) => (),
+    }
+    self.call("main", &[])
+  }
+
+  // Define the functions of a program without calling any of them. A top-level statement or expression
+  // becomes the body of `main`, as with `start`; a top-level if runs right away. Two tests with the same
+  // name are an error, since `run_test` couldn't tell them apart.
+  pub fn load(&mut self, program: &Program) -> Result<(), &'static str> {
+    let mut tests = vec![];
+    for item in &program.items {
+      self.count();
+      match item {
+        Item::Fn(function) => self.define(&function.name, Rc::new(function.clone())),
+        Item::Struct { name, fields } => {
+          self.structs.insert(name.clone(), fields.clone());
         },
-        // If the `Node` is a `Bool`, wrap its value in a `Value::Bool` and return it.
-        Node::Bool { value } => {
-            Ok(Value::Bool(*value))
+        Item::Enum { name, variants } => {
+          let variants = variants.iter().map(|variant| (variant.name.clone(), variant.fields.len())).collect();
+          self.enums.insert(name.clone(), variants);
         },
-        // If the `Node` is of an unhandled type, return an error message.
-        _ => {
-            Err("Unhandled Node")
+        // Methods are functions named `Struct::method`.
+        Item::Impl { name, methods } => {
+          for method in methods {
+            self.define(&format!("{}::{}", name, method.name), Rc::new(method.clone()));
+          }
         },
+        Item::Expr(expr) => self.define_main(vec![Stmt::Return(expr.clone())]),
+        Item::Stmt(stmt @ Stmt::If(_)) => {
+          let decl = FnDecl { doc: String::new(), name: String::new(), params: vec![], body: vec![stmt.clone()] };
+          let function = lower::function("", Rc::new(decl), &mut self.symbols);
+          self.exec(&function, function.body[0])?;
+        },
+        Item::Stmt(stmt) => self.define_main(vec![stmt.clone()]),
+        // Tests only run through `run_test`.
+        Item::Test { name, .. } if tests.contains(&name) => return Err("Duplicate test name"),
+        Item::Test { name, .. } => tests.push(name),
+        Item::Import { .. } => (),
+      }
     }
+    Ok(())
+  }
+
+  // Run the body of the test called `name` in `program`, which should be loaded, as a function of its
+  // own.
+  pub fn run_test(&mut self, program: &Program, name: &str) -> Result<Value, &'static str> {
+    let body = program.items.iter().find_map(|item| match item {
+      Item::Test { name: test, body } if test == name => Some(body),
+      _ => None,
+    });
+    let function = test_function(name);
+    let decl = FnDecl { doc: String::new(), name: function.clone(), params: vec![], body: body.ok_or("Undefined test")?.clone() };
+    self.define(&function, Rc::new(decl));
+    self.call(&function, &[])
+  }
+
+  fn define(&mut self, name: &str, decl: Rc<FnDecl>) {
+    let function = lower::function(name, decl, &mut self.symbols);
+    let name = self.symbols.intern(name);
+    self.functions.insert(name, Rc::new(function));
+  }
+
+  fn define_main(&mut self, body: Vec<Stmt>) {
+    self.define("main", Rc::new(FnDecl { doc: String::new(), name: "main".to_string(), params: vec![], body }));
+  }
+
+  // Load the modules `program` imports, and the modules they import, resolving paths against `path`, the
+  // file the program was read from. A module's functions are defined under its name, `utils.helper`.
+  // Each file is parsed once per runtime however often it is imported.
+  pub fn load_imports(&mut self, program: &Program, path: &Path) -> Result<(), ModuleError> {
+    let mut loading = vec![std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())];
+    self.import(&modules::imports(program, path), "", &mut loading)
   }
-}
 
-pub fn start_interpreter(node: &Node) -> Result<Value, &'static str> {
-  let mut runtime = Runtime::new();
-  let result = runtime.run(node);
-  match result {
-    Err(_) | Ok(_) => (),
-  }
-  let start_main = Node::FunctionCall{name: "main".to_string(), children: vec![]};
-  let result = runtime.run(&start_main);
-  match &result {
-    Err(_) | Ok(_) => {
-        return result;
+  // Run a program read from `path` the way `asa run` does: load its imports, then call `main`.
+  pub fn run_file(&mut self, program: &Program, path: &Path) -> Result<Value, RunError> {
+    self.load_imports(program, path).map_err(RunError::Module)?;
+    self.start(program).map_err(|error| {
+      let mut report = match &self.thrown {
+        Some(value) if error == THROWN => format!("uncaught error: {}", value),
+        _ => format!("runtime error: {}", error),
+      };
+      for function in self.error_stack.iter().rev() {
+        report += &format!("\n  in {}", function);
+      }
+      RunError::Runtime(report)
+    })
+  }
+
+  // Define the functions of `imports` under `prefix`. `loading` is the chain of modules being imported.
+  fn import(&mut self, imports: &[Import], prefix: &str, loading: &mut Vec<PathBuf>) -> Result<(), ModuleError> {
+    for import in imports {
+      let path = std::fs::canonicalize(&import.path).map_err(|_| ModuleError::Read(import.path.clone()))?;
+      if let Some(ix) = loading.iter().position(|loaded| *loaded == path) {
+        let mut cycle = loading[ix..].to_vec();
+        cycle.push(path);
+        return Err(ModuleError::Cycle(cycle));
+      }
+      let module = match self.modules.get(&path) {
+        Some(module) => module.clone(),
+        None => {
+          let module = Rc::new(modules::parse(&path)?);
+          self.modules.insert(path.clone(), module.clone());
+          module
+        },
+      };
+      let prefix = format!("{}{}.", prefix, import.name);
+      for (name, decl) in &module.functions {
+        self.define(&format!("{}{}", prefix, name), decl.clone());
+      }
+      loading.push(path);
+      self.import(&module.imports, &prefix, loading)?;
+      loading.pop();
+    }
+    Ok(())
+  }
+
+  // A variable of the current call, or else a global.
+  fn variable(&self, var: Var) -> Option<&Value> {
+    let local = match (var.slot, self.stack.last()) {
+      (Some(slot), Some(frame)) => frame.slots[slot].as_ref(),
+      _ => None,
+    };
+    local.or_else(|| self.globals.get(self.symbols.name(var.name)))
+  }
+
+  // Bind the variables of a pattern in the current call. Outside of any call they're dropped.
+  fn bind(&mut self, bindings: Vec<(usize, Value)>) {
+    if let Some(frame) = self.stack.last_mut() {
+      for (slot, value) in bindings {
+        frame.slots[slot] = Some(value);
+      }
+    }
+  }
+
+  fn unbind(&mut self, slots: &[usize]) {
+    if let Some(frame) = self.stack.last_mut() {
+      for slot in slots {
+        frame.slots[*slot] = None;
+      }
     }
   }
+
+  // For a call like `p.dist()` or `line.start.dist()` where `p` and `line` are variables, the struct
+  // the method is called on and the method's function name, `Point::dist`.
+  fn method(&self, callee: &Callee) -> Result<Option<(Value, String)>, &'static str> {
+    let path = match &callee.method {
+      Some(path) => path,
+      None => return Ok(None),
+    };
+    let mut receiver = match self.variable(path.receiver) {
+      Some(value) => value,
+      None => return Ok(None),
+    };
+    for field in &path.fields {
+      receiver = field_of(receiver, field)?;
+    }
+    match receiver {
+      Value::Struct { name, .. } => Ok(Some((receiver.clone(), format!("{}::{}", name, path.method)))),
+      _ => Err("Not a struct"),
+    }
+  }
+
+  // The function a call means. In a module, names refer to the module's own functions, then to host
+  // functions.
+  fn resolve(&self, callee: &Callee) -> Symbol {
+    match callee.qualified {
+      Some(qualified) if self.functions.contains_key(&qualified) || !self.host_functions.contains_key(&callee.name) => qualified,
+      _ => callee.name,
+    }
+  }
+
+  // Where the last uncaught error was raised: the function calls active then, outermost first.
+  pub fn error_stack(&self) -> &[String] {
+    &self.error_stack
+  }
+
+  // The value of the last `throw` nothing caught, which stopped the program with `THROWN`.
+  pub fn thrown(&self) -> Option<&Value> {
+    self.thrown.as_ref()
+  }
+
+  // For host functions that throw a value to the script: put it in here and return `Err(THROWN)`.
+  pub fn thrower(&self) -> Rc<RefCell<Option<Value>>> {
+    self.host_thrown.clone()
+  }
+
+  // Call a script or host function with already evaluated arguments.
+  pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, &'static str> {
+    let name = self.symbols.intern(name);
+    self.invoke(name, args)
+  }
+
+  fn invoke(&mut self, name: Symbol, args: &[Value]) -> Result<Value, &'static str> {
+    if self.calls.is_empty() {
+      self.thrown = None;
+      self.error_stack.clear();
+    }
+    let function = match self.functions.get(&name).cloned() {
+      Some(function) => function,
+      None => return match self.host_functions.get(&name).cloned() {
+        Some(host) => {
+          self.enter(name);
+          let result = host.call(args);
+          if result == Err(THROWN) {
+            self.thrown = self.host_thrown.borrow_mut().take();
+          }
+          if result.is_err() {
+            self.raised();
+          }
+          self.leave();
+          if result == Err(EXITED) {
+            self.halted = Some(EXITED);
+          }
+          result
+        },
+        None => Err("Undefined function"),
+      },
+    };
+    // Bind the arguments to the function's parameters in a new frame.
+    if function.params.len() != args.len() {
+      return Err("Wrong number of arguments");
+    }
+    if self.stack.len() >= MAX_CALL_DEPTH {
+      return Err("Call stack too deep");
+    }
+    let mut slots = vec![None; function.slots.len()];
+    for (slot, arg) in function.params.iter().zip(args) {
+      slots[*slot] = Some(arg.clone());
+    }
+    self.stack.push(Frame { function: function.clone(), slots });
+    self.enter(name);
+    // Evaluate each statement in the function body; the function's value is that of the last one. An
+    // error stops the function.
+    let mut result: Result<Value, &'static str> = Err("Undefined function");
+    for stmt in &function.body {
+      result = self.exec(&function, *stmt);
+      if result.is_err() {
+        self.raised();
+        break;
+      }
+    }
+    self.leave();
+    self.stack.pop();
+    result
+  }
+
+  // Note the active calls when an error is first seen, on its way out of the innermost one.
+  fn raised(&mut self) {
+    if self.error_stack.is_empty() {
+      self.error_stack = self.backtrace();
+    }
+  }
+
+  fn enter(&mut self, name: Symbol) {
+    self.calls.push(name);
+    if let Some(tracer) = &mut self.tracer {
+      tracer.enter(self.symbols.name(name));
+    }
+  }
+
+  fn leave(&mut self) {
+    if let Some(tracer) = &mut self.tracer {
+      tracer.exit();
+    }
+    self.calls.pop();
+  }
+
+  // Every function that can be called, with its parameters, sorted by name. A script function hides a
+  // host function of the same name.
+  pub fn functions(&self) -> Vec<FunctionInfo> {
+    let mut functions: Vec<FunctionInfo> = self.host_functions.keys().chain(self.functions.keys())
+      .filter_map(|name| self.function(self.symbols.name(*name)))
+      .collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    functions.dedup_by(|a, b| a.name == b.name);
+    functions
+  }
+
+  pub fn function(&self, name: &str) -> Option<FunctionInfo> {
+    let symbol = self.symbols.get(name)?;
+    if let Some(function) = self.functions.get(&symbol) {
+      let params = function.decl.params.clone();
+      return Some(FunctionInfo { name: name.to_string(), arity: Arity::Exact(params.len()), params, host: false });
+    }
+    self.host_functions.get(&symbol).map(|host| FunctionInfo { name: name.to_string(), params: vec![], arity: host.arity, host: true })
+  }
+
+  // Globals are visible to every function, behind its own variables.
+  pub fn globals(&self) -> &HashMap<String, Value> {
+    &self.globals
+  }
+
+  pub fn global(&self, name: &str) -> Option<&Value> {
+    self.globals.get(name)
+  }
+
+  pub fn set_global(&mut self, name: &str, value: Value) {
+    self.globals.insert(name.to_string(), value);
+  }
+
+}
+
+// A runtime error as the `Error` value `catch` binds, with a kind grouping similar errors.
+fn error_value(message: &'static str) -> Value {
+  let kind = match message {
+    "Division by zero" | "Number out of range" => "ArithmeticError",
+    "Wrong number of arguments" => "ArgumentError",
+    "No match arm matched" => "MatchError",
+    _ if message.starts_with("Undefined") => "NameError",
+    _ if message.starts_with("Could not") => "IoError",
+    _ if message.starts_with("Expected") || message.starts_with("Cannot") || message.starts_with("Not a") => "TypeError",
+    _ => "RuntimeError",
+  };
+  error(kind, message)
+}
+
+// An `Error { kind, message }` value.
+pub(crate) fn error(kind: &str, message: &str) -> Value {
+  Value::Struct { name: "Error".to_string(), fields: vec![
+    ("kind".to_string(), Value::String(kind.to_string())),
+    ("message".to_string(), Value::String(message.to_string())),
+  ] }
+}
+
+fn field_of<'a>(object: &'a Value, field: &str) -> Result<&'a Value, &'static str> {
+  match object {
+    Value::Struct { fields, .. } => fields.iter().find(|(name, _)| name == field).map(|(_, value)| value).ok_or("Undefined field"),
+    _ => Err("Not a struct"),
+  }
+}
+
+// Replace the field at the end of `path` inside `object`.
+fn set_field(object: &mut Value, path: &[&str], value: Value) -> Result<(), &'static str> {
+  let (field, rest) = match path.split_first() {
+    Some(split) => split,
+    None => return Err("Can only assign to fields of a variable"),
+  };
+  let slot = match object {
+    Value::Struct { fields, .. } => fields.iter_mut().find(|(name, _)| name == field).map(|(_, value)| value).ok_or("Undefined field")?,
+    _ => return Err("Not a struct"),
+  };
+  match rest.is_empty() {
+    true => {
+      *slot = value;
+      Ok(())
+    },
+    false => set_field(slot, rest, value),
+  }
+}
+
+// Check a value against one match pattern, recording any identifier it binds.
+fn pattern_matches(pattern: &Pat, value: &Value, bindings: &mut Vec<(usize, Value)>) -> bool {
+  match pattern {
+    Pat::Wildcard => true,
+    Pat::Binding(slot) => {
+      bindings.push((*slot, value.clone()));
+      true
+    },
+    Pat::Number(n) => *value == Value::Number(*n),
+    Pat::Bool(b) => *value == Value::Bool(*b),
+    Pat::String(s) => *value == Value::String(s.clone()),
+    Pat::Variant { name, variant, fields } => match value {
+      Value::Variant { name: value_name, variant: value_variant, values } => {
+        if name != value_name || variant != value_variant || fields.len() != values.len() {
+          return false;
+        }
+        // Only keep the bindings if every sub-pattern matches.
+        let mut inner = vec![];
+        if !fields.iter().zip(values).all(|(pattern, value)| pattern_matches(pattern, value, &mut inner)) {
+          return false;
+        }
+        bindings.extend(inner);
+        true
+      },
+      _ => false,
+    },
+  }
+}
+
+pub fn start_interpreter(program: &Program) -> Result<Value, &'static str> {
+  Runtime::new().start(program)
 }
\ No newline at end of file
@@ -1,7 +1,27 @@
 extern crate nom;
 
+pub mod ast;
+pub mod codegen_c;
+pub mod codegen_wasm;
+pub mod debugger;
+pub mod formatter;
+pub mod host;
 pub mod interpreter;
+pub mod lower;
+pub mod lsp;
+pub mod modules;
+pub mod optimize;
 pub mod parser;
+pub mod recovery;
+pub mod stdlib;
+pub mod tracer;
 
-pub use self::parser::{program, Node};
-pub use self::interpreter::{start_interpreter, Runtime, Value};
\ No newline at end of file
+pub use self::ast::{Expr, FnDecl, Item, Program, Stmt};
+pub use self::parser::{program, warnings};
+pub use self::interpreter::{start_interpreter, Debugger, RunError, Runtime, Value};
+pub use self::recovery::{program_with_recovery, statement_lines, SyntaxError};
+pub use self::formatter::{format_program, format_source, FormatError};
+pub use self::tracer::Tracer;
+pub use self::host::{Arity, FromValue, IntoValue, RuntimeError};
+pub use self::modules::ModuleError;
+pub use self::optimize::optimize;
\ No newline at end of file
@@ -0,0 +1,404 @@
+// Lowering: the form the runtime runs a function in.
+//
+// The syntax tree of a function is copied into an arena, `Code`, whose nodes refer to each other by
+// `NodeId`. Names are interned into `Symbols`, so looking up a function hashes a number rather than a
+// string. Every variable a function binds (its parameters, `let`s, pattern bindings and `catch`
+// variables) gets a slot in its frame ahead of time, and reading one is an index into the frame; the
+// bindings of a match arm get slots of their own. Names the function never binds are looked up among
+// the globals.
+
+use crate::ast::{walk_expr, walk_stmts, ArmBody, BinaryOp, Expr, FnDecl, Match, Pattern, Steps, Stmt, Visitor};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::ops::Index;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// Every name the runtime has seen, each stored once.
+#[derive(Debug, Default)]
+pub struct Symbols {
+  names: Vec<String>,
+  ids: HashMap<String, Symbol>,
+}
+
+impl Symbols {
+  pub fn intern(&mut self, name: &str) -> Symbol {
+    if let Some(symbol) = self.ids.get(name) {
+      return *symbol;
+    }
+    let symbol = Symbol(self.names.len() as u32);
+    self.names.push(name.to_string());
+    self.ids.insert(name.to_string(), symbol);
+    symbol
+  }
+
+  // The symbol of a name, if it has been interned.
+  pub fn get(&self, name: &str) -> Option<Symbol> {
+    self.ids.get(name).copied()
+  }
+
+  pub fn name(&self, symbol: Symbol) -> &str {
+    &self.names[symbol.0 as usize]
+  }
+}
+
+// A variable: its slot in the frame, if the function binds it, and its name to look up among the
+// globals when the slot is empty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Var {
+  pub slot: Option<usize>,
+  pub name: Symbol,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Callee {
+  pub name: Symbol,
+  // In a module, the module's function of that name, `utils.helper`, which comes before a host function.
+  pub qualified: Option<Symbol>,
+  // For a name with dots, `p.dist` or `line.start.dist`: a method call, if `p` is a variable.
+  pub method: Option<MethodPath>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodPath {
+  pub receiver: Var,
+  pub fields: Vec<String>,
+  pub method: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+  Number(i32),
+  Bool(bool),
+  String(String),
+  Var(Var),
+  Binary { op: BinaryOp, lhs: NodeId, rhs: NodeId },
+  Call { callee: Callee, args: Vec<NodeId> },
+  Struct { name: String, fields: Vec<(String, NodeId)> },
+  Field { object: NodeId, field: String },
+  Variant { name: String, variant: String, args: Vec<NodeId> },
+  // Both the expression and the statement.
+  Match { scrutinee: NodeId, arms: Vec<Arm> },
+  // Both the expression and the statement.
+  Throw(NodeId),
+  // `let pattern = value` as a condition.
+  IfLet { pattern: Pat, value: NodeId },
+  Let { var: Var, value: NodeId },
+  Assign { target: NodeId, value: NodeId },
+  Return(NodeId),
+  // A call on its own.
+  Expr(NodeId),
+  // The if arm, then the else-if arms, then the else arm, which has no condition.
+  If { arms: Vec<(Option<NodeId>, Vec<NodeId>)> },
+  Try { body: Vec<NodeId>, slot: usize, handler: Vec<NodeId> },
+}
+
+impl Node {
+  // Whether the runtime calls its debugger before this statement, as `Stmt::is_step`.
+  pub fn is_step(&self) -> bool {
+    !matches!(self, Node::Match { .. } | Node::Try { .. })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+  pub patterns: Vec<Pat>,
+  pub guard: Option<NodeId>,
+  pub body: ArmCode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArmCode {
+  Expr(NodeId),
+  Block(Vec<NodeId>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pat {
+  Wildcard,
+  Number(i32),
+  Bool(bool),
+  String(String),
+  // Matches anything and binds it to a slot.
+  Binding(usize),
+  Variant { name: String, variant: String, fields: Vec<Pat> },
+}
+
+#[derive(Debug, Default)]
+pub struct Code {
+  nodes: Vec<Node>,
+}
+
+impl Code {
+  fn push(&mut self, node: Node) -> NodeId {
+    self.nodes.push(node);
+    NodeId(self.nodes.len() as u32 - 1)
+  }
+}
+
+impl Index<NodeId> for Code {
+  type Output = Node;
+
+  fn index(&self, id: NodeId) -> &Node {
+    &self.nodes[id.0 as usize]
+  }
+}
+
+#[derive(Debug)]
+pub struct Function {
+  pub decl: Rc<FnDecl>,
+  pub code: Code,
+  pub body: Vec<NodeId>,
+  // The slot each parameter is bound to.
+  pub params: Vec<usize>,
+  // The name of the variable in each slot.
+  pub slots: Vec<Symbol>,
+  // The statements the debugger is called at, in the order `ast::Steps` finds them in `decl`.
+  pub steps: Vec<NodeId>,
+  // The index in `steps` of each of them.
+  step_ixs: HashMap<NodeId, usize>,
+  // The statements of `decl` they were lowered from, copied out the first time the debugger needs one.
+  statements: OnceCell<Vec<Stmt>>,
+}
+
+impl Function {
+  fn new(decl: Rc<FnDecl>, lowerer: Lowerer, body: Vec<NodeId>, params: Vec<usize>) -> Function {
+    let step_ixs = lowerer.steps.iter().enumerate().map(|(ix, id)| (*id, ix)).collect();
+    Function { code: lowerer.code, body, params, slots: lowerer.names, steps: lowerer.steps, step_ixs, statements: OnceCell::new(), decl }
+  }
+
+  // The index in `steps` of a statement, if the debugger is called at it.
+  pub fn step(&self, id: NodeId) -> Option<usize> {
+    self.step_ixs.get(&id).copied()
+  }
+
+  // The syntax of the statement at index `step` of `steps`.
+  pub fn statement(&self, step: usize) -> &Stmt {
+    let statements = self.statements.get_or_init(|| {
+      let mut steps = Steps(vec![]);
+      walk_stmts(&self.decl.body, &mut steps);
+      steps.0.into_iter().cloned().collect()
+    });
+    &statements[step]
+  }
+}
+
+// Lower `decl`, defined under `name`. A function of a module, `utils.helper`, calls the module's other
+// functions by their plain names.
+pub fn function(name: &str, decl: Rc<FnDecl>, symbols: &mut Symbols) -> Function {
+  let mut lowerer = Lowerer::new(symbols, name.rfind('.').map(|ix| &name[..=ix]));
+  let params = decl.params.iter().map(|param| lowerer.slot(param)).collect();
+  let mut locals = Locals(vec![]);
+  walk_stmts(&decl.body, &mut locals);
+  for local in locals.0 {
+    lowerer.slot(local);
+  }
+  let body = lowerer.block(&decl.body);
+  Function::new(decl, lowerer, body, params)
+}
+
+// Lower an expression evaluated outside of any function.
+pub fn expression(expr: &Expr, symbols: &mut Symbols) -> (Function, NodeId) {
+  let mut lowerer = Lowerer::new(symbols, None);
+  let mut locals = Locals(vec![]);
+  walk_expr(expr, &mut locals);
+  for local in locals.0 {
+    lowerer.slot(local);
+  }
+  let id = lowerer.expression(expr);
+  let decl = Rc::new(FnDecl { doc: String::new(), name: String::new(), params: vec![], body: vec![] });
+  let function = Function::new(decl, lowerer, vec![], vec![]);
+  (function, id)
+}
+
+struct Lowerer<'a> {
+  symbols: &'a mut Symbols,
+  module: Option<&'a str>,
+  code: Code,
+  slots: HashMap<String, usize>,
+  names: Vec<Symbol>,
+  steps: Vec<NodeId>,
+}
+
+impl<'a> Lowerer<'a> {
+  fn new(symbols: &'a mut Symbols, module: Option<&'a str>) -> Lowerer<'a> {
+    Lowerer { symbols, module, code: Code::default(), slots: HashMap::new(), names: vec![], steps: vec![] }
+  }
+
+  // The slot of a variable the function binds, given one the first time it's seen.
+  fn slot(&mut self, name: &str) -> usize {
+    if let Some(slot) = self.slots.get(name) {
+      return *slot;
+    }
+    let slot = self.names.len();
+    self.names.push(self.symbols.intern(name));
+    self.slots.insert(name.to_string(), slot);
+    slot
+  }
+
+  fn var(&mut self, name: &str) -> Var {
+    Var { slot: self.slots.get(name).copied(), name: self.symbols.intern(name) }
+  }
+
+  fn block(&mut self, stmts: &[Stmt]) -> Vec<NodeId> {
+    stmts.iter().map(|stmt| self.statement(stmt)).collect()
+  }
+
+  fn statement(&mut self, stmt: &Stmt) -> NodeId {
+    // A statement is a step before the ones inside it, but is only pushed after them.
+    let step = self.steps.len();
+    if stmt.is_step() {
+      self.steps.push(NodeId(u32::MAX));
+    }
+    let node = match stmt {
+      Stmt::Let { name, value } => Node::Let { var: self.var(name), value: self.expression(value) },
+      Stmt::Assign { target, value } => Node::Assign { target: self.expression(target), value: self.expression(value) },
+      Stmt::Return(value) => Node::Return(self.expression(value)),
+      Stmt::Throw(value) => Node::Throw(self.expression(value)),
+      Stmt::Expr(value) => Node::Expr(self.expression(value)),
+      Stmt::If(if_) => {
+        let mut arms = vec![(Some(self.expression(&if_.cond)), self.block(&if_.then))];
+        for (condition, body) in &if_.elifs {
+          arms.push((Some(self.expression(condition)), self.block(body)));
+        }
+        if let Some(body) = &if_.else_ {
+          arms.push((None, self.block(body)));
+        }
+        Node::If { arms }
+      },
+      Stmt::Match(match_) => self.match_expression(match_),
+      Stmt::Try { body, variable, handler } => Node::Try { body: self.block(body), slot: self.slot(variable), handler: self.block(handler) },
+    };
+    let id = self.code.push(node);
+    if stmt.is_step() {
+      self.steps[step] = id;
+    }
+    id
+  }
+
+  fn expression(&mut self, expr: &Expr) -> NodeId {
+    let node = match expr {
+      Expr::Number(value) => Node::Number(*value),
+      Expr::Bool(value) => Node::Bool(*value),
+      Expr::String(value) => Node::String(value.clone()),
+      Expr::Identifier(name) => Node::Var(self.var(name)),
+      Expr::Binary { op, lhs, rhs } => Node::Binary { op: *op, lhs: self.expression(lhs), rhs: self.expression(rhs) },
+      Expr::Call { name, args } => Node::Call { callee: self.callee(name), args: args.iter().map(|arg| self.expression(arg)).collect() },
+      Expr::Struct { name, fields } => Node::Struct {
+        name: name.clone(),
+        fields: fields.iter().map(|(field, value)| (field.clone(), self.expression(value))).collect(),
+      },
+      Expr::Field { object, field } => Node::Field { object: self.expression(object), field: field.clone() },
+      Expr::Variant { name, variant, args } => Node::Variant {
+        name: name.clone(),
+        variant: variant.clone(),
+        args: args.iter().map(|arg| self.expression(arg)).collect(),
+      },
+      Expr::Match(match_) => self.match_expression(match_),
+      Expr::Throw(value) => Node::Throw(self.expression(value)),
+      Expr::Let { pattern, value } => Node::IfLet { pattern: self.pattern(pattern), value: self.expression(value) },
+    };
+    self.code.push(node)
+  }
+
+  // The variables an arm's patterns bind get slots of their own, which only its guard and body see; a
+  // variable of the same name outside the arm keeps its slot.
+  fn match_expression(&mut self, match_: &Match) -> Node {
+    let scrutinee = self.expression(&match_.scrutinee);
+    let arms = match_.arms.iter().map(|arm| {
+      let mut bindings = Locals(vec![]);
+      for pattern in &arm.patterns {
+        bindings.pattern(pattern);
+      }
+      let mut shadowed = vec![];
+      for name in bindings.0 {
+        if shadowed.iter().any(|(bound, _)| *bound == name) {
+          continue;
+        }
+        let slot = self.names.len();
+        self.names.push(self.symbols.intern(name));
+        shadowed.push((name, self.slots.insert(name.to_string(), slot)));
+      }
+      let arm = Arm {
+        patterns: arm.patterns.iter().map(|pattern| self.pattern(pattern)).collect(),
+        guard: arm.guard.as_ref().map(|guard| self.expression(guard)),
+        body: match &arm.body {
+          ArmBody::Expr(expr) => ArmCode::Expr(self.expression(expr)),
+          ArmBody::Block(stmts) => ArmCode::Block(self.block(stmts)),
+        },
+      };
+      for (name, slot) in shadowed {
+        match slot {
+          Some(slot) => self.slots.insert(name.to_string(), slot),
+          None => self.slots.remove(name),
+        };
+      }
+      arm
+    }).collect();
+    Node::Match { scrutinee, arms }
+  }
+
+  fn pattern(&mut self, pattern: &Pattern) -> Pat {
+    match pattern {
+      Pattern::Wildcard => Pat::Wildcard,
+      Pattern::Number(value) => Pat::Number(*value),
+      Pattern::Bool(value) => Pat::Bool(*value),
+      Pattern::String(value) => Pat::String(value.clone()),
+      Pattern::Binding(name) => Pat::Binding(self.slot(name)),
+      Pattern::Variant { name, variant, fields } => Pat::Variant {
+        name: name.clone(),
+        variant: variant.clone(),
+        fields: fields.iter().map(|field| self.pattern(field)).collect(),
+      },
+    }
+  }
+
+  fn callee(&mut self, name: &str) -> Callee {
+    let mut path: Vec<&str> = name.split('.').collect();
+    let method = path.pop().unwrap_or(name);
+    let method = path.split_first().map(|(receiver, fields)| MethodPath {
+      receiver: self.var(receiver),
+      fields: fields.iter().map(|field| field.to_string()).collect(),
+      method: method.to_string(),
+    });
+    let qualified = self.module.map(|module| self.symbols.intern(&format!("{}{}", module, name)));
+    Callee { name: self.symbols.intern(name), qualified, method }
+  }
+}
+
+// The names a function binds for the rest of its body besides its parameters: not those a match arm
+// binds, which the arm gets slots for.
+struct Locals<'a>(Vec<&'a str>);
+
+impl<'a> Locals<'a> {
+  fn pattern(&mut self, pattern: &'a Pattern) {
+    match pattern {
+      Pattern::Binding(name) => self.0.push(name),
+      Pattern::Variant { fields, .. } => {
+        for field in fields {
+          self.pattern(field);
+        }
+      },
+      _ => (),
+    }
+  }
+}
+
+impl<'a> Visitor<'a> for Locals<'a> {
+  fn visit_stmt(&mut self, stmt: &'a Stmt) {
+    if let Stmt::Let { name, .. } | Stmt::Try { variable: name, .. } = stmt {
+      self.0.push(name);
+    }
+  }
+
+  fn visit_expr(&mut self, expr: &'a Expr) {
+    if let Expr::Let { pattern, .. } = expr {
+      self.pattern(pattern);
+    }
+  }
+}
@@ -0,0 +1,463 @@
+// Language server for Asa, spoken over any lsp-server Connection (stdio in `asa-lsp`, in memory in the
+// tests).
+//
+// Diagnostics come from the parser: `parser::program` decides whether a document is valid and the
+// recovering parser supplies the messages. Definitions, hovers, completions and symbols cover the
+// functions, methods, structs and enums in the tree the recovering parser builds. The tree doesn't
+// record positions, so a light scan of the source that locates words, braces and `let`s finds where
+// each of them is.
+
+use crate::ast::{FnDecl, Item, Program};
+use crate::formatter::format_program;
+use crate::parser;
+use crate::recovery::program_with_recovery;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+  CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse, Diagnostic, DiagnosticSeverity,
+  DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+  HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position,
+  PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::collections::HashMap;
+use std::error::Error;
+
+pub fn capabilities() -> ServerCapabilities {
+  ServerCapabilities {
+    text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+    definition_provider: Some(OneOf::Left(true)),
+    hover_provider: Some(HoverProviderCapability::Simple(true)),
+    completion_provider: Some(CompletionOptions::default()),
+    document_symbol_provider: Some(OneOf::Left(true)),
+    ..ServerCapabilities::default()
+  }
+}
+
+// Run the initialize handshake and then serve requests until the client shuts the server down.
+pub fn serve(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+  connection.initialize(serde_json::to_value(capabilities())?)?;
+  let mut documents: HashMap<Url, String> = HashMap::new();
+  for message in &connection.receiver {
+    match message {
+      Message::Request(request) => {
+        if connection.handle_shutdown(&request)? {
+          return Ok(());
+        }
+        let response = handle_request(&documents, request);
+        connection.sender.send(Message::Response(response))?;
+      },
+      Message::Notification(notification) => {
+        if let Some(uri) = handle_notification(&mut documents, notification) {
+          let source = documents.get(&uri).map(String::as_str).unwrap_or("");
+          let params = PublishDiagnosticsParams { uri: uri.clone(), diagnostics: diagnostics(source), version: None };
+          connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_string(), params)))?;
+        }
+      },
+      Message::Response(_) => (),
+    }
+  }
+  Ok(())
+}
+
+// Keep the document store up to date. Returns the document whose diagnostics need publishing.
+fn handle_notification(documents: &mut HashMap<Url, String>, notification: Notification) -> Option<Url> {
+  match notification.method.as_str() {
+    DidOpenTextDocument::METHOD => {
+      let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      documents.insert(params.text_document.uri.clone(), params.text_document.text);
+      Some(params.text_document.uri)
+    },
+    DidChangeTextDocument::METHOD => {
+      // Full sync: the last change holds the whole document.
+      let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      let text = params.content_changes.into_iter().last()?.text;
+      documents.insert(params.text_document.uri.clone(), text);
+      Some(params.text_document.uri)
+    },
+    DidCloseTextDocument::METHOD => {
+      let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      documents.remove(&params.text_document.uri);
+      Some(params.text_document.uri)
+    },
+    _ => None,
+  }
+}
+
+fn handle_request(documents: &HashMap<Url, String>, request: Request) -> Response {
+  let source = |uri: &Url| documents.get(uri).map(String::as_str).unwrap_or("");
+  let id = request.id.clone();
+  let result = match request.method.as_str() {
+    GotoDefinition::METHOD => serde_json::from_value::<GotoDefinitionParams>(request.params).map(|params| {
+      let at = params.text_document_position_params;
+      let text = source(&at.text_document.uri);
+      let location = definition(text, at.position).map(|range| Location { uri: at.text_document.uri.clone(), range });
+      serde_json::to_value(location.map(GotoDefinitionResponse::Scalar))
+    }),
+    HoverRequest::METHOD => serde_json::from_value::<HoverParams>(request.params).map(|params| {
+      let at = params.text_document_position_params;
+      serde_json::to_value(hover(source(&at.text_document.uri), at.position))
+    }),
+    Completion::METHOD => serde_json::from_value::<CompletionParams>(request.params).map(|params| {
+      let at = params.text_document_position;
+      serde_json::to_value(CompletionResponse::Array(completions(source(&at.text_document.uri), at.position)))
+    }),
+    DocumentSymbolRequest::METHOD => serde_json::from_value::<DocumentSymbolParams>(request.params).map(|params| {
+      serde_json::to_value(DocumentSymbolResponse::Nested(document_symbols(source(&params.text_document.uri))))
+    }),
+    _ => return Response::new_err(id, lsp_server::ErrorCode::MethodNotFound as i32, format!("unsupported request {}", request.method)),
+  };
+  match result {
+    Ok(Ok(value)) => Response { id, result: Some(value), error: None },
+    Ok(Err(error)) | Err(error) => Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, error.to_string()),
+  }
+}
+
+// ---- Analyses ----
+
+// Syntax errors, or nothing if `parser::program` accepts the whole document.
+pub fn diagnostics(source: &str) -> Vec<Diagnostic> {
+  if let Ok(("", _)) = parser::program(source) {
+    return vec![];
+  }
+  let mut errors = program_with_recovery(source).1;
+  if errors.is_empty() {
+    errors.push(crate::recovery::SyntaxError { message: "could not parse program".to_string(), offset: 0, line: 1, column: 1 });
+  }
+  errors.into_iter().map(|error| {
+    let end = error.offset + source[error.offset..].chars().next().map(|c| c.len_utf8()).unwrap_or(0);
+    Diagnostic {
+      range: Range { start: position(source, error.offset), end: position(source, end) },
+      severity: Some(DiagnosticSeverity::ERROR),
+      source: Some("asa".to_string()),
+      message: error.message,
+      ..Diagnostic::default()
+    }
+  }).collect()
+}
+
+// The definition of the function, method, type or variable under the cursor.
+pub fn definition(source: &str, at: Position) -> Option<Range> {
+  let outline = Outline::new(source);
+  let word = outline.word_at(offset(source, at))?;
+  let target = match outline.definition(word.text) {
+    Some(definition) if definition.is_type() || !outline.is_variable_use(word) => definition.name,
+    _ => outline.variables_in_scope(word.offset + 1).into_iter().rev().find(|v| v.text == word.text)?,
+  };
+  Some(outline.range(target))
+}
+
+// The signature and doc comment of the function, method or type under the cursor.
+pub fn hover(source: &str, at: Position) -> Option<Hover> {
+  let outline = Outline::new(source);
+  let word = outline.word_at(offset(source, at))?;
+  let definition = outline.definition(word.text)?;
+  let mut text = format!("```asa\n{}\n```", definition.signature);
+  if !definition.doc.is_empty() {
+    text.push_str("\n\n");
+    text.push_str(&definition.doc);
+  }
+  Some(Hover {
+    contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: text }),
+    range: Some(outline.range(word)),
+  })
+}
+
+// Every defined function, method and type, plus the parameters and `let` variables visible at the
+// cursor.
+pub fn completions(source: &str, at: Position) -> Vec<CompletionItem> {
+  let outline = Outline::new(source);
+  let mut items: Vec<CompletionItem> = outline.definitions.iter().map(|definition| CompletionItem {
+    label: definition.name.text.to_string(),
+    kind: Some(match definition.kind {
+      Kind::Function => CompletionItemKind::FUNCTION,
+      Kind::Method(_) => CompletionItemKind::METHOD,
+      Kind::Struct => CompletionItemKind::STRUCT,
+      Kind::Enum => CompletionItemKind::ENUM,
+    }),
+    detail: Some(definition.signature.clone()),
+    ..CompletionItem::default()
+  }).collect();
+  let mut seen = vec![];
+  for variable in outline.variables_in_scope(offset(source, at)).into_iter().rev() {
+    if !seen.contains(&variable.text) {
+      seen.push(variable.text);
+      items.push(CompletionItem { label: variable.text.to_string(), kind: Some(CompletionItemKind::VARIABLE), ..CompletionItem::default() });
+    }
+  }
+  items
+}
+
+// Functions and methods, each with its parameters and `let` variables as children, and structs and
+// enums with their fields or variants. A method is named after its struct, `Point::dist`.
+#[allow(deprecated)] // DocumentSymbol::deprecated has to be set even though it is deprecated.
+pub fn document_symbols(source: &str) -> Vec<DocumentSymbol> {
+  let outline = Outline::new(source);
+  let symbol = |name: String, word: Word, kind: SymbolKind, range: Range, detail: Option<String>, children: Option<Vec<DocumentSymbol>>| DocumentSymbol {
+    name,
+    detail,
+    kind,
+    tags: None,
+    deprecated: None,
+    range,
+    selection_range: outline.range(word),
+    children,
+  };
+  outline.definitions.iter().map(|definition| {
+    let name = definition.name.text.to_string();
+    let (kind, name, member) = match definition.kind {
+      Kind::Function => (SymbolKind::FUNCTION, name, SymbolKind::VARIABLE),
+      Kind::Method(owner) => (SymbolKind::METHOD, format!("{}::{}", owner, name), SymbolKind::VARIABLE),
+      Kind::Struct => (SymbolKind::STRUCT, name, SymbolKind::FIELD),
+      Kind::Enum => (SymbolKind::ENUM, name, SymbolKind::ENUM_MEMBER),
+    };
+    let children = definition.members.iter().chain(definition.lets.iter())
+      .map(|v| symbol(v.text.to_string(), *v, member, outline.range(*v), None, None))
+      .collect();
+    let range = Range { start: position(source, definition.start), end: position(source, definition.end) };
+    symbol(name, definition.name, kind, range, Some(definition.signature.clone()), Some(children))
+  }).collect()
+}
+
+// ---- Source outline ----
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Word<'a> {
+  text: &'a str,
+  offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind<'a> {
+  Function,
+  // A method of the named struct.
+  Method(&'a str),
+  Struct,
+  Enum,
+}
+
+struct Definition<'a> {
+  kind: Kind<'a>,
+  name: Word<'a>,
+  // A function's parameters, a struct's fields or an enum's variants.
+  members: Vec<Word<'a>>,
+  // The `let` variables of a function.
+  lets: Vec<Word<'a>>,
+  // How hovers and completions show it: `fn inc(a)`, `fn Point::dist(self)`, `struct Point { x, y }`.
+  signature: String,
+  doc: String,
+  // Byte range from `fn`, `struct` or `enum` to just past the closing brace.
+  start: usize,
+  end: usize,
+}
+
+impl<'a> Definition<'a> {
+  fn is_type(&self) -> bool {
+    matches!(self.kind, Kind::Struct | Kind::Enum)
+  }
+}
+
+struct Outline<'a> {
+  source: &'a str,
+  tokens: Vec<Word<'a>>,
+  definitions: Vec<Definition<'a>>,
+  // `let` variables outside any function.
+  globals: Vec<Word<'a>>,
+}
+
+impl<'a> Outline<'a> {
+  fn new(source: &'a str) -> Outline<'a> {
+    let tokens = tokens(source);
+    let globals = tokens.windows(2).filter(|pair| pair[0].text == "let" && is_identifier(pair[1].text)).map(|pair| pair[1]).collect();
+    let tree = program_with_recovery(source).0;
+    let mut outline = Outline { source, tokens, definitions: vec![], globals };
+    // Each item is looked for in the tokens after the one before it.
+    let mut next = 0;
+    for item in &tree.items {
+      next = match item {
+        Item::Fn(function) => outline.function(next, function, Kind::Function),
+        Item::Impl { name, methods } => match outline.find(next, "impl", name) {
+          Some(ix) => {
+            let owner = outline.tokens[ix + 1].text;
+            methods.iter().fold(ix + 2, |next, method| outline.function(next, method, Kind::Method(owner)))
+          },
+          None => next,
+        },
+        Item::Struct { name, .. } => outline.type_definition(next, "struct", name, Kind::Struct, item),
+        Item::Enum { name, .. } => outline.type_definition(next, "enum", name, Kind::Enum, item),
+        _ => next,
+      };
+    }
+    outline
+  }
+
+  // The index of the first `keyword name` at or after the token at `from`.
+  fn find(&self, from: usize, keyword: &str, name: &str) -> Option<usize> {
+    (from..self.tokens.len().saturating_sub(1)).find(|ix| self.tokens[*ix].text == keyword && self.tokens[ix + 1].text == name)
+  }
+
+  // Add the function or method `decl`, looking for it from the token at `from`. Returns where to look
+  // for the next item.
+  fn function(&mut self, from: usize, decl: &FnDecl, kind: Kind<'a>) -> usize {
+    let ix = match self.find(from, "fn", &decl.name) {
+      Some(ix) => ix,
+      None => return from,
+    };
+    let tokens = &self.tokens;
+    let is_word = |i: usize| tokens.get(i).map(|t: &Word| is_identifier(t.text)).unwrap_or(false);
+    let signature = match kind {
+      Kind::Method(owner) => format!("fn {}::{}({})", owner, decl.name, decl.params.join(", ")),
+      _ => format!("fn {}({})", decl.name, decl.params.join(", ")),
+    };
+    let mut function = Definition { kind, name: tokens[ix + 1], members: vec![], lets: vec![], signature, doc: decl.doc.clone(), start: tokens[ix].offset, end: self.source.len() };
+    let mut next = ix + 2;
+    if tokens.get(next).map(|t| t.text) == Some("(") {
+      next += 1;
+      while next < tokens.len() && tokens[next].text != ")" && tokens[next].text != "{" {
+        if is_identifier(tokens[next].text) {
+          function.members.push(tokens[next]);
+        }
+        next += 1;
+      }
+    }
+    // The body runs from the first `{` to its matching `}`, or to the next `fn` if it is unclosed.
+    while next < tokens.len() && tokens[next].text != "{" && tokens[next].text != "fn" {
+      next += 1;
+    }
+    if tokens.get(next).map(|t| t.text) != Some("{") {
+      function.end = tokens.get(next).map(|t| t.offset).unwrap_or(self.source.len());
+    }
+    let mut depth = 0;
+    while tokens.get(next).map(|t| t.text) == Some("{") || (depth > 0 && next < tokens.len()) {
+      match tokens[next].text {
+        "{" => depth += 1,
+        "}" => depth -= 1,
+        "fn" => {
+          function.end = tokens[next].offset;
+          break;
+        },
+        "let" if is_word(next + 1) => function.lets.push(tokens[next + 1]),
+        _ => (),
+      }
+      next += 1;
+      if depth == 0 {
+        function.end = tokens[next - 1].offset + 1;
+        break;
+      }
+    }
+    self.definitions.push(function);
+    next.max(ix + 1)
+  }
+
+  // Add the struct or enum `item`, declared with `keyword`, looking for it from the token at `from`. Its
+  // members are the names in its braces outside any parentheses. Returns where to look for the next item.
+  fn type_definition(&mut self, from: usize, keyword: &str, name: &str, kind: Kind<'a>, item: &Item) -> usize {
+    let ix = match self.find(from, keyword, name) {
+      Some(ix) => ix,
+      None => return from,
+    };
+    let signature = format_program(&Program { items: vec![item.clone()] }).trim_end().to_string();
+    let mut definition = Definition { kind, name: self.tokens[ix + 1], members: vec![], lets: vec![], signature, doc: String::new(), start: self.tokens[ix].offset, end: self.source.len() };
+    let (mut next, mut parens) = (ix + 2, 0);
+    while let Some(token) = self.tokens.get(next) {
+      next += 1;
+      match token.text {
+        "(" => parens += 1,
+        ")" => parens -= 1,
+        "}" => {
+          definition.end = token.offset + 1;
+          break;
+        },
+        text if parens == 0 && is_identifier(text) => definition.members.push(*token),
+        _ => (),
+      }
+    }
+    self.definitions.push(definition);
+    next
+  }
+
+  fn range(&self, word: Word) -> Range {
+    Range { start: position(self.source, word.offset), end: position(self.source, word.offset + word.text.len()) }
+  }
+
+  fn word_at(&self, offset: usize) -> Option<Word<'a>> {
+    self.tokens.iter().copied().find(|t| is_identifier(t.text) && t.offset <= offset && offset <= t.offset + t.text.len())
+  }
+
+  fn definition(&self, name: &str) -> Option<&Definition<'a>> {
+    self.definitions.iter().find(|definition| definition.name.text == name)
+  }
+
+  // A name that isn't followed by `(` and isn't a function's own name refers to a variable.
+  fn is_variable_use(&self, word: Word) -> bool {
+    let ix = self.tokens.iter().position(|t| *t == word).unwrap_or(0);
+    let called = self.tokens.get(ix + 1).map(|t| t.text) == Some("(");
+    let defined = ix > 0 && self.tokens[ix - 1].text == "fn";
+    !called && !defined
+  }
+
+  // Parameters and `let` variables visible at `offset`, in order of definition.
+  fn variables_in_scope(&self, offset: usize) -> Vec<Word<'a>> {
+    let mut functions = self.definitions.iter().filter(|definition| !definition.is_type());
+    match functions.clone().find(|f| f.start <= offset && offset <= f.end) {
+      Some(function) => function.members.iter().chain(function.lets.iter().filter(|v| v.offset < offset)).copied().collect(),
+      None => self.globals.iter().filter(|v| v.offset < offset && !functions.any(|f| f.start <= v.offset && v.offset <= f.end)).copied().collect(),
+    }
+  }
+}
+
+fn is_identifier(text: &str) -> bool {
+  text.starts_with(|c: char| c.is_alphabetic() || c == '_') && !parser::RESERVED_WORDS.contains(&text)
+}
+
+// Words and single punctuation characters, skipping whitespace, comments and string literals.
+fn tokens(source: &str) -> Vec<Word<'_>> {
+  let mut tokens = vec![];
+  let mut rest = source;
+  while !rest.is_empty() {
+    let skipped = parser::trivia(rest).map(|(after, _)| after).unwrap_or(rest);
+    let skipped = parser::doc_comment(skipped).map(|(after, _)| after).unwrap_or(skipped);
+    if skipped.len() < rest.len() {
+      rest = skipped;
+      continue;
+    }
+    if let Ok((after, _)) = parser::string(rest) {
+      rest = after;
+      continue;
+    }
+    let offset = source.len() - rest.len();
+    let word = rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_').map(|c| c.len_utf8()).sum::<usize>();
+    let len = match word {
+      0 => rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1),
+      n => n,
+    };
+    tokens.push(Word { text: &rest[..len], offset });
+    rest = &rest[len..];
+  }
+  tokens
+}
+
+// LSP positions count UTF-16 code units within a line.
+pub fn position(source: &str, offset: usize) -> Position {
+  let before = &source[..offset.min(source.len())];
+  let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+  Position { line: before.matches('\n').count() as u32, character: before[line_start..].encode_utf16().count() as u32 }
+}
+
+pub fn offset(source: &str, position: Position) -> usize {
+  let mut line_start = 0;
+  for _ in 0..position.line {
+    match source[line_start..].find('\n') {
+      Some(i) => line_start += i + 1,
+      None => return source.len(),
+    }
+  }
+  let mut units = 0;
+  for (i, c) in source[line_start..].char_indices() {
+    if units >= position.character as usize || c == '\n' {
+      return line_start + i;
+    }
+    units += c.len_utf16();
+  }
+  source.len()
+}
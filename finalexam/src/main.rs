@@ -1,11 +1,11 @@
 extern crate nom;
 extern crate asalang;
 
-use asalang::{program, start_interpreter};
+use asalang::{program, program_with_recovery, start_interpreter, warnings};
 
 fn main() -> Result<(), nom::Err<(&'static str, nom::error::ErrorKind)>> {
   
-  let result = program(r#"
+  let source = r#"
   fn main() {
     let x = 2;
     let y = 5;
@@ -20,11 +20,20 @@ fn main() -> Result<(), nom::Err<(&'static str, nom::error::ErrorKind)>> {
       return 103;
     }
   }
-  "#);
+  "#;
+  let result = program(source);
   match result {
     Ok((unparsed,tree)) => {
       println!("Unparsed Text: {:?}", unparsed);
+      if !unparsed.is_empty() {
+        for error in program_with_recovery(source).1 {
+          println!("syntax error: {}", error);
+        }
+      }
       println!("Parse Tree:\n {:#?}", tree);
+      for warning in warnings(&tree) {
+        println!("warning: {}", warning);
+      }
       let result = start_interpreter(&tree);
       println!("{:?}", result);
     }
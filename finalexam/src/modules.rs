@@ -0,0 +1,93 @@
+// Modules: other .asa files a program pulls in with `import`.
+//
+//   import utils;               // utils.asa, next to the importing file
+//   import "lib/strings.asa";   // a path relative to the importing file; the module is `strings`
+//
+// A module's functions are called through its name, `utils.helper(x)`. Inside a module, plain names
+// refer to the module's own functions first, then to host functions. A module can't declare struct
+// or enum types or methods; anything else in it besides imports and function definitions is ignored.
+//
+// `Runtime::load_imports` does the loading; this file holds the pieces that don't need a runtime.
+
+use crate::ast::{FnDecl, Item, Program};
+use crate::parser;
+use crate::recovery::{program_with_recovery, SyntaxError};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleError {
+  Read(PathBuf),
+  Syntax { path: PathBuf, errors: Vec<SyntaxError> },
+  // The modules that import each other, starting and ending with the same one.
+  Cycle(Vec<PathBuf>),
+  // A declaration a module can't hold, such as `struct Point`.
+  Unsupported { path: PathBuf, item: String },
+}
+
+impl fmt::Display for ModuleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ModuleError::Read(path) => write!(f, "{}: could not read module", path.display()),
+      ModuleError::Syntax { path, errors } if errors.is_empty() => write!(f, "{}: could not parse module", path.display()),
+      ModuleError::Syntax { path, errors } => {
+        let lines: Vec<String> = errors.iter().map(|error| format!("{}:{}", path.display(), error)).collect();
+        write!(f, "{}", lines.join("\n"))
+      },
+      ModuleError::Cycle(paths) => {
+        let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+        write!(f, "import cycle: {}", paths.join(" -> "))
+      },
+      ModuleError::Unsupported { path, item } => write!(f, "{}: modules can't declare `{}`", path.display(), item),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+  pub name: String,
+  pub path: PathBuf,
+}
+
+// A parsed module file.
+#[derive(Debug, Clone)]
+pub struct Module {
+  pub imports: Vec<Import>,
+  // Each function's name and definition, as the runtime stores them.
+  pub functions: Vec<(String, Rc<FnDecl>)>,
+}
+
+// The imports of `program`, with paths resolved against the directory of `from`, the file it came from.
+pub fn imports(program: &Program, from: &Path) -> Vec<Import> {
+  let dir = from.parent().unwrap_or_else(|| Path::new(""));
+  program.items.iter().filter_map(|item| match item {
+    Item::Import { name, path } => Some(Import {
+      name: name.clone(),
+      path: dir.join(path.clone().unwrap_or_else(|| format!("{}.asa", name))),
+    }),
+    _ => None,
+  }).collect()
+}
+
+pub fn parse(path: &Path) -> Result<Module, ModuleError> {
+  let source = std::fs::read_to_string(path).map_err(|_| ModuleError::Read(path.to_path_buf()))?;
+  let tree = match parser::program(&source) {
+    Ok(("", tree)) => tree,
+    _ => return Err(ModuleError::Syntax { path: path.to_path_buf(), errors: program_with_recovery(&source).1 }),
+  };
+  let unsupported = tree.items.iter().find_map(|item| match item {
+    Item::Struct { name, .. } => Some(format!("struct {}", name)),
+    Item::Enum { name, .. } => Some(format!("enum {}", name)),
+    Item::Impl { name, .. } => Some(format!("impl {}", name)),
+    _ => None,
+  });
+  if let Some(item) = unsupported {
+    return Err(ModuleError::Unsupported { path: path.to_path_buf(), item });
+  }
+  let functions = tree.items.iter().filter_map(|item| match item {
+    Item::Fn(function) => Some((function.name.clone(), Rc::new(function.clone()))),
+    _ => None,
+  }).collect();
+  Ok(Module { imports: imports(&tree, path), functions })
+}
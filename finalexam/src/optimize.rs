@@ -0,0 +1,295 @@
+// Optimizer: rewrites a parsed program into one that does less work and runs to the same result.
+//
+//   - math and comparisons on constants are folded into their value: `2 * 3 + 1` becomes `7`;
+//   - if/else-if arms whose condition is constantly false are dropped, and so are the arms after one
+//     whose condition is constantly true;
+//   - calls to trivial functions, ones that only return arithmetic on their parameters, are replaced
+//     by that arithmetic with the arguments filled in.
+//
+// Constants are evaluated by a scratch `Runtime`, so a folded value is always what the interpreter
+// would have computed. Anything that fails, like `1 / 0`, is left in place for the run to report.
+// Inlined calls don't appear in `Runtime::error_stack`.
+
+use crate::ast::{ArmBody, Expr, FnDecl, If, Item, Match, Program, Stmt};
+use crate::interpreter::{Runtime, Value};
+use std::collections::HashMap;
+
+pub fn optimize(program: &Program) -> Program {
+  let mut optimizer = Optimizer { runtime: Runtime::new(), trivial: trivial_functions(program) };
+  let mut program = program.clone();
+  for item in &mut program.items {
+    match item {
+      Item::Fn(function) => optimizer.block(&mut function.body),
+      Item::Test { body, .. } => optimizer.block(body),
+      Item::Impl { methods, .. } => {
+        for method in methods {
+          optimizer.block(&mut method.body);
+        }
+      },
+      Item::Stmt(stmt) => optimizer.statement(stmt),
+      Item::Expr(expr) => optimizer.expression(expr, Context::Value),
+      Item::Import { .. } | Item::Struct { .. } | Item::Enum { .. } => (),
+    }
+  }
+  program
+}
+
+// Where an expression is evaluated. The runtime has different rules for what may be compared in a
+// condition, and for what a comparison may have on either side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Context {
+  Value,
+  // The condition of an if or a match guard.
+  Condition,
+  // A side of a comparison. A comparison here isn't folded, since a literal isn't allowed in its place.
+  Operand,
+}
+
+struct Optimizer {
+  runtime: Runtime,
+  // The parameters of each trivial function and the expression it returns.
+  trivial: HashMap<String, (Vec<String>, Expr)>,
+}
+
+impl Optimizer {
+  fn block(&mut self, stmts: &mut [Stmt]) {
+    for stmt in stmts {
+      self.statement(stmt);
+    }
+  }
+
+  fn statement(&mut self, stmt: &mut Stmt) {
+    match stmt {
+      Stmt::Let { value, .. } | Stmt::Assign { value, .. } | Stmt::Return(value) | Stmt::Throw(value) => self.expression(value, Context::Value),
+      // A call on its own statement is kept, so only its arguments are optimized.
+      Stmt::Expr(Expr::Call { args, .. }) => {
+        for arg in args {
+          self.expression(arg, Context::Value);
+        }
+      },
+      Stmt::Expr(expr) => self.expression(expr, Context::Value),
+      Stmt::If(if_) => {
+        self.expression(&mut if_.cond, Context::Condition);
+        self.block(&mut if_.then);
+        for (condition, body) in &mut if_.elifs {
+          self.expression(condition, Context::Condition);
+          self.block(body);
+        }
+        if let Some(body) = &mut if_.else_ {
+          self.block(body);
+        }
+        prune(if_);
+      },
+      Stmt::Match(match_) => self.match_expression(match_),
+      Stmt::Try { body, handler, .. } => {
+        self.block(body);
+        self.block(handler);
+      },
+    }
+  }
+
+  fn match_expression(&mut self, match_: &mut Match) {
+    self.expression(&mut match_.scrutinee, Context::Value);
+    for arm in &mut match_.arms {
+      if let Some(guard) = &mut arm.guard {
+        self.expression(guard, Context::Condition);
+      }
+      match &mut arm.body {
+        ArmBody::Expr(expr) => self.expression(expr, Context::Value),
+        ArmBody::Block(stmts) => self.block(stmts),
+      }
+    }
+  }
+
+  fn expression(&mut self, expr: &mut Expr, context: Context) {
+    match expr {
+      Expr::Binary { op, lhs, rhs } => {
+        let operands = match op.is_comparison() {
+          true => Context::Operand,
+          false => Context::Value,
+        };
+        self.expression(lhs, operands);
+        self.expression(rhs, operands);
+      },
+      Expr::Call { args, .. } | Expr::Variant { args, .. } => {
+        for arg in args {
+          self.expression(arg, Context::Value);
+        }
+      },
+      Expr::Struct { fields, .. } => {
+        for (_, value) in fields {
+          self.expression(value, Context::Value);
+        }
+      },
+      Expr::Field { object, .. } => self.expression(object, Context::Value),
+      Expr::Match(match_) => self.match_expression(match_),
+      Expr::Throw(value) | Expr::Let { value, .. } => self.expression(value, Context::Value),
+      Expr::Number(_) | Expr::Bool(_) | Expr::String(_) | Expr::Identifier(_) => (),
+    }
+    let fold = match expr {
+      Expr::Binary { op, .. } => !(op.is_comparison() && context == Context::Operand) && constant(expr),
+      _ => false,
+    };
+    if fold {
+      if let Some(value) = self.evaluate(expr, context) {
+        *expr = value;
+      }
+      return;
+    }
+    if context == Context::Value {
+      if let Some(mut inlined) = self.inline(expr) {
+        self.expression(&mut inlined, context);
+        *expr = inlined;
+      }
+    }
+  }
+
+  // The value of a constant expression as a literal, if it has one.
+  fn evaluate(&mut self, expr: &Expr, context: Context) -> Option<Expr> {
+    let result = match context {
+      Context::Condition => self.runtime.condition(expr),
+      _ => self.runtime.eval(expr),
+    };
+    match result {
+      Ok(Value::Number(value)) => Some(Expr::Number(value)),
+      Ok(Value::Bool(value)) => Some(Expr::Bool(value)),
+      Ok(Value::String(value)) => Some(Expr::String(value)),
+      _ => None,
+    }
+  }
+
+  // The body of a call to a trivial function. Only numbers and variables are passed in, since those
+  // can be repeated or reordered without changing what the program does.
+  fn inline(&self, call: &Expr) -> Option<Expr> {
+    let (name, args) = match call {
+      Expr::Call { name, args } => (name, args),
+      _ => return None,
+    };
+    let (params, body) = self.trivial.get(name)?;
+    if args.len() != params.len() {
+      return None;
+    }
+    let mut values = HashMap::new();
+    for (param, arg) in params.iter().zip(args) {
+      match arg {
+        Expr::Number(_) | Expr::Identifier(_) => {
+          values.insert(param.as_str(), arg.clone());
+        },
+        _ => return None,
+      }
+    }
+    let mut body = body.clone();
+    substitute(&mut body, &values);
+    Some(body)
+  }
+}
+
+// Drop the arms of an if expression that can't run. The interpreter moves on to the next arm when one
+// returns false, so only an arm without a `return` cuts off the ones after it.
+fn prune(if_: &mut If) {
+  let first = (Some(if_.cond.clone()), if_.then.clone());
+  let arms = std::iter::once(first.clone())
+    .chain(if_.elifs.drain(..).map(|(condition, body)| (Some(condition), body)))
+    .chain(if_.else_.take().map(|body| (None, body)));
+  let mut kept = vec![];
+  for (ix, (condition, body)) in arms.enumerate() {
+    let always = match &condition {
+      None => true,
+      Some(Expr::Bool(false)) => continue,
+      Some(Expr::Bool(true)) => true,
+      _ => false,
+    };
+    let returns = body.iter().any(|stmt| matches!(stmt, Stmt::Return(_)));
+    kept.push((ix, condition, body));
+    if always && !returns {
+      break;
+    }
+  }
+  // The arms left have to start with an `if`.
+  match kept.first_mut() {
+    None => kept.push((0, first.0, first.1)),
+    Some((_, condition @ None, _)) => *condition = Some(Expr::Bool(true)),
+    Some((0, ..)) => (),
+    // An else-if whose condition errors reports it differently from an if, so only a constant one is
+    // turned into an if.
+    Some((_, Some(Expr::Bool(_)), _)) => (),
+    Some(_) => kept.insert(0, (0, first.0, first.1)),
+  }
+  let mut kept = kept.into_iter();
+  if let Some((_, Some(condition), body)) = kept.next() {
+    if_.cond = condition;
+    if_.then = body;
+  }
+  for (_, condition, body) in kept {
+    match condition {
+      Some(condition) => if_.elifs.push((condition, body)),
+      None => if_.else_ = Some(body),
+    }
+  }
+}
+
+// The functions of `program` whose body is a single `return` of arithmetic on their parameters that
+// uses every parameter. A name defined more than once is left alone.
+fn trivial_functions(program: &Program) -> HashMap<String, (Vec<String>, Expr)> {
+  let mut functions = HashMap::new();
+  for item in &program.items {
+    if let Item::Fn(function) = item {
+      let trivial = match functions.contains_key(&function.name) {
+        true => None,
+        false => trivial(function),
+      };
+      functions.insert(function.name.clone(), trivial);
+    }
+  }
+  functions.into_iter().filter_map(|(name, trivial)| trivial.map(|trivial| (name, trivial))).collect()
+}
+
+fn trivial(function: &FnDecl) -> Option<(Vec<String>, Expr)> {
+  let value = match function.body.as_slice() {
+    [Stmt::Return(value)] => value,
+    _ => return None,
+  };
+  let mut used = vec![];
+  if !arithmetic(value, &function.params, &mut used) || function.params.iter().any(|param| !used.contains(param)) {
+    return None;
+  }
+  Some((function.params.clone(), value.clone()))
+}
+
+// Whether `expr` is only literals, parameters and math, recording the parameters it uses.
+fn arithmetic(expr: &Expr, params: &[String], used: &mut Vec<String>) -> bool {
+  match expr {
+    Expr::Number(_) | Expr::Bool(_) | Expr::String(_) => true,
+    Expr::Identifier(name) if params.contains(name) => {
+      used.push(name.clone());
+      true
+    },
+    Expr::Binary { op, lhs, rhs } if !op.is_comparison() => arithmetic(lhs, params, used) && arithmetic(rhs, params, used),
+    _ => false,
+  }
+}
+
+// Fill in the parameters of an arithmetic expression.
+fn substitute(expr: &mut Expr, values: &HashMap<&str, Expr>) {
+  match expr {
+    Expr::Identifier(name) => {
+      if let Some(arg) = values.get(name.as_str()) {
+        *expr = arg.clone();
+      }
+    },
+    Expr::Binary { lhs, rhs, .. } => {
+      substitute(lhs, values);
+      substitute(rhs, values);
+    },
+    _ => (),
+  }
+}
+
+// Literals, and math or comparisons on them.
+fn constant(expr: &Expr) -> bool {
+  match expr {
+    Expr::Number(_) | Expr::Bool(_) | Expr::String(_) => true,
+    Expr::Binary { lhs, rhs, .. } => constant(lhs) && constant(rhs),
+    _ => false,
+  }
+}
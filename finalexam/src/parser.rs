@@ -1,314 +1,737 @@
 // Here is where the various combinators are imported. You can find all the combinators here:
 // If you want to use it in your parser, you need to import it here. I've already imported a couple.
 
+use std::collections::HashMap;
+
+use crate::ast::{walk_program, ArmBody, BinaryOp, Expr, FnDecl, If, Item, Match, MatchArm, Pattern, Program, Stmt, VariantDecl, Visitor};
 use nom::{
     IResult,
     branch::alt,
-    combinator::opt,
+    combinator::{map, map_opt, map_res, not, opt, peek, recognize, value, verify},
     multi::{many1, many0},
-    bytes::complete::{tag},
-    character::complete::{alphanumeric1, digit1, space1, line_ending},
+    bytes::complete::{is_not, tag, take_till},
+    character::complete::{alpha1, alphanumeric1, anychar, digit1, multispace0, multispace1, satisfy},
+    sequence::{pair, preceded, terminated, tuple},
   };
-  
-  // Here are the different node types. You will use these to make your parser and your grammar.
-  // You may add other nodes as you see fit, but these are expected by the runtime.
-  
-  #[derive(Debug, Clone)]
-  pub enum Node {
-    Program { children: Vec<Node> },
-    Statement { children: Vec<Node> },
-    FunctionReturn { children: Vec<Node> },
-    FunctionDefine { children: Vec<Node> },
-    FunctionArguments { children: Vec<Node> },
-    FunctionStatements { children: Vec<Node> },
-    IfExpression { children: Vec<Node> },
-    IfBlock { condition: Vec<Node>, children: Vec<Node> },
-    ElseIfBlock { condition: Vec<Node>, children: Vec<Node> },
-    ElseBlock { children: Vec<Node> },
-    Expression { children: Vec<Node> },
-    ComparisonOperator { operator: String, children: Vec<Node> },
-    MathExpression {name: String, children: Vec<Node> },
-    MathAdd {children: Vec<Node> },
-    FunctionCall { name: String, children: Vec<Node> },
-    VariableDefine { children: Vec<Node> },
-    Number { value: i32 },
-    Bool { value: bool },
-    Identifier { value: String },
-    String { value: String },
-    Null,
-  }
-  
+
+  // The parsers build the tree types in `ast`: a `Program` of items, made of statements and expressions.
+
   // Here is the grammar, for your reference:
-  
-  pub fn identifier(input: &str) -> IResult<&str, Node> {
-    let (input, result) = alphanumeric1(input)?;              // Consume at least 1 alphanumeric character. The ? automatically unwraps the result if it's okay and bails if it is an error.
-    Ok((input, Node::Identifier{ value: result.to_string()})) // Return the now partially consumed input, as well as a node with the string on it.
+
+  // trivia = {whitespace | line_comment | block_comment}
+  // Trivia may appear anywhere whitespace is allowed. A run of doc comments directly in front of `fn`
+  // is left alone so that function_definition can attach it to the function.
+  pub fn trivia(input: &str) -> IResult<&str, ()> {
+    let (input, _) = many0(alt((multispace1, block_comment, preceded(not(fn_docs_ahead), line_comment))))(input)?;
+    Ok((input, ()))
+  }
+
+  // line_comment = "//", {any character except newline}
+  pub fn line_comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(tag("//"), take_till(|c| c == '\n')))(input)
+  }
+
+  // block_comment = "/*", {block_comment | any character}, "*/"
+  pub fn block_comment(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+      tag("/*"),
+      many0(alt((block_comment, recognize(preceded(not(tag("*/")), anychar))))),
+      tag("*/"),
+    )))(input)
+  }
+
+  // doc_comment = "///", {any character except newline}
+  // The `\r` of a `\r\n` line ending isn't part of the text; a `\r` on its own is.
+  pub fn doc_comment(input: &str) -> IResult<&str, &str> {
+    let (input, _) = tag("///")(input)?;
+    let (input, text) = take_till(|c| c == '\n')(input)?;
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    Ok((input, text.strip_prefix(' ').unwrap_or(text)))
+  }
+
+  fn fn_docs_ahead(input: &str) -> IResult<&str, &str> {
+    peek(recognize(pair(many1(pair(doc_comment, multispace0)), keyword("fn"))))(input)
+  }
+
+  // Words with a meaning of their own in the grammar; these can't be used as identifiers.
+  pub const RESERVED_WORDS: [&str; 16] = ["fn", "let", "return", "if", "else", "match", "true", "false", "import", "struct", "impl", "enum", "throw", "try", "catch", "_"];
+
+  // keyword = word, ? not followed by a letter, digit or "_" ?
+  pub fn keyword<'a>(word: &'static str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    move |input| terminated(tag(word), not(satisfy(|c| c.is_alphanumeric() || c == '_')))(input)
+  }
+
+  // identifier = (letter | "_"), {letter | digit | "_"} ; excluding reserved words
+  pub fn identifier(input: &str) -> IResult<&str, String> {
+    let name = recognize(pair(alt((alpha1, tag("_"))), many0(alt((alphanumeric1, tag("_"))))));
+    let (input, result) = verify(name, |s: &str| !RESERVED_WORDS.contains(&s))(input)?; // Consume a name that isn't a reserved word. The ? automatically unwraps the result if it's okay and bails if it is an error.
+    Ok((input, result.to_string())) // Return the now partially consumed input, as well as the name.
   }
-  
+
+  // function_name = identifier, {".", identifier} ; a function in an imported module is `module.name`
+  pub fn function_name(input: &str) -> IResult<&str, &str> {
+    recognize(pair(identifier, many0(preceded(tag("."), identifier))))(input)
+  }
+
   // number (i32) := {digit};
-  pub fn number(input: &str) -> IResult<&str, Node> {
-    let (input, result) = digit1(input)?;                     // Consume at least 1 digit 0-9
-    let number = result.parse::<i32>().unwrap();              // Parse the string result into a usize
-    Ok((input, Node::Number{ value: number}))                 // Return the now partially consumed input with a number as well
-  }
-  
-  pub fn boolean(input: &str) -> IResult<&str, Node> {
-    let (input, result) = alt((tag("true"),tag("false")))(input)?;
+  // Digits that don't fit in an i32 aren't a number.
+  pub fn number(input: &str) -> IResult<&str, Expr> {
+    let (input, number) = map_res(digit1, str::parse::<i32>)(input)?;  // Consume at least 1 digit 0-9 and parse them into an i32
+    Ok((input, Expr::Number(number)))                                 // Return the now partially consumed input with a number as well
+  }
+
+  pub fn boolean(input: &str) -> IResult<&str, Expr> {
+    let (input, result) = alt((keyword("true"),keyword("false")))(input)?;
     let bool_value = if result == "true" {true} else {false};
-    Ok((input, Node::Bool{ value: bool_value}))
+    Ok((input, Expr::Bool(bool_value)))
   }
-  
-  pub fn string(input: &str) -> IResult<&str, Node> {
+
+  pub fn string(input: &str) -> IResult<&str, Expr> {
     let (input, _) = tag("\"")(input)?;
     let (input, string) = many1(alt((alphanumeric1,tag(" "))))(input)?;
     let (input, _) = tag("\"")(input)?;
-    Ok((input, Node::String{ value: string.join("")}))
+    Ok((input, Expr::String(string.join(""))))
   }
-  
-  pub fn function_call(input: &str) -> IResult<&str, Node> {
-    let (input, name) = identifier(input)?;
-    let call_name = match name {
-      Node::Identifier{ value } => value,
-      _ => String::from(""),
-    };
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+
+  // literal = number | boolean | string
+  pub fn literal(input: &str) -> IResult<&str, Expr> {
+    alt((number, boolean, string))(input)
+  }
+
+  pub fn function_call(input: &str) -> IResult<&str, Expr> {
+    let (input, name) = function_name(input)?;
+    let call_name = name.to_string();
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("(")(input)?;
-    let (input, args) = many0(arguments)(input)?;
+    let (input, args) = opt(arguments)(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag(")")(input)?;
-    Ok((input, Node::FunctionCall{name: call_name, children: args}))   
-  } 
-
-  pub fn function_definition(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, _) = tag("fn ")(input)?;
-    let (input, _) = many0(space1)(input)?;
-    let (input, function_name) = identifier(input)?;
+    Ok((input, Expr::Call{name: call_name, args: args.unwrap_or_default()}))
+  }
+
+  // function_definition = {doc_comment}, "fn", identifier, "(", [parameters], ")", "{", {function_statement}, "}"
+  pub fn function_definition(input: &str) -> IResult<&str, FnDecl> {
+    let (input, _) = trivia(input)?;
+    let (input, doc) = many0(terminated(doc_comment, multispace0))(input)?;
+    let (input, _) = keyword("fn")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, name) = identifier(input)?;
     let (input, _) = tag("(")(input)?;
-    let (input, mut args) = many0(arguments)(input)?;
+    let (input, params) = opt(parameters)(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag(")")(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("{")(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, mut statements) = many1(alt((if_expression,statement)))(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, body) = many1(function_statement)(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("}")(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let mut children = vec![function_name];
-    children.append(&mut args);
-    children.append(&mut statements);
-    Ok((input, Node::FunctionDefine{ children: children }))   
-  }
-
-  pub fn function_return(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("return ")(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, return_value) = alt((function_call, expression))(input)?;
-    Ok((input, Node::FunctionReturn{ children: vec![return_value]}))
-  }
-  
-  pub fn l4_infix(input: &str) -> IResult<&str, Node>{ // parenthesis
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, FnDecl{ doc: doc.join("\n"), name, params: params.unwrap_or_default(), body }))
+  }
+
+  // parameters = identifier, {",", identifier}
+  pub fn parameters(input: &str) -> IResult<&str, Vec<String>> {
+    let (input, _) = trivia(input)?;
+    let (input, first) = identifier(input)?;
+    let (input, others) = many0(preceded(tuple((trivia, tag(","), trivia)), identifier))(input)?;
+    Ok((input, std::iter::once(first).chain(others).collect()))
+  }
+
+  // function_statement = if_expression | match_expression | try_catch | statement
+  // What a function body, a try block or a match arm block is made of.
+  pub fn function_statement(input: &str) -> IResult<&str, Stmt> {
+    alt((map(if_expression, Stmt::If), map(match_expression, Stmt::Match), try_catch, statement))(input)
+  }
+
+  pub fn function_return(input: &str) -> IResult<&str, Stmt> {
+    let (input, _) = keyword("return")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, return_value) = expression(input)?;
+    Ok((input, Stmt::Return(return_value)))
+  }
+
+  pub fn l4_infix(input: &str) -> IResult<&str, Expr>{ // parenthesis
+    let(input, _) = trivia(input)?;
     let(input, _) = tag("(")(input)?;
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+    let(input, _) = trivia(input)?;
     let(input, expr) = expression(input)?;
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+    let(input, _) = trivia(input)?;
     let(input, _) = tag(")")(input)?;
     Ok((input, expr))
   }
-  pub fn l4(input: &str) -> IResult<&str, Node>{
-    alt((l4_infix, number, identifier, function_call))(input)
+  pub fn l4(input: &str) -> IResult<&str, Expr>{
+    alt((l4_infix, number, enum_variant, struct_literal, field_access, map(identifier, Expr::Identifier), function_call))(input)
   }
-  pub fn l3_infix(input: &str) -> IResult<&str, Node>{ // exponents
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
-    let(input, op) = tag("^")(input)?;
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+  pub fn l3_infix(input: &str) -> IResult<&str, (BinaryOp, Expr)>{ // exponents
+    let(input, _) = trivia(input)?;
+    let(input, op) = value(BinaryOp::Pow, tag("^"))(input)?;
+    let(input, _) = trivia(input)?;
     let(input, args) = l4(input)?;
-    Ok((input, Node::MathExpression{name: op.to_string(), children: vec![args]}))
+    Ok((input, (op, args)))
   }
-  pub fn l3(input: &str) -> IResult<&str, Node>{
-    let(input, mut head) = l4(input)?;
+  pub fn l3(input: &str) -> IResult<&str, Expr>{
+    let(input, head) = l4(input)?;
     let(input, tail) = many0(l3_infix)(input)?;
-    for n in tail{
-      match n{
-        Node::MathExpression{name, mut children} => {
-          let mut new_children = vec![head.clone()];
-          new_children.append(&mut children);
-          head = Node::MathExpression{name, children: new_children};
-        }
-        _ => ()
-      };
-    }
-    Ok((input,head))
+    Ok((input, fold_binary(head, tail)))
   }
-  pub fn l2_infix(input: &str) -> IResult<&str, Node>{ // multiplication, division
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
-    let(input, op) = alt((tag("*"),tag("/")))(input)?;
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+  pub fn l2_infix(input: &str) -> IResult<&str, (BinaryOp, Expr)>{ // multiplication, division
+    let(input, _) = trivia(input)?;
+    let(input, op) = alt((value(BinaryOp::Mul, tag("*")), value(BinaryOp::Div, tag("/"))))(input)?;
+    let(input, _) = trivia(input)?;
     let(input, args) = l3(input)?;
-    Ok((input, Node::MathExpression{name: op.to_string(), children: vec![args]}))
+    Ok((input, (op, args)))
   }
-  pub fn l2(input: &str) -> IResult<&str, Node>{
-    let(input, mut head) = l3(input)?;
+  pub fn l2(input: &str) -> IResult<&str, Expr>{
+    let(input, head) = l3(input)?;
     let(input, tail) = many0(l2_infix)(input)?;
-    for n in tail{
-      match n{
-        Node::MathExpression{name, mut children} => {
-          let mut new_children = vec![head.clone()];
-          new_children.append(&mut children);
-          head = Node::MathExpression{name, children: new_children};
-        }
-        _ => ()
-      };
-    }
-    Ok((input,head))
+    Ok((input, fold_binary(head, tail)))
   }
-  pub fn l1_infix(input: &str) -> IResult<&str, Node>{ // addition, subtraction
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
-    let(input, op) = alt((tag("+"),tag("-")))(input)?;
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+  pub fn l1_infix(input: &str) -> IResult<&str, (BinaryOp, Expr)>{ // addition, subtraction
+    let(input, _) = trivia(input)?;
+    let(input, op) = alt((value(BinaryOp::Add, tag("+")), value(BinaryOp::Sub, tag("-"))))(input)?;
+    let(input, _) = trivia(input)?;
     let(input, args) = l2(input)?;
-    Ok((input, Node::MathExpression{name: op.to_string(), children: vec![args]}))
+    Ok((input, (op, args)))
   }
-  pub fn l1(input: &str) -> IResult<&str, Node>{
-    let(input, mut head) = l2(input)?;
+  pub fn l1(input: &str) -> IResult<&str, Expr>{
+    let(input, head) = l2(input)?;
     let(input, tail) = many0(l1_infix)(input)?;
-    for n in tail {
-      match n {
-        Node::MathExpression{name, mut children} => {
-          let mut new_children = vec![head.clone()];
-          new_children.append(&mut children);
-          head = Node::MathExpression{name, children: new_children};
-        }
-        _ => ()
-      };
-    }
-    Ok((input,head))
+    Ok((input, fold_binary(head, tail)))
+  }
+
+  // Every level is left-associative: `1 - 2 - 3` is `(1 - 2) - 3`.
+  fn fold_binary(head: Expr, tail: Vec<(BinaryOp, Expr)>) -> Expr {
+    tail.into_iter().fold(head, |lhs, (op, rhs)| Expr::Binary{ op, lhs: Box::new(lhs), rhs: Box::new(rhs) })
   }
 
   // math_expression = value , { ("+" | "-") , value } ;
-  pub fn math_expression(input: &str) -> IResult<&str, Node> {
-    let(input, _) = many0(alt((space1, line_ending)))(input)?;
+  pub fn math_expression(input: &str) -> IResult<&str, Expr> {
+    let(input, _) = trivia(input)?;
     l1(input)
   }
-  pub fn expression(input: &str) -> IResult<&str, Node> {
-    let (input, result) = alt((comparison_operator,boolean,function_call, math_expression, number, string, identifier))(input)?;
-    Ok((input, Node::Expression{ children: vec![result]}))   
+  pub fn expression(input: &str) -> IResult<&str, Expr> {
+    alt((throw_expression,map(match_expression, |m| Expr::Match(Box::new(m))),comparison_or_operand))(input)
   }
-  
-  pub fn statement(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, result) = alt((function_return,function_call,variable_define))(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
+
+  pub fn statement(input: &str) -> IResult<&str, Stmt> {
+    let (input, _) = trivia(input)?;
+    let (input, result) = alt((function_return,map(thrown_value, Stmt::Throw),map(function_call, Stmt::Expr),variable_define,field_assign))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag(";")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    Ok((input, Node::Statement{ children: vec![result]}))   
+    let (input, _) = trivia(input)?;
+    Ok((input, result))
   }
-  
-  pub fn variable_define(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("let ")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+
+  pub fn variable_define(input: &str) -> IResult<&str, Stmt> {
+    let (input, _) = keyword("let")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, name) = identifier(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("=")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, value) = expression(input)?;
+    Ok((input, Stmt::Let{ name, value }))
+  }
+
+  // throw_expression = "throw", expression
+  pub fn throw_expression(input: &str) -> IResult<&str, Expr> {
+    map(thrown_value, |value| Expr::Throw(Box::new(value)))(input)
+  }
+
+  // The value after `throw`.
+  fn thrown_value(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = keyword("throw")(input)?;
+    let (input, _) = trivia(input)?;
+    expression(input)
+  }
+
+  // try_catch = "try", try_block, "catch", identifier, try_block
+  pub fn try_catch(input: &str) -> IResult<&str, Stmt> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("try")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, body) = try_block(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("catch")(input)?;
+    let (input, _) = trivia(input)?;
     let (input, variable) = identifier(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, handler) = try_block(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Stmt::Try{ body, variable, handler }))
+  }
+
+  // try_block = "{", {function_statement}, "}"
+  pub fn try_block(input: &str) -> IResult<&str, Vec<Stmt>> {
+    let (input, _) = tag("{")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, statements) = many1(function_statement)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    Ok((input, statements))
+  }
+
+  // field_assign = field_access, "=", expression
+  pub fn field_assign(input: &str) -> IResult<&str, Stmt> {
+    let (input, target) = field_access(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("=")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, expression) = expression(input)?;
-    Ok((input, Node::VariableDefine{ children: vec![variable, expression]}))   
+    let (input, _) = trivia(input)?;
+    let (input, value) = expression(input)?;
+    Ok((input, Stmt::Assign{ target, value }))
+  }
+
+  // field_access = identifier, ".", identifier, {".", identifier} ; not followed by "(", which makes it a call
+  pub fn field_access(input: &str) -> IResult<&str, Expr> {
+    let (input, object) = identifier(input)?;
+    let (input, fields) = many1(preceded(tag("."), identifier))(input)?;
+    let (input, _) = not(pair(trivia, tag("(")))(input)?;
+    let access = fields.into_iter().fold(Expr::Identifier(object), |object, field| Expr::Field{ object: Box::new(object), field });
+    Ok((input, access))
+  }
+
+  // struct_literal = identifier, "{", [field_value, {",", field_value}, [","]], "}"
+  pub fn struct_literal(input: &str) -> IResult<&str, Expr> {
+    let (input, name) = identifier(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("{")(input)?;
+    let (input, first) = opt(field_value)(input)?;
+    let (input, others) = match first {
+      Some(_) => many0(preceded(pair(trivia, tag(",")), field_value))(input)?,
+      None => (input, vec![]),
+    };
+    let (input, _) = trivia(input)?;
+    let (input, _) = opt(tag(","))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    Ok((input, Expr::Struct{ name, fields: first.into_iter().chain(others).collect()}))
   }
-  
-  pub fn arguments(input: &str) -> IResult<&str, Node> {
+
+  // field_value = identifier, ":", expression
+  pub fn field_value(input: &str) -> IResult<&str, (String, Expr)> {
+    let (input, _) = trivia(input)?;
+    let (input, field) = identifier(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = terminated(tag(":"), not(tag(":")))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, value) = expression(input)?;
+    Ok((input, (field, value)))
+  }
+
+  // arguments = expression, {",", expression}
+  pub fn arguments(input: &str) -> IResult<&str, Vec<Expr>> {
+    let (input, _) = trivia(input)?;
     let (input, arg) = expression(input)?;
     let (input, mut others) = many0(other_arg)(input)?;
     let mut args = vec![arg];
     args.append(&mut others);
-    Ok((input, Node::FunctionArguments{children: args}))
+    Ok((input, args))
   }
-  
-  pub fn other_arg(input: &str) -> IResult<&str, Node> {
+
+  pub fn other_arg(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = trivia(input)?;
     let (input, _) = tag(",")(input)?;
+    let (input, _) = trivia(input)?;
     expression(input)
   }
 
   // expression, ("==", ">", "<", ">=", "<=", "!="), expression
-  pub fn comparison_operator(input: &str) -> IResult<&str, Node> {
-    // Directly calling the expression function causes a Stack Overflow, so I have to manually check for each thing considered an expression
-    let (input, exp1) = alt((boolean,function_call, math_expression, number, string, identifier))(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, op) = alt((tag(">="),tag("<="),tag("<"),tag(">"),tag("=="),tag("!=")))(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, exp2) = alt((boolean,function_call, math_expression, number, string, identifier))(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    Ok((input, Node::ComparisonOperator{ operator: op.to_string(), children: vec![exp1, exp2]}))
+  pub fn comparison_operator(input: &str) -> IResult<&str, Expr> {
+    let (input, exp1) = comparison_operand(input)?;
+    let (input, (op, exp2)) = comparison_rest(input)?;
+    Ok((input, Expr::Binary{ op, lhs: Box::new(exp1), rhs: Box::new(exp2) }))
   }
 
-  // if_block, [{elseif_block}], [else_block]
-  pub fn if_expression(input: &str) -> IResult<&str, Node> {
-    let (input, if_blk) = if_block(input)?;
-    let (input, elseif_blk) = many0(else_if_block)(input)?;
-    let (input, else_blk) = opt(else_block)(input)?;
-    
-    let mut blocks: Vec<Node> = vec![if_blk.clone()];
-
-    blocks.extend(elseif_blk);
-
-    match &else_blk {
-      Some(val) => {
-        blocks.push(val.clone());
-      }
-      _ => ()
+  // A comparison, or its left side alone if no operator follows. The left side is parsed only once:
+  // trying a whole comparison and then the operand on its own would parse every level of nested
+  // parentheses twice, which takes time exponential in their depth.
+  pub fn comparison_or_operand(input: &str) -> IResult<&str, Expr> {
+    let (input, exp1) = comparison_operand(input)?;
+    let (input, rest) = opt(comparison_rest)(input)?;
+    match rest {
+      Some((op, exp2)) => Ok((input, Expr::Binary{ op, lhs: Box::new(exp1), rhs: Box::new(exp2) })),
+      None => Ok((input, exp1)),
     }
-    Ok((input, Node::IfExpression{ children: blocks }))
-  }
-  // if_block = "if ", conditional_op, "{", {statement}, "}"
-  pub fn if_block(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, _) = tag("if ")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, boolval) = alt((comparison_operator,identifier))(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+  }
+
+  // Directly calling the expression function causes a Stack Overflow, so I have to manually check for each thing considered an expression
+  fn comparison_operand(input: &str) -> IResult<&str, Expr> {
+    alt((boolean,function_call, math_expression, number, string, map(identifier, Expr::Identifier)))(input)
+  }
+
+  // The operator and right side of a comparison.
+  fn comparison_rest(input: &str) -> IResult<&str, (BinaryOp, Expr)> {
+    let (input, _) = trivia(input)?;
+    let (input, op) = map_opt(alt((tag(">="),tag("<="),tag("<"),tag(">"),tag("=="),tag("!="))), BinaryOp::from_symbol)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, exp2) = comparison_operand(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, (op, exp2)))
+  }
+
+  // condition = let_condition | comparison_operator | boolean | "(", expression, ")" | identifier
+  pub fn condition(input: &str) -> IResult<&str, Expr> {
+    alt((let_condition, comparison_operator, boolean, l4_infix, map(identifier, Expr::Identifier)))(input)
+  }
+
+  // let_condition = "let", pattern, "=", expression
+  pub fn let_condition(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = keyword("let")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, pattern) = pattern(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("=")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, value) = expression(input)?;
+    Ok((input, Expr::Let{ pattern, value: Box::new(value) }))
+  }
+
+  // if_block, [{elseif_block}], [else_block]
+  pub fn if_expression(input: &str) -> IResult<&str, If> {
+    let (input, (cond, then)) = if_block(input)?;
+    let (input, elifs) = many0(else_if_block)(input)?;
+    let (input, else_) = opt(else_block)(input)?;
+    Ok((input, If{ cond, then, elifs, else_ }))
+  }
+  // if_block = "if", condition, "{", {statement}, "}"
+  pub fn if_block(input: &str) -> IResult<&str, (Expr, Vec<Stmt>)> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("if")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, boolval) = condition(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("{")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, statements) = many1(statement)(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("}")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
 
-    Ok((input, Node::IfBlock{ condition: vec![boolval], children: statements}))
+    Ok((input, (boolval, statements)))
   }
-  // elseif_block = "else if ", conditional_op, "{", {statement}, "}"
-  pub fn else_if_block(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, _) = tag("else if ")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, boolval) = alt((comparison_operator,identifier))(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+  // elseif_block = "else", "if", condition, "{", {statement}, "}"
+  pub fn else_if_block(input: &str) -> IResult<&str, (Expr, Vec<Stmt>)> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("else")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("if")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, boolval) = condition(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("{")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, statements) = many1(statement)(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("}")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    Ok((input, Node::ElseIfBlock{ condition: vec![boolval], children: statements}))
-  }
-  // else_block = "else ", "{", {statement}, "}"
-  pub fn else_block(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    let (input, _) = tag("else ")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, (boolval, statements)))
+  }
+  // else_block = "else", "{", {statement}, "}"
+  pub fn else_block(input: &str) -> IResult<&str, Vec<Stmt>> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("else")(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("{")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
     let (input, statements) = many1(statement)(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    let (input, _) = trivia(input)?;
+
+    Ok((input, statements))
+  }
+
+  // match_expression = "match", expression, "{", match_arm, {match_arm}, "}"
+  pub fn match_expression(input: &str) -> IResult<&str, Match> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("match")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, scrutinee) = expression(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("{")(input)?;
+    let (input, arms) = many1(match_arm)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Match{ scrutinee, arms }))
+  }
+  // match_arm = pattern, {"|", pattern}, ["if", condition], "=>", (match_block | expression), [","]
+  pub fn match_arm(input: &str) -> IResult<&str, MatchArm> {
+    let (input, _) = trivia(input)?;
+    let (input, first) = pattern(input)?;
+    let (input, others) = many0(other_pattern)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, guard) = opt(match_guard)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("=>")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, body) = alt((map(match_block, ArmBody::Block), map(expression, ArmBody::Expr)))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = opt(tag(","))(input)?;
+    let mut patterns = vec![first];
+    patterns.extend(others);
+    Ok((input, MatchArm{ patterns, guard, body }))
+  }
+  // pattern = "_" | number | boolean | string | variant_pattern | identifier
+  pub fn pattern(input: &str) -> IResult<&str, Pattern> {
+    alt((wildcard, map_opt(literal, literal_pattern), variant_pattern, map(identifier, Pattern::Binding)))(input)
+  }
+
+  // The pattern matching exactly the value of a literal.
+  pub fn literal_pattern(literal: Expr) -> Option<Pattern> {
+    match literal {
+      Expr::Number(value) => Some(Pattern::Number(value)),
+      Expr::Bool(value) => Some(Pattern::Bool(value)),
+      Expr::String(value) => Some(Pattern::String(value)),
+      _ => None,
+    }
+  }
+
+  // variant_pattern = identifier, "::", identifier, ["(", [pattern, {",", pattern}], ")"]
+  pub fn variant_pattern(input: &str) -> IResult<&str, Pattern> {
+    map(variant(pattern), |(name, variant, fields)| Pattern::Variant{ name, variant, fields })(input)
+  }
+
+  // enum_variant = identifier, "::", identifier, ["(", [expression, {",", expression}], ")"]
+  pub fn enum_variant(input: &str) -> IResult<&str, Expr> {
+    map(variant(expression), |(name, variant, args)| Expr::Variant{ name, variant, args })(input)
+  }
+
+  // A variant path, with its arguments or sub-patterns parsed by `item`.
+  fn variant<'a, T>(item: fn(&'a str) -> IResult<&'a str, T>) -> impl Fn(&'a str) -> IResult<&'a str, (String, String, Vec<T>)> {
+    move |input| {
+      let (input, name) = identifier(input)?;
+      let (input, _) = tag("::")(input)?;
+      let (input, variant) = identifier(input)?;
+      let (input, items) = opt(tuple((
+        pair(trivia, tag("(")),
+        opt(pair(preceded(trivia, item), many0(preceded(tuple((trivia, tag(","), trivia)), item)))),
+        pair(trivia, tag(")")),
+      )))(input)?;
+      let children = match items {
+        Some((_, Some((first, others)), _)) => std::iter::once(first).chain(others).collect(),
+        _ => vec![],
+      };
+      Ok((input, (name, variant, children)))
+    }
+  }
+  pub fn wildcard(input: &str) -> IResult<&str, Pattern> {
+    let (input, _) = keyword("_")(input)?;
+    Ok((input, Pattern::Wildcard))
+  }
+  pub fn other_pattern(input: &str) -> IResult<&str, Pattern> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("|")(input)?;
+    let (input, _) = trivia(input)?;
+    pattern(input)
+  }
+  pub fn match_guard(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = keyword("if")(input)?;
+    let (input, _) = trivia(input)?;
+    condition(input)
+  }
+  // match_block = "{", {function_statement}, "}"
+  pub fn match_block(input: &str) -> IResult<&str, Vec<Stmt>> {
+    let (input, _) = tag("{")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, statements) = many1(function_statement)(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("}")(input)?;
-    let (input, _) = many0(alt((space1, line_ending)))(input)?;
-    
-    Ok((input, Node::ElseBlock{ children: statements}))
+    Ok((input, statements))
   }
 
   // boolean, function_call, math_expression, number, string, identifier
 
-  // program = function_definition+ ;
-  pub fn program(input: &str) -> IResult<&str, Node> {
-    let (input, result) = many1(alt((if_expression,function_definition,statement,expression)))(input)?;
-    Ok((input, Node::Program{ children: result}))
+  // import_declaration = "import", (identifier | module_path), ";"
+  pub fn import_declaration(input: &str) -> IResult<&str, Item> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("import")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, import) = alt((
+      map(module_path, |path| Item::Import { name: module_name(path), path: Some(path.to_string()) }),
+      map(identifier, |name| Item::Import { name, path: None }),
+    ))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag(";")(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, import))
+  }
+
+  // module_path = '"', {any character except '"' or newline}, '"'
+  pub fn module_path(input: &str) -> IResult<&str, &str> {
+    let (input, _) = tag("\"")(input)?;
+    let (input, path) = is_not("\"\n")(input)?;
+    let (input, _) = tag("\"")(input)?;
+    Ok((input, path))
+  }
+
+  // A module imported by path is named after its file: "lib/utils.asa" is `utils`.
+  pub fn module_name(path: &str) -> String {
+    std::path::Path::new(path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+  }
+
+  // struct_definition = "struct", identifier, "{", [identifier, {",", identifier}, [","]], "}"
+  pub fn struct_definition(input: &str) -> IResult<&str, Item> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("struct")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, name) = identifier(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("{")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, first) = opt(identifier)(input)?;
+    let (input, others) = match first {
+      Some(_) => many0(preceded(tuple((trivia, tag(","), trivia)), identifier))(input)?,
+      None => (input, vec![]),
+    };
+    let (input, _) = trivia(input)?;
+    let (input, _) = opt(tag(","))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Item::Struct{ name, fields: first.into_iter().chain(others).collect()}))
+  }
+
+  // enum_definition = "enum", identifier, "{", [variant_definition, {",", variant_definition}, [","]], "}"
+  pub fn enum_definition(input: &str) -> IResult<&str, Item> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("enum")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, name) = identifier(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("{")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, first) = opt(variant_definition)(input)?;
+    let (input, others) = match first {
+      Some(_) => many0(preceded(tuple((trivia, tag(","), trivia)), variant_definition))(input)?,
+      None => (input, vec![]),
+    };
+    let (input, _) = trivia(input)?;
+    let (input, _) = opt(tag(","))(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Item::Enum{ name, variants: first.into_iter().chain(others).collect()}))
+  }
+
+  // variant_definition = identifier, ["(", [identifier, {",", identifier}], ")"]
+  pub fn variant_definition(input: &str) -> IResult<&str, VariantDecl> {
+    let (input, name) = identifier(input)?;
+    let (input, fields) = opt(tuple((
+      pair(trivia, tag("(")),
+      opt(pair(preceded(trivia, identifier), many0(preceded(tuple((trivia, tag(","), trivia)), identifier)))),
+      pair(trivia, tag(")")),
+    )))(input)?;
+    let fields = match fields {
+      Some((_, Some((first, others)), _)) => std::iter::once(first).chain(others).collect(),
+      _ => vec![],
+    };
+    Ok((input, VariantDecl{ name, fields }))
+  }
+
+  // impl_block = "impl", identifier, "{", {function_definition}, "}"
+  pub fn impl_block(input: &str) -> IResult<&str, Item> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("impl")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, name) = identifier(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("{")(input)?;
+    let (input, methods) = many0(function_definition)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Item::Impl{ name, methods }))
+  }
+
+  // test_block = "test", string, "{", {function_statement}+, "}"
+  pub fn test_block(input: &str) -> IResult<&str, Item> {
+    let (input, _) = trivia(input)?;
+    let (input, _) = keyword("test")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, name) = map_opt(string, |name| match name {
+      Expr::String(name) => Some(name),
+      _ => None,
+    })(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("{")(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, body) = many1(function_statement)(input)?;
+    let (input, _) = trivia(input)?;
+    let (input, _) = tag("}")(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Item::Test{ name, body }))
+  }
+
+  // program = {import_declaration | struct_definition | enum_definition | impl_block | test_block | if_expression | function_definition | statement | expression}+ ;
+  pub fn program(input: &str) -> IResult<&str, Program> {
+    let (input, _) = trivia(input)?;
+    let (input, items) = many1(alt((
+      import_declaration,
+      struct_definition,
+      enum_definition,
+      impl_block,
+      test_block,
+      map(if_expression, |i| Item::Stmt(Stmt::If(i))),
+      map(function_definition, Item::Fn),
+      map(statement, Item::Stmt),
+      map(expression, Item::Expr),
+    )))(input)?;
+    let (input, _) = trivia(input)?;
+    Ok((input, Program{ items }))
+  }
+
+  // Collects non-fatal diagnostics for a parsed tree. Currently this flags `match` expressions
+  // without a catch-all arm that don't cover every variant of an enum declared in the program, since
+  // a value that matches no arm is a runtime error.
+  pub fn warnings(program: &Program) -> Vec<String> {
+    let mut enums = HashMap::new();
+    for item in &program.items {
+      if let Item::Enum { name, variants } = item {
+        enums.insert(name.as_str(), variants.iter().map(|variant| variant.name.as_str()).collect());
+      }
+    }
+    let mut matches = Matches(vec![]);
+    walk_program(program, &mut matches);
+    matches.0.into_iter()
+      .filter(|m| !exhaustive(m, &enums))
+      .map(|_| "match expression has no `_` arm; values matching no arm are a runtime error".to_string())
+      .collect()
+  }
+
+  // Every match statement and expression, in source order.
+  struct Matches<'a>(Vec<&'a Match>);
+
+  impl<'a> Visitor<'a> for Matches<'a> {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+      if let Stmt::Match(m) = stmt {
+        self.0.push(m);
+      }
+    }
+    fn visit_expr(&mut self, expr: &'a Expr) {
+      if let Expr::Match(m) = expr {
+        self.0.push(m);
+      }
+    }
+  }
+
+  fn exhaustive(m: &Match, enums: &HashMap<&str, Vec<&str>>) -> bool {
+    let irrefutable = |p: &Pattern| matches!(p, Pattern::Wildcard | Pattern::Binding(_));
+    let mut covered: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut exhaustive = false;
+    for arm in m.arms.iter().filter(|arm| arm.guard.is_none()) {
+      for p in &arm.patterns {
+        match p {
+          Pattern::Variant { name, variant, fields } if fields.iter().all(irrefutable) => {
+            covered.entry(name.as_str()).or_default().push(variant.as_str());
+          },
+          p => exhaustive |= irrefutable(p),
+        }
+      }
+    }
+    exhaustive || covered.iter().any(|(name, variants)| match enums.get(name) {
+      Some(all) => all.iter().all(|variant| variants.contains(variant)),
+      None => false,
+    })
   }
-  
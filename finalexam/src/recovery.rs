@@ -0,0 +1,848 @@
+// A hand-written recursive-descent parser for Asa that keeps going after a syntax error.
+//
+// For valid programs it builds the same tree as `parser::program`. When something is wrong it records a
+// `SyntaxError` saying what it expected, skips ahead to the next statement or function boundary (`;`, `}`
+// or `fn`) and carries on, so one pass reports every error and still hands back a partial tree that
+// tooling can use. The lexical pieces (trivia, identifiers, literals, keywords) are the nom combinators
+// from `parser`; everything above them is written out by hand so it can recover.
+
+use crate::ast::{ArmBody, BinaryOp, Expr, FnDecl, If, Item, Match, MatchArm, Pattern, Program, Stmt, VariantDecl};
+use crate::parser;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+  pub message: String,
+  pub offset: usize,
+  pub line: usize,
+  pub column: usize,
+}
+
+impl fmt::Display for SyntaxError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}: {}", self.line, self.column, self.message)
+  }
+}
+
+// Parse a whole program, returning the (possibly partial) tree and every syntax error found.
+pub fn program_with_recovery(source: &str) -> (Program, Vec<SyntaxError>) {
+  let mut parser = Parser::new(source);
+  let tree = parser.program();
+  (tree, parser.errors)
+}
+
+// The line each statement of a valid program starts on, in the order `ast::walk_program` meets them,
+// leaving out match and try statements. A top-level expression counts as the `return` statement the
+// runtime makes it into. The tree itself doesn't record positions; tools pair the two up.
+pub fn statement_lines(source: &str) -> Vec<usize> {
+  let mut parser = Parser::new(source);
+  parser.program();
+  parser.statements.iter().map(|offset| source[..*offset].matches('\n').count() + 1).collect()
+}
+
+struct Parser<'a> {
+  source: &'a str,
+  rest: &'a str,
+  errors: Vec<SyntaxError>,
+  // Start offsets of statements, in pre-order.
+  statements: Vec<usize>,
+}
+
+impl<'a> Parser<'a> {
+
+  fn new(source: &'a str) -> Parser<'a> {
+    Parser { source, rest: source, errors: vec![], statements: vec![] }
+  }
+
+  // Note where a statement starts; returns a mark for `statement_at`.
+  fn statement_start(&mut self) -> (usize, usize) {
+    self.skip_trivia();
+    (self.statements.len(), self.offset())
+  }
+
+  // Record a statement that began at `mark`, ahead of any statements nested inside it.
+  fn statement_at(&mut self, mark: (usize, usize)) {
+    self.statements.insert(mark.0, mark.1);
+  }
+
+  // ---- Input helpers ----
+
+  fn offset(&self) -> usize {
+    self.source.len() - self.rest.len()
+  }
+
+  fn skip_trivia(&mut self) {
+    if let Ok((rest, _)) = parser::trivia(self.rest) {
+      self.rest = rest;
+    }
+    if self.rest.starts_with("/*") {
+      self.error("unterminated block comment".to_string());
+      self.rest = "";
+    }
+  }
+
+  fn at_end(&mut self) -> bool {
+    self.skip_trivia();
+    self.rest.is_empty()
+  }
+
+  fn peek(&mut self, symbol: &str) -> bool {
+    self.skip_trivia();
+    self.rest.starts_with(symbol)
+  }
+
+  fn eat(&mut self, symbol: &str) -> bool {
+    if self.peek(symbol) {
+      self.rest = &self.rest[symbol.len()..];
+      true
+    } else {
+      false
+    }
+  }
+
+  // Consume `symbol`, or record "expected `symbol` <context>" and carry on as if it had been there.
+  fn expect(&mut self, symbol: &str, context: &str) -> bool {
+    if self.eat(symbol) {
+      return true;
+    }
+    let found = self.found();
+    self.error(format!("expected `{}` {}, found {}", symbol, context, found));
+    false
+  }
+
+  fn peek_keyword(&mut self, word: &'static str) -> bool {
+    self.skip_trivia();
+    parser::keyword(word)(self.rest).is_ok()
+  }
+
+  fn eat_keyword(&mut self, word: &'static str) -> bool {
+    self.skip_trivia();
+    match parser::keyword(word)(self.rest) {
+      Ok((rest, _)) => {
+        self.rest = rest;
+        true
+      },
+      Err(_) => false,
+    }
+  }
+
+  // A short description of what's next in the input, for error messages.
+  fn found(&mut self) -> String {
+    self.skip_trivia();
+    if self.rest.is_empty() {
+      return "end of input".to_string();
+    }
+    let word: String = self.rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+    match word.is_empty() {
+      true => format!("`{}`", self.rest.chars().next().unwrap()),
+      false => format!("`{}`", word),
+    }
+  }
+
+  fn error(&mut self, message: String) {
+    let offset = self.offset();
+    // One error per position is enough; anything after the first is usually a knock-on effect.
+    if self.errors.last().map(|e| e.offset) == Some(offset) {
+      return;
+    }
+    let before = &self.source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    self.errors.push(SyntaxError { message, offset, line, column });
+  }
+
+  // Skip input until one of `stops` or a `fn` keyword, stepping over whole words and strings so that
+  // e.g. the `fn` in `defn` isn't mistaken for a function boundary.
+  fn skip_until(&mut self, stops: &[&str]) {
+    loop {
+      self.skip_trivia();
+      if self.rest.is_empty() || stops.iter().any(|s| self.rest.starts_with(s)) || self.peek_keyword("fn") {
+        return;
+      }
+      let word = self.rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_').map(|c| c.len_utf8()).sum::<usize>();
+      let skip = match parser::string(self.rest) {
+        Ok((rest, _)) => self.rest.len() - rest.len(),
+        Err(_) if word > 0 => word,
+        Err(_) => self.rest.chars().next().unwrap().len_utf8(),
+      };
+      self.rest = &self.rest[skip..];
+    }
+  }
+
+  // Recover from a broken statement: skip past the next `;`, or up to the `}` or `fn` that ends the block.
+  fn synchronize(&mut self) {
+    self.skip_until(&[";", "}"]);
+    self.eat(";");
+  }
+
+  // ---- Grammar ----
+
+  // program = {import_declaration | struct_definition | enum_definition | impl_block | function_definition | if_expression | statement | expression}
+  fn program(&mut self) -> Program {
+    let mut items = vec![];
+    while !self.at_end() {
+      let start = self.offset();
+      if self.peek("}") {
+        self.error("unexpected `}` outside of a block".to_string());
+        self.eat("}");
+        continue;
+      }
+      if let Some(item) = self.item() {
+        items.push(item);
+      }
+      // Always make progress, even if an item failed without consuming anything.
+      if self.offset() == start {
+        self.rest = &self.rest[self.rest.chars().next().unwrap().len_utf8()..];
+      }
+    }
+    if items.is_empty() && self.errors.is_empty() {
+      self.error("expected a function, statement or expression".to_string());
+    }
+    Program { items }
+  }
+
+  fn item(&mut self) -> Option<Item> {
+    if self.peek_keyword("import") {
+      return self.import_declaration();
+    }
+    if self.peek_keyword("struct") {
+      return self.struct_definition();
+    }
+    if self.peek_keyword("enum") {
+      return self.enum_definition();
+    }
+    if self.peek_keyword("impl") {
+      return self.impl_block();
+    }
+    if self.peek("///") || self.peek_keyword("fn") {
+      return self.function_definition().map(Item::Fn);
+    }
+    // `test` is only a keyword in front of a string.
+    if self.peek_keyword("test") && self.rest["test".len()..].trim_start().starts_with('"') {
+      return self.test_block();
+    }
+    if self.peek_keyword("if") {
+      return Some(Item::Stmt(Stmt::If(self.if_expression())));
+    }
+    if self.peek_keyword("return") || self.peek_keyword("let") {
+      return self.statement().map(Item::Stmt);
+    }
+    // A call or throw followed by `;` is a statement, as is an assignment to a field; anything else at
+    // the top level is an expression.
+    let start = self.statement_start();
+    let expression = match self.expression() {
+      Some(expression) => expression,
+      None => {
+        self.synchronize();
+        return None;
+      }
+    };
+    self.statement_at(start);
+    match expression {
+      Expr::Call { .. } if self.eat(";") => Some(Item::Stmt(Stmt::Expr(expression))),
+      Expr::Throw(value) if self.eat(";") => Some(Item::Stmt(Stmt::Throw(*value))),
+      Expr::Field { .. } if !self.peek("==") && self.eat("=") => match self.expression() {
+        Some(value) => {
+          self.expect(";", "after statement");
+          Some(Item::Stmt(Stmt::Assign { target: expression, value }))
+        },
+        None => {
+          self.synchronize();
+          None
+        },
+      },
+      expression => Some(Item::Expr(expression)),
+    }
+  }
+
+  // import_declaration = "import", (identifier | module_path), ";"
+  fn import_declaration(&mut self) -> Option<Item> {
+    self.eat_keyword("import");
+    self.skip_trivia();
+    let import = if let Ok((rest, path)) = parser::module_path(self.rest) {
+      self.rest = rest;
+      Item::Import { name: parser::module_name(path), path: Some(path.to_string()) }
+    } else if let Some(name) = self.identifier() {
+      Item::Import { name, path: None }
+    } else {
+      let found = self.found();
+      self.error(format!("expected module name or path after `import`, found {}", found));
+      self.synchronize();
+      return None;
+    };
+    self.expect(";", "after import");
+    Some(import)
+  }
+
+  // struct_definition = "struct", identifier, "{", [identifier, {",", identifier}, [","]], "}"
+  fn struct_definition(&mut self) -> Option<Item> {
+    self.eat_keyword("struct");
+    let name = match self.identifier() {
+      Some(name) => name,
+      _ => {
+        let found = self.found();
+        self.error(format!("expected struct name after `struct`, found {}", found));
+        self.skip_until(&["}"]);
+        self.eat("}");
+        return None;
+      },
+    };
+    let mut fields = vec![];
+    if self.expect("{", "after struct name") {
+      while !self.peek("}") && !self.at_end() {
+        match self.identifier() {
+          Some(field) => fields.push(field),
+          _ => {
+            let found = self.found();
+            self.error(format!("expected field name, found {}", found));
+            self.skip_until(&["}"]);
+            break;
+          },
+        }
+        if !self.eat(",") {
+          break;
+        }
+      }
+      self.expect("}", "to close struct");
+    }
+    Some(Item::Struct { name, fields })
+  }
+
+  // enum_definition = "enum", identifier, "{", [variant_definition, {",", variant_definition}, [","]], "}"
+  // variant_definition = identifier, ["(", [identifier, {",", identifier}], ")"]
+  fn enum_definition(&mut self) -> Option<Item> {
+    self.eat_keyword("enum");
+    let name = match self.identifier() {
+      Some(name) => name,
+      _ => {
+        let found = self.found();
+        self.error(format!("expected enum name after `enum`, found {}", found));
+        self.skip_until(&["}"]);
+        self.eat("}");
+        return None;
+      },
+    };
+    let mut variants = vec![];
+    if self.expect("{", "after enum name") {
+      while !self.peek("}") && !self.at_end() {
+        let variant = match self.identifier() {
+          Some(variant) => variant,
+          _ => {
+            let found = self.found();
+            self.error(format!("expected variant name, found {}", found));
+            self.skip_until(&["}"]);
+            break;
+          },
+        };
+        let mut fields = vec![];
+        if self.eat("(") {
+          while let Some(field) = self.identifier() {
+            fields.push(field);
+            if !self.eat(",") {
+              break;
+            }
+          }
+          self.expect(")", "after variant fields");
+        }
+        variants.push(VariantDecl { name: variant, fields });
+        if !self.eat(",") {
+          break;
+        }
+      }
+      self.expect("}", "to close enum");
+    }
+    Some(Item::Enum { name, variants })
+  }
+
+  // impl_block = "impl", identifier, "{", {function_definition}, "}"
+  fn impl_block(&mut self) -> Option<Item> {
+    self.eat_keyword("impl");
+    let name = match self.identifier() {
+      Some(name) => name,
+      _ => {
+        let found = self.found();
+        self.error(format!("expected struct name after `impl`, found {}", found));
+        String::new()
+      },
+    };
+    let mut methods = vec![];
+    if self.expect("{", "after struct name") {
+      while !self.peek("}") && !self.at_end() {
+        if self.peek("///") || self.peek_keyword("fn") {
+          methods.extend(self.function_definition());
+          continue;
+        }
+        let found = self.found();
+        self.error(format!("expected `fn` in impl block, found {}", found));
+        self.skip_until(&["}"]);
+      }
+      self.expect("}", "to close impl block");
+    }
+    Some(Item::Impl { name, methods })
+  }
+
+  // function_definition = {doc_comment}, "fn", identifier, "(", [identifier, {",", identifier}], ")", block
+  fn function_definition(&mut self) -> Option<FnDecl> {
+    let mut doc = vec![];
+    while self.peek("///") {
+      if let Ok((rest, text)) = parser::doc_comment(self.rest) {
+        doc.push(text.to_string());
+        self.rest = rest;
+      }
+    }
+    if !self.eat_keyword("fn") {
+      let found = self.found();
+      self.error(format!("expected `fn` after doc comment, found {}", found));
+      return None;
+    }
+    let name = match self.identifier() {
+      Some(name) => name,
+      None => {
+        let found = self.found();
+        self.error(format!("expected function name after `fn`, found {}", found));
+        self.skip_until(&["}"]);
+        self.eat("}");
+        return None;
+      }
+    };
+    let mut params = vec![];
+    if self.expect("(", "after function name") {
+      while !self.peek(")") && !self.peek("{") && !self.at_end() {
+        match self.identifier() {
+          Some(param) => params.push(param),
+          None => {
+            let found = self.found();
+            self.error(format!("expected parameter name, found {}", found));
+            self.skip_until(&[",", ")", "{"]);
+          },
+        }
+        if !self.eat(",") {
+          break;
+        }
+      }
+      self.expect(")", "after parameters");
+    }
+    let body = self.block("function body", true);
+    Some(FnDecl { doc: doc.join("\n"), name, params, body })
+  }
+
+  // test_block = "test", string, block
+  fn test_block(&mut self) -> Option<Item> {
+    self.eat_keyword("test");
+    self.skip_trivia();
+    let name = match parser::string(self.rest) {
+      Ok((rest, Expr::String(name))) => {
+        self.rest = rest;
+        name
+      },
+      _ => {
+        let found = self.found();
+        self.error(format!("expected test name after `test`, found {}", found));
+        self.skip_until(&["}"]);
+        self.eat("}");
+        return None;
+      }
+    };
+    let body = self.block("test body", true);
+    Some(Item::Test { name, body })
+  }
+
+  // block = "{", statement, {statement}, "}"
+  // Function bodies and match arms may also contain if and match expressions.
+  fn block(&mut self, what: &str, allow_expressions: bool) -> Vec<Stmt> {
+    let mut statements = vec![];
+    if !self.expect("{", &format!("to open {}", what)) {
+      return statements;
+    }
+    while !self.peek("}") && !self.at_end() && !self.peek_keyword("fn") {
+      let statement = if allow_expressions && self.peek_keyword("if") {
+        Some(Stmt::If(self.if_expression()))
+      } else if allow_expressions && self.peek_keyword("match") {
+        self.match_expression().map(Stmt::Match)
+      } else if allow_expressions && self.peek_keyword("try") {
+        self.try_catch()
+      } else {
+        self.statement()
+      };
+      statements.extend(statement);
+    }
+    if statements.is_empty() {
+      self.error(format!("expected at least one statement in {}", what));
+    }
+    self.expect("}", &format!("to close {}", what));
+    statements
+  }
+
+  // try_catch = "try", block, "catch", identifier, block
+  fn try_catch(&mut self) -> Option<Stmt> {
+    self.eat_keyword("try");
+    let body = self.block("`try` block", true);
+    if !self.eat_keyword("catch") {
+      let found = self.found();
+      self.error(format!("expected `catch` after `try` block, found {}", found));
+      return Some(Stmt::Try { body, variable: String::new(), handler: vec![] });
+    }
+    let variable = match self.identifier() {
+      Some(variable) => variable,
+      _ => {
+        let found = self.found();
+        self.error(format!("expected error variable after `catch`, found {}", found));
+        String::new()
+      },
+    };
+    let handler = self.block("`catch` block", true);
+    Some(Stmt::Try { body, variable, handler })
+  }
+
+  // statement = (function_return | throw_expression | variable_define | function_call | field_assign), ";"
+  fn statement(&mut self) -> Option<Stmt> {
+    let start = self.statement_start();
+    let statement = if self.eat_keyword("return") {
+      self.expression().map(Stmt::Return)
+    } else if self.eat_keyword("throw") {
+      self.expression().map(Stmt::Throw)
+    } else if self.eat_keyword("let") {
+      self.variable_define()
+    } else {
+      match self.function_name() {
+        Some(name) if self.peek("(") => Some(Stmt::Expr(self.call(name))),
+        Some(name) if name.contains('.') && self.eat("=") => {
+          self.expression().map(|value| Stmt::Assign { target: field_access(&name), value })
+        },
+        _ => {
+          let found = self.found();
+          self.error(format!("expected `let`, `return` or a function call, found {}", found));
+          None
+        },
+      }
+    };
+    match statement {
+      Some(statement) => {
+        self.expect(";", "after statement");
+        self.statement_at(start);
+        Some(statement)
+      },
+      None => {
+        self.synchronize();
+        None
+      },
+    }
+  }
+
+  // variable_define = "let", identifier, "=", expression
+  fn variable_define(&mut self) -> Option<Stmt> {
+    let name = match self.identifier() {
+      Some(name) => name,
+      None => {
+        let found = self.found();
+        self.error(format!("expected variable name after `let`, found {}", found));
+        return None;
+      }
+    };
+    self.expect("=", "after variable name");
+    let value = self.expression()?;
+    Some(Stmt::Let { name, value })
+  }
+
+  // if_expression = "if", condition, block, {"else", "if", condition, block}, ["else", block]
+  // An arm whose condition doesn't parse is left out once its block has been read; if that leaves no
+  // arm with a condition, the `if` is `if false`.
+  fn if_expression(&mut self) -> If {
+    let start = self.statement_start();
+    self.statement_at(start);
+    self.eat_keyword("if");
+    let mut arms = vec![];
+    let condition = self.condition();
+    let then = self.block("`if` block", false);
+    arms.extend(condition.map(|cond| (cond, then)));
+    let mut else_ = None;
+    while self.eat_keyword("else") {
+      if self.eat_keyword("if") {
+        let condition = self.condition();
+        let body = self.block("`else if` block", false);
+        arms.extend(condition.map(|cond| (cond, body)));
+      } else {
+        else_ = Some(self.block("`else` block", false));
+        break;
+      }
+    }
+    let mut arms = arms.into_iter();
+    let (cond, then) = arms.next().unwrap_or((Expr::Bool(false), vec![]));
+    If { cond, then, elifs: arms.collect(), else_ }
+  }
+
+  // A condition is `let pattern = expression`, or a bare comparison, boolean, identifier or
+  // parenthesised expression.
+  fn condition(&mut self) -> Option<Expr> {
+    if self.eat_keyword("let") {
+      let pattern = self.pattern();
+      self.expect("=", "after pattern");
+      return match (pattern, self.expression()) {
+        (Some(pattern), Some(value)) => Some(Expr::Let { pattern, value: Box::new(value) }),
+        _ => {
+          self.skip_until(&["{", ";", "}"]);
+          None
+        },
+      };
+    }
+    let condition = self.comparison();
+    if condition.is_none() {
+      self.skip_until(&["{", ";", "}"]);
+    }
+    condition
+  }
+
+  // match_expression = "match", expression, "{", match_arm, {match_arm}, "}"
+  fn match_expression(&mut self) -> Option<Match> {
+    self.eat_keyword("match");
+    let scrutinee = self.expression()?;
+    let mut arms = vec![];
+    if !self.expect("{", "after match scrutinee") {
+      return Some(Match { scrutinee, arms });
+    }
+    while !self.peek("}") && !self.at_end() && !self.peek_keyword("fn") {
+      match self.match_arm() {
+        Some(arm) => arms.push(arm),
+        None => {
+          self.skip_until(&[",", "}"]);
+          self.eat(",");
+        },
+      }
+    }
+    if arms.is_empty() {
+      self.error("expected at least one match arm".to_string());
+    }
+    self.expect("}", "to close match expression");
+    Some(Match { scrutinee, arms })
+  }
+
+  // match_arm = pattern, {"|", pattern}, ["if", condition], "=>", (block | expression), [","]
+  fn match_arm(&mut self) -> Option<MatchArm> {
+    let mut patterns = vec![self.pattern()?];
+    while self.eat("|") {
+      patterns.push(self.pattern()?);
+    }
+    let guard = match self.eat_keyword("if") {
+      true => self.condition(),
+      false => None,
+    };
+    self.expect("=>", "after match pattern");
+    let body = match self.peek("{") {
+      true => ArmBody::Block(self.block("match arm", true)),
+      false => ArmBody::Expr(self.expression()?),
+    };
+    self.eat(",");
+    Some(MatchArm { patterns, guard, body })
+  }
+
+  // pattern = "_" | number | boolean | string | variant_pattern | identifier
+  fn pattern(&mut self) -> Option<Pattern> {
+    self.skip_trivia();
+    if self.eat_keyword("_") {
+      return Some(Pattern::Wildcard);
+    }
+    if let Some(literal) = self.literal() {
+      return parser::literal_pattern(literal);
+    }
+    if let Some(name) = self.identifier() {
+      if self.rest.starts_with("::") {
+        let (name, variant, fields) = self.variant(name, Self::pattern)?;
+        return Some(Pattern::Variant { name, variant, fields });
+      }
+      return Some(Pattern::Binding(name));
+    }
+    let found = self.found();
+    self.error(format!("expected pattern, found {}", found));
+    None
+  }
+
+  // expression = throw_expression | match_expression | comparison
+  fn expression(&mut self) -> Option<Expr> {
+    if self.eat_keyword("throw") {
+      return Some(Expr::Throw(Box::new(self.expression()?)));
+    }
+    if self.peek_keyword("match") {
+      return Some(Expr::Match(Box::new(self.match_expression()?)));
+    }
+    self.comparison()
+  }
+
+  // comparison = math, [("==" | "!=" | "<=" | ">=" | "<" | ">"), math]
+  fn comparison(&mut self) -> Option<Expr> {
+    let lhs = self.l1()?;
+    for op in [BinaryOp::Eq, BinaryOp::Ne, BinaryOp::Le, BinaryOp::Ge, BinaryOp::Lt, BinaryOp::Gt] {
+      if self.eat(op.symbol()) {
+        let rhs = self.l1()?;
+        return Some(Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) });
+      }
+    }
+    Some(lhs)
+  }
+
+  // Left-associative binary operators, one precedence level at a time (see l1-l4 in `parser`).
+  fn binary(&mut self, ops: &[BinaryOp], next: fn(&mut Self) -> Option<Expr>) -> Option<Expr> {
+    let mut head = next(self)?;
+    while let Some(&op) = ops.iter().find(|op| self.peek(op.symbol())) {
+      self.eat(op.symbol());
+      let rhs = next(self)?;
+      head = Expr::Binary { op, lhs: Box::new(head), rhs: Box::new(rhs) };
+    }
+    Some(head)
+  }
+  fn l1(&mut self) -> Option<Expr> {
+    self.binary(&[BinaryOp::Add, BinaryOp::Sub], Self::l2)
+  }
+  fn l2(&mut self) -> Option<Expr> {
+    self.binary(&[BinaryOp::Mul, BinaryOp::Div], Self::l3)
+  }
+  fn l3(&mut self) -> Option<Expr> {
+    self.binary(&[BinaryOp::Pow], Self::l4)
+  }
+
+  // l4 = "(", expression, ")" | literal | function_call | identifier
+  fn l4(&mut self) -> Option<Expr> {
+    if self.eat("(") {
+      let expression = self.expression();
+      if expression.is_none() {
+        self.skip_until(&[")", ";", "}", "{"]);
+      }
+      self.expect(")", "to close parenthesised expression");
+      return expression;
+    }
+    if let Some(literal) = self.literal() {
+      return Some(literal);
+    }
+    match self.function_name() {
+      Some(name) if self.rest.starts_with("::") => {
+        let (name, variant, args) = self.variant(name, Self::expression)?;
+        Some(Expr::Variant { name, variant, args })
+      },
+      Some(name) if self.peek("(") => Some(self.call(name)),
+      Some(name) if self.struct_literal_ahead() => self.struct_literal(name),
+      Some(name) => Some(field_access(&name)),
+      None => {
+        let found = self.found();
+        self.error(format!("expected expression, found {}", found));
+        None
+      },
+    }
+  }
+
+  // function_call = function_name, "(", [expression, {",", expression}], ")"
+  fn call(&mut self, name: String) -> Expr {
+    self.eat("(");
+    let mut args = vec![];
+    if !self.peek(")") {
+      loop {
+        match self.expression() {
+          Some(arg) => args.push(arg),
+          None => {
+            self.skip_until(&[")", ";", "}", "{"]);
+            break;
+          },
+        }
+        if !self.eat(",") {
+          break;
+        }
+      }
+    }
+    self.expect(")", "after arguments");
+    Expr::Call { name, args }
+  }
+
+  // The rest of `Enum::Variant(...)` after the enum name, with the arguments or sub-patterns parsed
+  // by `item`.
+  fn variant<T>(&mut self, name: String, item: fn(&mut Self) -> Option<T>) -> Option<(String, String, Vec<T>)> {
+    self.eat("::");
+    let variant = match self.identifier() {
+      Some(variant) => variant,
+      _ => {
+        let found = self.found();
+        self.error(format!("expected variant name after `{}::`, found {}", name, found));
+        return None;
+      },
+    };
+    let mut children = vec![];
+    if self.eat("(") {
+      while !self.peek(")") && !self.at_end() {
+        match item(self) {
+          Some(child) => children.push(child),
+          None => {
+            self.skip_until(&[")", ";", "}", "{"]);
+            break;
+          },
+        }
+        if !self.eat(",") {
+          break;
+        }
+      }
+      self.expect(")", "after variant values");
+    }
+    Some((name, variant, children))
+  }
+
+  // Whether a `{` starting a struct literal comes next, rather than a block after a condition.
+  fn struct_literal_ahead(&mut self) -> bool {
+    let rest = self.rest;
+    let ahead = self.eat("{") && (self.peek("}") || (self.identifier().is_some() && self.peek(":") && !self.peek("::")));
+    self.rest = rest;
+    ahead
+  }
+
+  // struct_literal = identifier, "{", [field_value, {",", field_value}, [","]], "}"
+  fn struct_literal(&mut self, name: String) -> Option<Expr> {
+    self.eat("{");
+    let mut fields = vec![];
+    while !self.peek("}") && !self.at_end() {
+      let field = match self.identifier() {
+        Some(field) => field,
+        _ => {
+          let found = self.found();
+          self.error(format!("expected field name, found {}", found));
+          self.skip_until(&["}", ";"]);
+          break;
+        },
+      };
+      self.expect(":", "after field name");
+      let value = self.expression()?;
+      fields.push((field, value));
+      if !self.eat(",") {
+        break;
+      }
+    }
+    self.expect("}", "to close struct literal");
+    Some(Expr::Struct { name, fields })
+  }
+
+  fn literal(&mut self) -> Option<Expr> {
+    self.skip_trivia();
+    let (rest, literal) = parser::literal(self.rest).ok()?;
+    self.rest = rest;
+    Some(literal)
+  }
+
+  // An identifier, or a dotted name like `utils.helper`.
+  fn function_name(&mut self) -> Option<String> {
+    self.skip_trivia();
+    let (rest, name) = parser::function_name(self.rest).ok()?;
+    self.rest = rest;
+    Some(name.to_string())
+  }
+
+  fn identifier(&mut self) -> Option<String> {
+    self.skip_trivia();
+    let (rest, identifier) = parser::identifier(self.rest).ok()?;
+    self.rest = rest;
+    Some(identifier)
+  }
+}
+
+// `a.b.c` as field accesses on the variable `a`.
+fn field_access(name: &str) -> Expr {
+  let mut parts = name.split('.');
+  let mut expr = Expr::Identifier(parts.next().unwrap_or(name).to_string());
+  for field in parts {
+    expr = Expr::Field { object: Box::new(expr), field: field.to_string() };
+  }
+  expr
+}
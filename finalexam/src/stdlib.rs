@@ -0,0 +1,183 @@
+// The prelude: built-in functions every Runtime starts with.
+//
+//   print(...), println(...)      write the values, separated by spaces
+//   read_line()                   the next line of input without its newline; "" at end of input
+//   read_file(path)               a file's contents
+//   write_file(path, contents)    replace a file's contents
+//   env_var(name)                 an environment variable, or false if it isn't set
+//   args()                        the program's command-line arguments, as a list of strings
+//   exit(code)                    stop the program
+//   assert(condition)             throw an `AssertionError` unless the condition is true
+//   assert_eq(left, right)        throw an `AssertionError` showing both values unless they're equal
+//
+// All but the asserts go through an `Io`, so embedders and tests can swap the real process I/O for
+// something else with `Runtime::set_io`.
+
+use crate::host::{Arity, RuntimeError};
+use crate::interpreter::{error, quoted, Runtime, Value, THROWN};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// The error `exit` stops the program with; the runtime fails every later statement with it too.
+pub const EXITED: RuntimeError = "Program exited";
+
+pub trait Io {
+  fn write(&mut self, text: &str) -> Result<(), RuntimeError>;
+  // The next line of input, without its line ending, or None at the end of input.
+  fn read_line(&mut self) -> Result<Option<String>, RuntimeError>;
+  fn read_file(&mut self, path: &str) -> Result<String, RuntimeError>;
+  fn write_file(&mut self, path: &str, contents: &str) -> Result<(), RuntimeError>;
+  fn env_var(&self, name: &str) -> Option<String>;
+  fn args(&self) -> Vec<String>;
+  fn exit(&mut self, code: i32);
+}
+
+// The real thing: stdin, stdout, the filesystem and the process environment.
+#[derive(Debug, Clone, Default)]
+pub struct StdIo {
+  pub args: Vec<String>,
+}
+
+impl Io for StdIo {
+  fn write(&mut self, text: &str) -> Result<(), RuntimeError> {
+    let mut stdout = std::io::stdout();
+    stdout.write_all(text.as_bytes()).and_then(|_| stdout.flush()).map_err(|_| "Could not write output")
+  }
+
+  fn read_line(&mut self) -> Result<Option<String>, RuntimeError> {
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+      Ok(0) => Ok(None),
+      Ok(_) => Ok(Some(line.trim_end_matches(['\n', '\r']).to_string())),
+      Err(_) => Err("Could not read input"),
+    }
+  }
+
+  fn read_file(&mut self, path: &str) -> Result<String, RuntimeError> {
+    std::fs::read_to_string(path).map_err(|_| "Could not read file")
+  }
+
+  fn write_file(&mut self, path: &str, contents: &str) -> Result<(), RuntimeError> {
+    std::fs::write(path, contents).map_err(|_| "Could not write file")
+  }
+
+  fn env_var(&self, name: &str) -> Option<String> {
+    std::env::var(name).ok()
+  }
+
+  fn args(&self) -> Vec<String> {
+    self.args.clone()
+  }
+
+  fn exit(&mut self, code: i32) {
+    let _ = std::io::stdout().flush();
+    std::process::exit(code);
+  }
+}
+
+// In-memory I/O for tests and sandboxed embedding. Output collects in `stdout`; `exit` records its
+// code instead of ending the process.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIo {
+  pub stdout: String,
+  pub stdin: VecDeque<String>,
+  pub files: HashMap<String, String>,
+  pub env: HashMap<String, String>,
+  pub args: Vec<String>,
+  pub exit_code: Option<i32>,
+}
+
+impl MemoryIo {
+  // Input is split into lines for `read_line`.
+  pub fn with_stdin(input: &str) -> MemoryIo {
+    MemoryIo { stdin: input.lines().map(String::from).collect(), ..MemoryIo::default() }
+  }
+}
+
+impl Io for MemoryIo {
+  fn write(&mut self, text: &str) -> Result<(), RuntimeError> {
+    self.stdout.push_str(text);
+    Ok(())
+  }
+
+  fn read_line(&mut self) -> Result<Option<String>, RuntimeError> {
+    Ok(self.stdin.pop_front())
+  }
+
+  fn read_file(&mut self, path: &str) -> Result<String, RuntimeError> {
+    self.files.get(path).cloned().ok_or("Could not read file")
+  }
+
+  fn write_file(&mut self, path: &str, contents: &str) -> Result<(), RuntimeError> {
+    self.files.insert(path.to_string(), contents.to_string());
+    Ok(())
+  }
+
+  fn env_var(&self, name: &str) -> Option<String> {
+    self.env.get(name).cloned()
+  }
+
+  fn args(&self) -> Vec<String> {
+    self.args.clone()
+  }
+
+  fn exit(&mut self, code: i32) {
+    self.exit_code = Some(code);
+  }
+}
+
+fn text(values: &[Value]) -> String {
+  values.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+// Throw an `AssertionError` with `message` to the script.
+fn fail(thrower: &RefCell<Option<Value>>, message: String) -> Result<Value, RuntimeError> {
+  *thrower.borrow_mut() = Some(error("AssertionError", &message));
+  Err(THROWN)
+}
+
+// Register the prelude on `runtime`, with every function doing its I/O through `io`.
+pub fn register(runtime: &mut Runtime, io: Rc<RefCell<dyn Io>>) {
+  let handle = io.clone();
+  runtime.register_fn("print", Arity::Variadic, move |args| {
+    handle.borrow_mut().write(&text(args)).map(|_| Value::Bool(true))
+  });
+  let handle = io.clone();
+  runtime.register_fn("println", Arity::Variadic, move |args| {
+    handle.borrow_mut().write(&format!("{}\n", text(args))).map(|_| Value::Bool(true))
+  });
+  let handle = io.clone();
+  runtime.register_typed("read_line", move || -> Result<String, RuntimeError> {
+    Ok(handle.borrow_mut().read_line()?.unwrap_or_default())
+  });
+  let handle = io.clone();
+  runtime.register_typed("read_file", move |path: String| handle.borrow_mut().read_file(&path));
+  let handle = io.clone();
+  runtime.register_typed("write_file", move |path: String, contents: String| {
+    handle.borrow_mut().write_file(&path, &contents).map(|_| true)
+  });
+  let handle = io.clone();
+  runtime.register_fn("env_var", Arity::Exact(1), move |args| match &args[0] {
+    Value::String(name) => Ok(handle.borrow().env_var(name).map(Value::String).unwrap_or(Value::Bool(false))),
+    _ => Err("Expected a string"),
+  });
+  let handle = io.clone();
+  runtime.register_typed("args", move || handle.borrow().args());
+  runtime.register_typed("exit", move |code: i32| -> Result<Value, RuntimeError> {
+    io.borrow_mut().exit(code);
+    Err(EXITED)
+  });
+  let thrower = runtime.thrower();
+  runtime.register_fn("assert", Arity::Exact(1), move |args| match &args[0] {
+    Value::Bool(true) => Ok(Value::Bool(true)),
+    Value::Bool(false) => fail(&thrower, "assertion failed".to_string()),
+    _ => Err("Expected a boolean"),
+  });
+  let thrower = runtime.thrower();
+  runtime.register_fn("assert_eq", Arity::Exact(2), move |args| match args[0] == args[1] {
+    true => Ok(Value::Bool(true)),
+    false => fail(&thrower, format!("assert_eq failed: left: {}, right: {}", quoted(&args[0]), quoted(&args[1]))),
+  });
+}
@@ -0,0 +1,126 @@
+// Execution tracing for the runtime: function entries and exits with timings, and how many nodes each
+// function evaluated. A finished trace renders as a flat profile table or as Chrome trace-event JSON
+// (load it in chrome://tracing or Perfetto).
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Nodes evaluated outside of any function call are counted under this name.
+pub const TOP_LEVEL: &str = "<top level>";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+  pub name: String,
+  pub calls: u64,
+  pub nodes: u64,
+  // Time spent in the function itself, excluding the functions it called.
+  pub self_time: Duration,
+  // Time from entry to exit. Recursive calls are only counted once.
+  pub total_time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+  pub name: String,
+  // "B" for a function entry, "E" for its exit, as in the trace-event format.
+  pub phase: &'static str,
+  pub time: Duration,
+}
+
+struct ActiveCall {
+  name: String,
+  entered: Duration,
+  // Time spent in calls made from this one.
+  children: Duration,
+}
+
+pub struct Tracer {
+  start: Instant,
+  events: Vec<TraceEvent>,
+  active: Vec<ActiveCall>,
+  functions: HashMap<String, FunctionProfile>,
+}
+
+impl Tracer {
+  pub fn new() -> Tracer {
+    Tracer { start: Instant::now(), events: vec![], active: vec![], functions: HashMap::new() }
+  }
+
+  fn profile_mut(&mut self, name: &str) -> &mut FunctionProfile {
+    self.functions.entry(name.to_string()).or_insert_with(|| FunctionProfile { name: name.to_string(), ..FunctionProfile::default() })
+  }
+
+  // Count one node evaluated by the innermost active function.
+  pub fn node(&mut self) {
+    let name = self.active.last().map(|call| call.name.clone()).unwrap_or_else(|| TOP_LEVEL.to_string());
+    self.profile_mut(&name).nodes += 1;
+  }
+
+  pub fn enter(&mut self, name: &str) {
+    let now = self.start.elapsed();
+    self.profile_mut(name).calls += 1;
+    self.events.push(TraceEvent { name: name.to_string(), phase: "B", time: now });
+    self.active.push(ActiveCall { name: name.to_string(), entered: now, children: Duration::ZERO });
+  }
+
+  // Leave the innermost active function.
+  pub fn exit(&mut self) {
+    let now = self.start.elapsed();
+    let call = match self.active.pop() {
+      Some(call) => call,
+      None => return,
+    };
+    let elapsed = now - call.entered;
+    let recursive = self.active.iter().any(|outer| outer.name == call.name);
+    let profile = self.profile_mut(&call.name);
+    profile.self_time += elapsed.saturating_sub(call.children);
+    if !recursive {
+      profile.total_time += elapsed;
+    }
+    if let Some(caller) = self.active.last_mut() {
+      caller.children += elapsed;
+    }
+    self.events.push(TraceEvent { name: call.name, phase: "E", time: now });
+  }
+
+  pub fn events(&self) -> &[TraceEvent] {
+    &self.events
+  }
+
+  // One entry per function, most self time first.
+  pub fn profile(&self) -> Vec<FunctionProfile> {
+    let mut profile: Vec<FunctionProfile> = self.functions.values().cloned().collect();
+    profile.sort_by(|a, b| b.self_time.cmp(&a.self_time).then_with(|| a.name.cmp(&b.name)));
+    profile
+  }
+
+  pub fn profile_table(&self) -> String {
+    let profile = self.profile();
+    let width = profile.iter().map(|p| p.name.len()).chain(std::iter::once("function".len())).max().unwrap_or(0);
+    let mut table = format!("{:<width$}  {:>8}  {:>10}  {:>12}  {:>12}\n", "function", "calls", "nodes", "self (ms)", "total (ms)", width = width);
+    for p in profile {
+      let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+      table.push_str(&format!("{:<width$}  {:>8}  {:>10}  {:>12.3}  {:>12.3}\n", p.name, p.calls, p.nodes, ms(p.self_time), ms(p.total_time), width = width));
+    }
+    table
+  }
+
+  // The trace in Chrome's trace-event JSON format, with timestamps in microseconds.
+  pub fn chrome_trace(&self) -> String {
+    let events: Vec<serde_json::Value> = self.events.iter().map(|event| serde_json::json!({
+      "name": event.name,
+      "cat": "function",
+      "ph": event.phase,
+      "ts": event.time.as_micros() as u64,
+      "pid": 1,
+      "tid": 1,
+    })).collect();
+    serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+  }
+}
+
+impl Default for Tracer {
+  fn default() -> Tracer {
+    Tracer::new()
+  }
+}
@@ -1,7 +1,14 @@
 extern crate asalang;
+extern crate lsp_server;
 extern crate nom;
+extern crate quickcheck;
+extern crate serde_json;
+extern crate wasmi;
+extern crate wasmparser;
 
-use asalang::{program, Node, Value, start_interpreter};
+use asalang::{format_program, format_source, optimize, program, program_with_recovery, warnings, FormatError, Item, Value, start_interpreter};
+use asalang::ast::{ArmBody, BinaryOp, Expr, FnDecl, If, Match, MatchArm, Pattern, Program, Stmt, VariantDecl};
+use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
 use nom::IResult;
 
 macro_rules! test {
@@ -12,6 +19,8 @@ macro_rules! test {
         Ok((input, p)) => {
           assert_eq!(input, "");
           assert_eq!(start_interpreter(&p), $expected);
+          // Every program runs to the same result after optimizing.
+          assert_eq!(start_interpreter(&optimize(&p)), $expected);
           Ok(())
         },
         Err(e) => Err(format!("{:?}",e)),
@@ -20,6 +29,27 @@ macro_rules! test {
   )
 }
 
+// A program run by `run_with`: how it ended, the runtime it ran in and the I/O it left behind.
+struct Run {
+  result: Result<Value, &'static str>,
+  runtime: asalang::Runtime,
+  io: asalang::stdlib::MemoryIo,
+}
+
+// Parse `source` and run it in a fresh runtime with `io` as its I/O, once `setup` has configured the
+// runtime for the program.
+fn run_with(source: &str, io: asalang::stdlib::MemoryIo, setup: impl FnOnce(&mut asalang::Runtime, &Program)) -> Run {
+  let (rest, tree) = program(source).unwrap();
+  assert_eq!(rest, "");
+  let io = std::rc::Rc::new(std::cell::RefCell::new(io));
+  let mut runtime = asalang::Runtime::new();
+  runtime.set_io(io.clone());
+  setup(&mut runtime, &tree);
+  let result = runtime.start(&tree);
+  let io = io.borrow().clone();
+  Run { result, runtime, io }
+}
+
 test!(numeric, r#"123"#, Ok(Value::Number(123)));
 test!(identifier, r#"x"#, Err("Undefined variable"));
 test!(string, r#""hello world""#, Ok(Value::String("hello world".to_string())));
@@ -227,4 +257,2208 @@ fn main() {
     return 2;
   }
 }
-"#, Ok(Value::Number(2)));
\ No newline at end of file
+"#, Ok(Value::Number(2)));
+
+// Match expressions
+test!(match_literal, r#"
+fn main() {
+  let x = 2;
+  return match x {
+    1 => 10,
+    2 => 20,
+    _ => 30,
+  };
+}
+"#, Ok(Value::Number(20)));
+test!(match_alternatives, r#"
+fn main() {
+  let x = 3;
+  return match x {
+    1 => 10,
+    2 | 3 => 23,
+    _ => 30,
+  };
+}
+"#, Ok(Value::Number(23)));
+test!(match_binding_guard, r#"
+fn main() {
+  let x = 12;
+  return match x {
+    1 => 10,
+    n if n > 10 => n * 2,
+    _ => 0,
+  };
+}
+"#, Ok(Value::Number(24)));
+test!(match_guard_falls_through, r#"
+fn main() {
+  let x = 5;
+  return match x {
+    n if n > 10 => 1,
+    _ => 2,
+  };
+}
+"#, Ok(Value::Number(2)));
+test!(match_block_arms, r#"
+fn main() {
+  let x = true;
+  match x {
+    false => {
+      return 1;
+    }
+    true => {
+      let y = 40;
+      return y + 2;
+    }
+  }
+}
+"#, Ok(Value::Number(42)));
+test!(match_string, r#"
+fn main() {
+  let s = "hello";
+  let r = match s { "bye" => 1, "hello" => 2, _ => 3 };
+  return r;
+}
+"#, Ok(Value::Number(2)));
+test!(match_no_arm, r#"
+fn main() {
+  let x = 7;
+  return match x { 1 => 1, 2 => 2 };
+}
+"#, Err("No match arm matched"));
+test!(match_failed_guard_keeps_outer_variable, r#"
+fn main() {
+  let n = 5;
+  let r = match 20 { n if n > 100 => 1, _ => 0 };
+  return n;
+}
+"#, Ok(Value::Number(5)));
+test!(match_binding_shadows_outer_variable, r#"
+fn main() {
+  let n = 5;
+  let r = match 20 { n if n > 10 => n * 2, _ => 0 };
+  return r + n;
+}
+"#, Ok(Value::Number(45)));
+test!(match_nested_bindings, r#"
+fn main() {
+  let r = match 3 {
+    n => {
+      let m = match n + 1 { n if n > 3 => n * 10, _ => 0 };
+      return m + n;
+    }
+  };
+  return r;
+}
+"#, Ok(Value::Number(43)));
+test!(match_binding_ends_with_arm, r#"
+fn main() {
+  let r = match 3 { n => n * 2 };
+  return n;
+}
+"#, Err("Undefined variable"));
+
+#[test]
+fn match_exhaustiveness_warning() {
+  let (_, tree) = program(r#"fn main() { return match 1 { 1 => 1, 2 => 2 }; }"#).unwrap();
+  assert_eq!(warnings(&tree).len(), 1);
+  let (_, tree) = program(r#"fn main() { return match 1 { 1 => 1, _ => 2 }; }"#).unwrap();
+  assert!(warnings(&tree).is_empty());
+}
+
+// Comments
+test!(line_comments, r#"
+// Leading comment
+fn foo(a,b,c) {
+  let x = a + 1; 
+  // This is a comment
+  let y = bar(c - b);
+  return x + y; // Add the results
+}
+
+fn bar(a) {
+  return a + 3;
+}
+
+fn main() {
+  return foo(1,2,3);  
+}
+// Trailing comment"#, Ok(Value::Number(6)));
+test!(block_comments, r#"
+/* A block comment
+   spanning lines */
+fn main() {
+  let x = /* inline */ 5;
+  return foo( x /* first */ , 2 );
+}
+fn foo(a, b) { return a * b; }
+"#, Ok(Value::Number(10)));
+test!(nested_block_comments, r#"
+fn main() {
+  /* outer /* inner */ still a comment */
+  return 1;
+}
+"#, Ok(Value::Number(1)));
+
+#[test]
+fn unterminated_block_comment() {
+  let source = "fn main() { /* oops return 1; }";
+  assert!(program(source).is_err());
+  let (_, errors) = program_with_recovery(source);
+  assert_eq!((errors[0].message.as_str(), errors[0].offset), ("unterminated block comment", 12));
+}
+
+#[test]
+fn doc_comments_on_functions() {
+  let (rest, tree) = program(r#"
+/// Adds one.
+/// Really.
+fn inc(a) { return a + 1; }
+// not a doc comment
+fn main() { return inc(1); }
+"#).unwrap();
+  assert_eq!(rest, "");
+  let docs: Vec<String> = tree.items.iter().filter_map(|item| match item {
+    Item::Fn(f) => Some(f.doc.clone()),
+    _ => None,
+  }).collect();
+  assert_eq!(docs, vec!["Adds one.\nReally.".to_string(), "".to_string()]);
+}
+
+#[test]
+fn comments_with_carriage_returns() {
+  let (_, tree) = program("/// Adds one.\r\nfn inc(a) { return a + 1; } // a lone \r is part of the comment\r\ninc(1)").unwrap();
+  assert!(matches!(&tree.items[0], Item::Fn(f) if f.doc == "Adds one."));
+  assert_eq!(start_interpreter(&tree), Ok(Value::Number(2)));
+  assert_eq!(program_with_recovery("impl Point {\n  /// \r x\n}").1, vec![]);
+}
+
+// Keywords and identifiers
+fn parses_completely(source: &str) -> bool {
+  matches!(program(source), Ok((rest, _)) if rest.is_empty())
+}
+
+test!(return_with_paren, r#"fn main() { return(5); }"#, Ok(Value::Number(5)));
+test!(if_with_paren, r#"
+fn main() {
+  let x = true;
+  if(x){
+    return 1;
+  }else{
+    return 2;
+  }
+}
+"#, Ok(Value::Number(1)));
+test!(tab_after_let, "fn main() {\n\tlet\tx\t=\t7;\n\treturn\tx;\n}", Ok(Value::Number(7)));
+test!(underscore_identifiers, r#"
+fn add_one(some_value) { return some_value + 1; }
+fn main() {
+  let _tmp = 2;
+  return add_one(_tmp);
+}
+"#, Ok(Value::Number(3)));
+test!(keyword_prefixed_identifiers, r#"
+fn fnord(letter, iffy) { return letter + iffy; }
+fn main() {
+  let returned = 1;
+  let matches = 2;
+  return fnord(returned, matches);
+}
+"#, Ok(Value::Number(3)));
+test!(if_true_literal, r#"fn main() { if true { return 1; } }"#, Ok(Value::Number(1)));
+
+#[test]
+fn identifier_cannot_start_with_digit() {
+  assert!(!parses_completely("fn main() { let 123abc = 1; return 1; }"));
+  assert!(!parses_completely("let x = 123abc;"));
+}
+
+#[test]
+fn reserved_words_are_not_identifiers() {
+  assert!(!parses_completely("let let = 1;"));
+  assert!(!parses_completely("let match = 1;"));
+  assert!(!parses_completely("fn return() { return 1; }"));
+  assert!(!parses_completely("let _ = 1;"));
+}
+
+#[test]
+fn keywords_need_word_boundaries() {
+  assert!(!parses_completely("fn main() { returnx; }"));
+  assert!(!parses_completely("letx = 1;"));
+  assert!(!parses_completely("fnmain() { return 1; }"));
+}
+
+// Error-recovering parser
+fn same_tree_as_program(source: &str) {
+  let (rest, expected) = program(source).unwrap();
+  assert_eq!(rest, "");
+  let (tree, errors) = program_with_recovery(source);
+  assert_eq!(errors, vec![]);
+  assert_eq!(tree, expected);
+}
+
+fn error_messages(source: &str) -> Vec<String> {
+  program_with_recovery(source).1.iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn recovery_matches_program_on_valid_input() {
+  same_tree_as_program(r#"123"#);
+  same_tree_as_program(r#""hello world""#);
+  same_tree_as_program(r#"foo(a,b,c)"#);
+  same_tree_as_program(r#"let x = foo(a,b,c);"#);
+  same_tree_as_program(r#"((10+2)*6)/4"#);
+  same_tree_as_program(r#"
+/// Adds things.
+fn foo(a,b,c) {
+  let x = a + 1;
+  // This is a comment
+  let y = bar(c - b);
+  return x * y;
+}
+fn bar(a) { return a * 3; }
+fn main() {
+  let x = 2;
+  if x + 2 == y - 6 {
+    return 100;
+  } else if x == 3 {
+    return 101;
+  } else {
+    return foo(1,2,3);
+  }
+}
+"#);
+  same_tree_as_program(r#"
+fn main() {
+  return match x { 1 => 10, 2 | 3 => { return 23; }, n if n > 10 => n, _ => 0 };
+}
+"#);
+}
+
+#[test]
+fn recovery_reports_missing_paren() {
+  assert_eq!(error_messages("fn main() { return foo(1, 2; }"), vec![
+    "1:28: expected `)` after arguments, found `;`".to_string(),
+  ]);
+}
+
+#[test]
+fn recovery_collects_multiple_errors() {
+  let source = r#"
+fn broken() {
+  let = 5;
+  return 1;
+}
+fn also_broken() {
+  return 2
+}
+fn main() {
+  return 3;
+}
+"#;
+  let (tree, errors) = program_with_recovery(source);
+  let messages: Vec<String> = errors.iter().map(|e| e.message.clone()).collect();
+  assert_eq!(messages, vec![
+    "expected variable name after `let`, found `=`".to_string(),
+    "expected `;` after statement, found `}`".to_string(),
+  ]);
+  assert_eq!((errors[0].line, errors[1].line), (3, 8));
+  // The partial tree still has all three functions, with the broken statement left out.
+  assert_eq!(tree.items.len(), 3);
+  match &tree.items[0] {
+    Item::Fn(f) => assert_eq!(f.body.len(), 1),
+    other => panic!("expected a function, got {:?}", other),
+  }
+}
+
+#[test]
+fn recovery_at_function_boundary() {
+  let messages = error_messages(r#"
+fn first() {
+  return 1;
+fn second() { return 2; }
+"#);
+  assert_eq!(messages, vec!["4:1: expected `}` to close function body, found `fn`".to_string()]);
+}
+
+#[test]
+fn recovery_unterminated_comment() {
+  assert_eq!(error_messages("fn main() { return 1; } /* oops"), vec!["1:25: unterminated block comment".to_string()]);
+}
+
+// Formatter
+fn assert_formats(source: &str, expected: &str) {
+  let formatted = format_source(source).unwrap();
+  assert_eq!(formatted, expected);
+  // Formatting is idempotent and keeps the program's meaning.
+  assert_eq!(format_source(&formatted).unwrap(), formatted);
+  let (_, before) = program(source).unwrap();
+  let (_, after) = program(&formatted).unwrap();
+  assert_eq!(start_interpreter(&before), start_interpreter(&after));
+}
+
+#[test]
+fn format_layout() {
+  assert_formats(r#"fn add(a,b){return a+b;}
+fn main( ) {
+  let x=add(1,2);  if x==3 {return x;} else if x > 3 { return 0; } else {return 1;}
+}"#, r#"fn add(a, b) {
+  return a + b;
+}
+
+fn main() {
+  let x = add(1, 2);
+  if x == 3 {
+    return x;
+  } else if x > 3 {
+    return 0;
+  } else {
+    return 1;
+  }
+}
+"#);
+}
+
+#[test]
+fn format_minimal_parentheses() {
+  assert_formats(r#"fn main() {
+  let a = ((10+2)*6)/4;
+  let b = 2-(3-1);
+  let c = (2*3)+(4^2);
+  let d = 2*(3+4);
+  return a + b - c + d;
+}"#, r#"fn main() {
+  let a = (10 + 2) * 6 / 4;
+  let b = 2 - (3 - 1);
+  let c = 2 * 3 + 4 ^ 2;
+  let d = 2 * (3 + 4);
+  return a + b - c + d;
+}
+"#);
+}
+
+#[test]
+fn format_match() {
+  assert_formats(r#"fn main() {
+  let x = 2;
+  return match x { 1 => 10, 2 | 3 if x > 1 => { let y = 20; return y + x; } _ => (0) };
+}"#, r#"fn main() {
+  let x = 2;
+  return match x {
+    1 => 10,
+    2 | 3 if x > 1 => {
+      let y = 20;
+      return y + x;
+    }
+    _ => 0,
+  };
+}
+"#);
+}
+
+#[test]
+fn format_keeps_comments() {
+  assert_formats(r#"// Leading comment
+/// Doubles a number.
+fn double(a) {
+  return a * 2;   // trailing comment
+}
+fn main() {
+  let x = double(3); /* inline */
+
+
+  // Own line, after a blank line
+  return x;
+}
+// Trailing comment"#, r#"// Leading comment
+/// Doubles a number.
+fn double(a) {
+  return a * 2; // trailing comment
+}
+
+fn main() {
+  let x = double(3); /* inline */
+
+  // Own line, after a blank line
+  return x;
+}
+// Trailing comment
+"#);
+}
+
+#[test]
+fn format_rejects_syntax_errors() {
+  match format_source("fn main() { return foo(1, 2; }") {
+    Err(FormatError::Syntax(errors)) => assert_eq!(errors[0].to_string(), "1:28: expected `)` after arguments, found `;`"),
+    other => panic!("expected a syntax error, got {:?}", other),
+  }
+}
+
+// Language server
+struct LspClient {
+  connection: lsp_server::Connection,
+  server: Option<std::thread::JoinHandle<()>>,
+  next_id: i32,
+}
+
+impl LspClient {
+  fn start() -> LspClient {
+    let (server, connection) = lsp_server::Connection::memory();
+    let server = std::thread::spawn(move || asalang::lsp::serve(&server).unwrap());
+    let mut client = LspClient { connection, server: Some(server), next_id: 0 };
+    client.request("initialize", serde_json::json!({ "capabilities": {} }));
+    client.notify("initialized", serde_json::json!({}));
+    client
+  }
+
+  fn request(&mut self, method: &str, params: serde_json::Value) -> serde_json::Value {
+    self.next_id += 1;
+    let request = lsp_server::Request::new(self.next_id.into(), method.to_string(), params);
+    self.connection.sender.send(request.into()).unwrap();
+    loop {
+      match self.connection.receiver.recv().unwrap() {
+        lsp_server::Message::Response(response) => return response.result.unwrap_or(serde_json::Value::Null),
+        _ => continue,
+      }
+    }
+  }
+
+  fn notify(&self, method: &str, params: serde_json::Value) {
+    self.connection.sender.send(lsp_server::Notification::new(method.to_string(), params).into()).unwrap();
+  }
+
+  // Open a document and return the diagnostics published for it.
+  fn open(&self, text: &str) -> serde_json::Value {
+    self.notify("textDocument/didOpen", serde_json::json!({
+      "textDocument": { "uri": "file:///test.asa", "languageId": "asa", "version": 1, "text": text }
+    }));
+    self.diagnostics()
+  }
+
+  fn diagnostics(&self) -> serde_json::Value {
+    loop {
+      if let lsp_server::Message::Notification(n) = self.connection.receiver.recv().unwrap() {
+        if n.method == "textDocument/publishDiagnostics" {
+          return n.params["diagnostics"].clone();
+        }
+      }
+    }
+  }
+
+  fn at(&mut self, method: &str, line: u32, character: u32) -> serde_json::Value {
+    self.request(method, serde_json::json!({
+      "textDocument": { "uri": "file:///test.asa" },
+      "position": { "line": line, "character": character }
+    }))
+  }
+}
+
+impl Drop for LspClient {
+  fn drop(&mut self) {
+    self.request("shutdown", serde_json::Value::Null);
+    self.notify("exit", serde_json::Value::Null);
+    self.server.take().unwrap().join().unwrap();
+  }
+}
+
+const LSP_SOURCE: &str = r#"/// Adds one.
+fn inc(a) {
+  return a + 1;
+}
+fn main() {
+  let count = 2;
+  let total = inc(count);
+  return total;
+}"#;
+
+#[test]
+fn lsp_diagnostics_on_change() {
+  let client = LspClient::start();
+  assert_eq!(client.open(LSP_SOURCE), serde_json::json!([]));
+  client.notify("textDocument/didChange", serde_json::json!({
+    "textDocument": { "uri": "file:///test.asa", "version": 2 },
+    "contentChanges": [{ "text": "fn main() { return foo(1, 2; }" }]
+  }));
+  let diagnostics = client.diagnostics();
+  assert_eq!(diagnostics[0]["message"], "expected `)` after arguments, found `;`");
+  assert_eq!(diagnostics[0]["range"]["start"], serde_json::json!({ "line": 0, "character": 27 }));
+}
+
+#[test]
+fn lsp_goto_definition() {
+  let mut client = LspClient::start();
+  client.open(LSP_SOURCE);
+  // `inc` in `inc(count)` goes to the function, `count` to its `let`.
+  let function = client.at("textDocument/definition", 6, 15);
+  assert_eq!(function["range"]["start"], serde_json::json!({ "line": 1, "character": 3 }));
+  let variable = client.at("textDocument/definition", 6, 20);
+  assert_eq!(variable["range"]["start"], serde_json::json!({ "line": 5, "character": 6 }));
+}
+
+#[test]
+fn lsp_hover_shows_signature() {
+  let mut client = LspClient::start();
+  client.open(LSP_SOURCE);
+  let hover = client.at("textDocument/hover", 6, 15);
+  assert_eq!(hover["contents"]["value"], "```asa\nfn inc(a)\n```\n\nAdds one.");
+}
+
+#[test]
+fn lsp_completion_in_scope() {
+  let mut client = LspClient::start();
+  client.open(LSP_SOURCE);
+  let labels = |items: serde_json::Value| -> Vec<String> {
+    items.as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+  };
+  // Inside `inc` only its parameter is in scope; in `main` the lets defined so far.
+  assert_eq!(labels(client.at("textDocument/completion", 2, 9)), vec!["inc", "main", "a"]);
+  assert_eq!(labels(client.at("textDocument/completion", 6, 2)), vec!["inc", "main", "count"]);
+}
+
+#[test]
+fn lsp_document_symbols() {
+  let mut client = LspClient::start();
+  client.open(LSP_SOURCE);
+  let symbols = client.request("textDocument/documentSymbol", serde_json::json!({ "textDocument": { "uri": "file:///test.asa" } }));
+  let names: Vec<&str> = symbols.as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+  assert_eq!(names, vec!["inc", "main"]);
+  let variables: Vec<&str> = symbols[1]["children"].as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+  assert_eq!(variables, vec!["count", "total"]);
+  assert_eq!(symbols[1]["range"]["end"], serde_json::json!({ "line": 8, "character": 1 }));
+}
+
+const LSP_TYPES_SOURCE: &str = r#"struct Point { x, y }
+enum Shape { Dot(at), Empty }
+impl Point {
+  /// Sum of the coordinates.
+  fn sum(self) {
+    let total = self.x + self.y;
+    return total;
+  }
+}
+fn main() {
+  let p = Point { x: 1, y: 2 };
+  return p.sum();
+}"#;
+
+#[test]
+fn lsp_structs_enums_and_methods() {
+  let mut client = LspClient::start();
+  client.open(LSP_TYPES_SOURCE);
+  let symbols = client.request("textDocument/documentSymbol", serde_json::json!({ "textDocument": { "uri": "file:///test.asa" } }));
+  let names: Vec<&str> = symbols.as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+  assert_eq!(names, vec!["Point", "Shape", "Point::sum", "main"]);
+  let children = |ix: usize| -> Vec<String> {
+    symbols[ix]["children"].as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap().to_string()).collect()
+  };
+  assert_eq!(children(0), vec!["x", "y"]);
+  assert_eq!(children(1), vec!["Dot", "Empty"]);
+  assert_eq!(children(2), vec!["self", "total"]);
+  // `sum` in `p.sum()` is the method, `Point` in `Point { x: 1, y: 2 }` the struct.
+  let hover = client.at("textDocument/hover", 11, 12);
+  assert_eq!(hover["contents"]["value"], "```asa\nfn Point::sum(self)\n```\n\nSum of the coordinates.");
+  let method = client.at("textDocument/definition", 11, 12);
+  assert_eq!(method["range"]["start"], serde_json::json!({ "line": 4, "character": 5 }));
+  let hover = client.at("textDocument/hover", 10, 12);
+  assert_eq!(hover["contents"]["value"], "```asa\nstruct Point { x, y }\n```");
+  let point = client.at("textDocument/definition", 10, 12);
+  assert_eq!(point["range"]["start"], serde_json::json!({ "line": 0, "character": 7 }));
+  let labels: Vec<String> = client.at("textDocument/completion", 11, 2).as_array().unwrap().iter()
+    .map(|item| item["label"].as_str().unwrap().to_string())
+    .collect();
+  assert_eq!(labels, vec!["Point", "Shape", "sum", "main", "p"]);
+}
+
+// Debugger
+#[derive(Clone, Default)]
+struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedOutput {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0.borrow_mut().write(buf)
+  }
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+// Run `source` under the step debugger with `commands` as its input; returns the result and transcript.
+fn debug_session(source: &str, commands: &str) -> (Result<Value, &'static str>, String) {
+  let (_, tree) = program(source).unwrap();
+  let output = SharedOutput::default();
+  let debugger = asalang::debugger::StepDebugger::new(source, &tree, std::io::Cursor::new(commands.to_string()), output.clone());
+  let mut runtime = asalang::Runtime::new();
+  runtime.set_debugger(Box::new(debugger));
+  let result = runtime.start(&tree);
+  let transcript = String::from_utf8(output.0.borrow().clone()).unwrap();
+  (result, transcript)
+}
+
+const DEBUG_SOURCE: &str = r#"fn inc(a) {
+  let b = a + 1;
+  return b;
+}
+fn main() {
+  let count = 2;
+  let total = inc(count);
+  if total > 2 {
+    return total;
+  } else {
+    return 0;
+  }
+}"#;
+
+#[test]
+fn statement_lines_in_tree_order() {
+  assert_eq!(asalang::statement_lines(DEBUG_SOURCE), vec![2, 3, 6, 7, 8, 9, 11]);
+}
+
+#[test]
+fn debugger_breakpoints_and_backtrace() {
+  let (result, transcript) = debug_session(DEBUG_SOURCE, "break inc\ncontinue\nprint\nbacktrace\nbreak 11\ncontinue\n");
+  assert_eq!(result, Ok(Value::Number(3)));
+  assert_eq!(transcript, "main at line 6: let count = 2;
+(asa) breakpoint 1: function inc
+(asa) inc at line 2: let b = a + 1;
+(asa) a = Number(2)
+(asa) #0 inc at line 2: let b = a + 1;
+#1 main at line 7: let total = inc(count);
+(asa) breakpoint 2: line 11
+(asa) ");
+}
+
+#[test]
+fn debugger_step_next_finish() {
+  let (_, transcript) = debug_session(DEBUG_SOURCE, "next\nstep\nnext\nfinish\nprint total\nstep\nquit\n");
+  let stops: Vec<&str> = transcript.lines().filter(|line| line.contains(" at line ")).map(|line| line.trim_start_matches("(asa) ")).collect();
+  assert_eq!(stops, vec![
+    "main at line 6: let count = 2;",
+    "main at line 7: let total = inc(count);",
+    "inc at line 2: let b = a + 1;",
+    "inc at line 3: return b;",
+    "main at line 8: if total > 2 {",
+    "main at line 9: return total;",
+  ]);
+  assert!(transcript.contains("total = Number(3)"));
+}
+
+#[test]
+fn debugger_quit_stops_program() {
+  assert_eq!(debug_session(DEBUG_SOURCE, "quit\n").0, Err("Stopped by debugger"));
+}
+
+#[test]
+fn debugger_breaks_in_methods() {
+  let source = r#"struct Point { x, y }
+impl Point {
+  fn sum(self) {
+    let total = self.x + self.y;
+    return total;
+  }
+}
+fn main() {
+  let p = Point { x: 1, y: 2 };
+  return p.sum();
+}"#;
+  let (result, transcript) = debug_session(source, "break 5
+continue
+backtrace
+continue
+");
+  assert_eq!(result, Ok(Value::Number(3)));
+  assert!(transcript.contains("(asa) #0 Point::sum at line 5: return total;\n#1 main at line 10: return p.sum();\n"), "{}", transcript);
+}
+
+// Records the step index and statement a debugger is handed.
+type Handed = Vec<(Option<usize>, Stmt)>;
+
+struct Recorder(std::rc::Rc<std::cell::RefCell<Handed>>);
+
+impl asalang::Debugger for Recorder {
+  fn before_statement(&mut self, runtime: &asalang::Runtime, statement: &Stmt) -> Result<(), &'static str> {
+    self.0.borrow_mut().push((runtime.step(), statement.clone()));
+    Ok(())
+  }
+}
+
+#[test]
+fn debugger_is_handed_each_statement_with_its_step() {
+  let (_, tree) = program("fn main() { let a = 1; if a == 1 { let b = 2; } return a; }").unwrap();
+  let seen = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+  let mut runtime = asalang::Runtime::new();
+  runtime.set_debugger(Box::new(Recorder(seen.clone())));
+  assert_eq!(runtime.start(&tree), Ok(Value::Number(1)));
+  let mut steps = asalang::ast::Steps(vec![]);
+  asalang::ast::walk_stmts(runtime.function_body("main").unwrap(), &mut steps);
+  let expected: Handed = steps.0.into_iter().cloned().enumerate().map(|(ix, stmt)| (Some(ix), stmt)).collect();
+  assert_eq!(*seen.borrow(), expected);
+}
+
+// Tracing
+const TRACE_SOURCE: &str = r#"fn sq(a) { return a * a; }
+fn sum(a, b) {
+  let p = sq(a);
+  let q = sq(b);
+  return p + q;
+}
+fn main() {
+  let x = sum(3, 4);
+  return sum(x, 1);
+}"#;
+
+#[test]
+fn tracer_counts_calls_and_nodes() {
+  let Run { result, runtime, .. } = run_with(TRACE_SOURCE, Default::default(), |runtime, _| runtime.set_tracer(asalang::Tracer::new()));
+  assert_eq!(result, Ok(Value::Number(626)));
+  let profile = runtime.tracer().unwrap().profile();
+  let calls: Vec<(&str, u64)> = {
+    let mut calls: Vec<(&str, u64)> = profile.iter().map(|p| (p.name.as_str(), p.calls)).collect();
+    calls.sort();
+    calls
+  };
+  assert_eq!(calls, vec![("<top level>", 0), ("main", 1), ("sq", 4), ("sum", 2)]);
+  for p in &profile {
+    assert!(p.nodes > 0);
+    assert!(p.self_time <= p.total_time || p.name == "<top level>");
+  }
+  let main = profile.iter().find(|p| p.name == "main").unwrap();
+  let sum = profile.iter().find(|p| p.name == "sum").unwrap();
+  assert!(main.total_time >= sum.total_time);
+}
+
+#[test]
+fn tracer_events_nest() {
+  let runtime = run_with(TRACE_SOURCE, Default::default(), |runtime, _| runtime.set_tracer(asalang::Tracer::new())).runtime;
+  let events: Vec<String> = runtime.tracer().unwrap().events().iter().map(|e| format!("{} {}", e.phase, e.name)).collect();
+  assert_eq!(&events[..6], &["B main", "B sum", "B sq", "E sq", "B sq", "E sq"]);
+  assert_eq!(events.len(), 14);
+  assert_eq!(events.last().unwrap(), "E main");
+}
+
+#[test]
+fn tracer_chrome_trace_json() {
+  let runtime = run_with(TRACE_SOURCE, Default::default(), |runtime, _| runtime.set_tracer(asalang::Tracer::new())).runtime;
+  let trace: serde_json::Value = serde_json::from_str(&runtime.tracer().unwrap().chrome_trace()).unwrap();
+  let events = trace["traceEvents"].as_array().unwrap();
+  assert_eq!(events.len(), 14);
+  assert_eq!(events[0]["name"], "main");
+  assert_eq!(events[0]["ph"], "B");
+  assert!(events.windows(2).all(|pair| pair[0]["ts"].as_u64() <= pair[1]["ts"].as_u64()));
+}
+
+#[test]
+fn tracer_profile_table() {
+  let runtime = run_with(TRACE_SOURCE, Default::default(), |runtime, _| runtime.set_tracer(asalang::Tracer::new())).runtime;
+  let table = runtime.tracer().unwrap().profile_table();
+  let header: Vec<&str> = table.lines().next().unwrap().split_whitespace().collect();
+  assert_eq!(header, vec!["function", "calls", "nodes", "self", "(ms)", "total", "(ms)"]);
+  assert_eq!(table.lines().count(), 5);
+}
+
+// Host functions
+#[test]
+fn host_function_raw() {
+  let result = run_with("fn main() { let t = now(); return t + 1; }", Default::default(), |runtime, _| {
+    runtime.register_fn("now", asalang::Arity::Exact(0), |_| Ok(Value::Number(41)));
+  }).result;
+  assert_eq!(result, Ok(Value::Number(42)));
+}
+
+#[test]
+fn host_function_typed() {
+  let result = run_with(r#"fn main() { let n = add(2, 3); return greet("Asa", n); }"#, Default::default(), |runtime, _| {
+    runtime.register_typed("add", |a: i64, b: i64| a + b);
+    runtime.register_typed("greet", |name: String, times: i32| format!("{} x{}", name, times));
+  }).result;
+  assert_eq!(result, Ok(Value::String("Asa x5".to_string())));
+}
+
+#[test]
+fn host_function_lists() {
+  let result = run_with("fn main() { let xs = range(4); return sum(xs); }", Default::default(), |runtime, _| {
+    runtime.register_typed("range", |n: i64| (0..n).collect::<Vec<i64>>());
+    runtime.register_typed("sum", |xs: Vec<i64>| xs.iter().sum::<i64>());
+  }).result;
+  assert_eq!(result, Ok(Value::Number(6)));
+}
+
+#[test]
+fn host_function_errors() {
+  let setup = |runtime: &mut asalang::Runtime, _: &Program| {
+    runtime.register_typed("neg", |b: bool| !b);
+    runtime.register_typed("big", || 1i64 << 40);
+    runtime.register_fn("fail", asalang::Arity::Variadic, |_| Err("Host failure"));
+  };
+  assert_eq!(run_with("fn main() { return neg(1, 2); }", Default::default(), setup).result, Err("Wrong number of arguments"));
+  assert_eq!(run_with("fn main() { return neg(1); }", Default::default(), setup).result, Err("Expected a boolean"));
+  assert_eq!(run_with("fn main() { return big(); }", Default::default(), setup).result, Err("Number out of range"));
+  assert_eq!(run_with("fn main() { return fail(1, 2, 3); }", Default::default(), setup).result, Err("Host failure"));
+}
+
+#[test]
+fn script_functions_shadow_host_functions() {
+  let result = run_with("fn twice(a) { return a * 2; } fn main() { return twice(4); }", Default::default(), |runtime, _| {
+    runtime.register_typed("twice", |a: i64| a * 3);
+  }).result;
+  assert_eq!(result, Ok(Value::Number(8)));
+}
+
+// Calling into a loaded program
+#[test]
+fn call_function_by_name() {
+  let mut runtime = run_with("fn score(a, b) { return a * 10 + b; } fn main() { return score(1, 2); }", Default::default(), |_, _| ()).runtime;
+  assert_eq!(runtime.call("score", &[Value::Number(3), Value::Number(4)]), Ok(Value::Number(34)));
+  assert_eq!(runtime.call("score", &[Value::Number(5), Value::Number(6)]), Ok(Value::Number(56)));
+  assert_eq!(runtime.call("main", &[]), Ok(Value::Number(12)));
+  assert_eq!(runtime.call("score", &[Value::Number(3)]), Err("Wrong number of arguments"));
+  assert_eq!(runtime.call("missing", &[]), Err("Undefined function"));
+}
+
+#[test]
+fn introspect_functions() {
+  let mut runtime = run_with("fn score(a, b) { return a + b; } fn main() { return 1; }", Default::default(), |_, _| ()).runtime;
+  runtime.register_typed("now", || 0);
+  let functions: Vec<(String, Vec<String>, bool)> = runtime.functions().into_iter()
+    .filter(|f| ["main", "now", "score"].contains(&f.name.as_str()))
+    .map(|f| (f.name, f.params, f.host))
+    .collect();
+  assert_eq!(functions, vec![
+    ("main".to_string(), vec![], false),
+    ("now".to_string(), vec![], true),
+    ("score".to_string(), vec!["a".to_string(), "b".to_string()], false),
+  ]);
+  assert_eq!(runtime.function("score").unwrap().arity, asalang::Arity::Exact(2));
+}
+
+#[test]
+fn globals_between_calls() {
+  let mut runtime = run_with("fn scaled(a) { return a * factor; } fn shadowed(factor) { return factor; }", Default::default(), |_, _| ()).runtime;
+  assert!(runtime.call("scaled", &[Value::Number(2)]).is_err());
+  runtime.set_global("factor", Value::Number(3));
+  assert_eq!(runtime.call("scaled", &[Value::Number(2)]), Ok(Value::Number(6)));
+  runtime.set_global("factor", Value::Number(5));
+  assert_eq!(runtime.call("scaled", &[Value::Number(2)]), Ok(Value::Number(10)));
+  assert_eq!(runtime.call("shadowed", &[Value::Number(1)]), Ok(Value::Number(1)));
+  assert_eq!(runtime.global("factor"), Some(&Value::Number(5)));
+  assert_eq!(runtime.globals().len(), 1);
+}
+
+#[test]
+fn local_is_a_global_until_bound() {
+  let mut runtime = run_with("fn f() { let a = x; let x = 2; return a + x; }", Default::default(), |_, _| ()).runtime;
+  runtime.set_global("x", Value::Number(1));
+  assert_eq!(runtime.call("f", &[]), Ok(Value::Number(3)));
+  assert_eq!(runtime.call("f", &[]), Ok(Value::Number(3)));
+  assert_eq!(runtime.global("x"), Some(&Value::Number(1)));
+}
+
+// Standard library
+#[test]
+fn stdlib_print() {
+  let Run { result, io, .. } = run_with(r#"fn main() {
+  print("a", 1);
+  println(" b", true);
+  return println(args());
+}"#, asalang::stdlib::MemoryIo { args: vec!["x".to_string(), "y".to_string()], ..Default::default() }, |_, _| ());
+  assert_eq!(result, Ok(Value::Bool(true)));
+  assert_eq!(io.stdout, "a 1 b true\n[\"x\", \"y\"]\n");
+}
+
+#[test]
+fn stdlib_read_line() {
+  let Run { result, io, .. } = run_with(r#"fn main() {
+  let name = read_line();
+  println("hello", name);
+  return read_line();
+}"#, asalang::stdlib::MemoryIo::with_stdin("Asa\n"), |_, _| ());
+  assert_eq!(result, Ok(Value::String("".to_string())));
+  assert_eq!(io.stdout, "hello Asa\n");
+}
+
+#[test]
+fn stdlib_files_and_env() {
+  let mut io = asalang::stdlib::MemoryIo::default();
+  io.files.insert("input".to_string(), "data".to_string());
+  io.env.insert("USER".to_string(), "asa".to_string());
+  let Run { result, io, .. } = run_with(r#"fn main() {
+  let contents = read_file("input");
+  write_file("output", contents);
+  let user = env_var("USER");
+  return env_var("NOPE");
+}"#, io, |_, _| ());
+  assert_eq!(result, Ok(Value::Bool(false)));
+  assert_eq!(io.files.get("output"), Some(&"data".to_string()));
+  assert_eq!(run_with(r#"fn main() { return read_file("nope"); }"#, Default::default(), |_, _| ()).result, Err("Could not read file"));
+}
+
+#[test]
+fn stdlib_exit_stops_program() {
+  let Run { result, io, .. } = run_with(r#"fn main() {
+  println("before");
+  exit(3);
+  println("after");
+  return 1;
+}"#, Default::default(), |_, _| ());
+  assert_eq!(result, Err("Program exited"));
+  assert_eq!(io.exit_code, Some(3));
+  assert_eq!(io.stdout, "before\n");
+}
+
+// Modules
+// Write `files` into a fresh directory and run the program in its main.asa, loading its imports.
+fn run_modules(test: &str, files: &[(&str, &str)]) -> (asalang::Runtime, Result<Value, String>) {
+  let dir = std::env::temp_dir().join(format!("asa-modules-{}-{}", test, std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  for (name, source) in files {
+    let path = dir.join(name);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, source).unwrap();
+  }
+  let main = dir.join("main.asa");
+  let mut imported = Ok(());
+  let run = run_with(&std::fs::read_to_string(&main).unwrap(), Default::default(), |runtime, tree| {
+    imported = runtime.load_imports(tree, &main);
+  });
+  let _ = std::fs::remove_dir_all(&dir);
+  let result = match imported {
+    Ok(()) => run.result.map_err(String::from),
+    Err(error) => Err(error.to_string()),
+  };
+  (run.runtime, result)
+}
+
+#[test]
+fn import_modules() {
+  let (_, result) = run_modules("import", &[
+    ("main.asa", r#"import utils;
+import "lib/math.asa";
+
+fn main() {
+  return utils.double(math.square(3));
+}"#),
+    ("utils.asa", "fn double(x) { return times(x, 2); } fn times(a, b) { return a * b; }"),
+    ("lib/math.asa", "fn square(x) { return x * x; }"),
+  ]);
+  assert_eq!(result, Ok(Value::Number(18)));
+}
+
+#[test]
+fn module_names_stay_in_their_module() {
+  let (_, result) = run_modules("scope", &[
+    ("main.asa", "import utils; fn main() { return utils.helper(); }"),
+    ("utils.asa", "fn helper() { return main(); }"),
+  ]);
+  assert_eq!(result, Err("Undefined function".to_string()));
+  // Host functions are still visible from a module.
+  let (_, result) = run_modules("host", &[
+    ("main.asa", "import utils; fn main() { return utils.helper(); }"),
+    ("utils.asa", "fn helper() { return env_var(\"ASA TEST UNSET\"); }"),
+  ]);
+  assert_eq!(result, Ok(Value::Bool(false)));
+}
+
+#[test]
+fn modules_are_parsed_once() {
+  let (runtime, result) = run_modules("once", &[
+    ("main.asa", "import left; import right; fn main() { let a = left.get(); let b = right.get(); return a + b; }"),
+    ("left.asa", "import shared; fn get() { return shared.value(); }"),
+    ("right.asa", "import shared; fn get() { return shared.value(); }"),
+    ("shared.asa", "fn value() { return 21; }"),
+  ]);
+  assert_eq!(result, Ok(Value::Number(42)));
+  let left = runtime.function_body("left.shared.value").unwrap();
+  let right = runtime.function_body("right.shared.value").unwrap();
+  assert!(std::ptr::eq(left, right));
+}
+
+#[test]
+fn module_errors() {
+  let (_, result) = run_modules("cycle", &[
+    ("main.asa", "import a; fn main() { return a.f(); }"),
+    ("a.asa", "import b; fn f() { return 1; }"),
+    ("b.asa", "import a; fn g() { return 2; }"),
+  ]);
+  let error = result.unwrap_err();
+  assert!(error.starts_with("import cycle: "), "{}", error);
+  assert!(error.ends_with("a.asa"), "{}", error);
+  let (_, result) = run_modules("missing", &[("main.asa", "import nope; fn main() { return 1; }")]);
+  assert!(result.unwrap_err().ends_with("nope.asa: could not read module"));
+  let (_, result) = run_modules("syntax", &[
+    ("main.asa", "import broken; fn main() { return 1; }"),
+    ("broken.asa", "fn f() { let = 1; }"),
+  ]);
+  assert!(result.unwrap_err().contains("broken.asa:1:14: expected variable name"));
+  let (_, result) = run_modules("struct", &[
+    ("main.asa", "import mods; fn main() { let p = mods.mk(); return p.x; }"),
+    ("mods.asa", "struct P { x } fn mk() { return P { x: 1 }; }"),
+  ]);
+  assert!(result.unwrap_err().ends_with("mods.asa: modules can't declare `struct P`"));
+  let (_, result) = run_modules("impl", &[
+    ("main.asa", "import mods; fn main() { return 1; }"),
+    ("mods.asa", "impl P { fn get(self) { return 1; } }"),
+  ]);
+  assert!(result.unwrap_err().ends_with("mods.asa: modules can't declare `impl P`"));
+  let (_, result) = run_modules("enum", &[
+    ("main.asa", "import mods; fn main() { return mods.none(); }"),
+    ("mods.asa", "enum Maybe { Some(x), None } fn none() { return Maybe::None; }"),
+  ]);
+  assert!(result.unwrap_err().ends_with("mods.asa: modules can't declare `enum Maybe`"));
+}
+
+#[test]
+fn format_imports() {
+  assert_formats("import utils;import \"lib/math.asa\";\nfn main(){return utils.double(math.square(3));}", r#"import utils;
+import "lib/math.asa";
+
+fn main() {
+  return utils.double(math.square(3));
+}
+"#);
+}
+
+// Structs
+const POINTS: &str = r#"struct Point { x, y }
+struct Line { start, end }
+
+impl Point {
+  fn sum(self) {
+    return self.x + self.y;
+  }
+
+  fn moved(self, dx) {
+    self.x = self.x + dx;
+    return self;
+  }
+}
+
+fn main() {
+  let p = Point { x: 1, y: 2 };
+  let line = Line { start: p, end: Point { y: 5, x: 4 } };
+  line.end.y = 10;
+  let q = p.moved(2);
+  let total = line.end.sum();
+  if q.x > p.x {
+    return total + q.x;
+  } else {
+    return 0;
+  }
+}"#;
+
+test!(struct_fields_and_methods, POINTS, Ok(Value::Number(17)));
+
+test!(struct_value, r#"struct Point { x, y }
+fn main() {
+  return Point { y: "up", x: 1 };
+}"#, Ok(Value::Struct { name: "Point".to_string(), fields: vec![("x".to_string(), Value::Number(1)), ("y".to_string(), Value::String("up".to_string()))] }));
+
+#[test]
+fn struct_type_checks() {
+  let run = |body: &str| {
+    let source = format!("struct Point {{ x, y }} impl Point {{ fn get(self) {{ return self.x; }} }} fn main() {{ {} }}", body);
+    let (rest, tree) = program(&source).unwrap();
+    assert_eq!(rest, "");
+    start_interpreter(&tree)
+  };
+  assert_eq!(run("return Pointe { x: 1, y: 2 };"), Err("Undefined struct"));
+  assert_eq!(run("return Point { x: 1 };"), Err("Missing field"));
+  assert_eq!(run("return Point { x: 1, y: 2, z: 3 };"), Err("Unknown field"));
+  assert_eq!(run("let p = Point { x: 1, y: 2 }; return p.z;"), Err("Undefined field"));
+  assert_eq!(run("let p = Point { x: 1, y: 2 }; p.z = 3;"), Err("Undefined field"));
+  assert_eq!(run("let p = Point { x: 1, y: 2 }; return p.missing();"), Err("Undefined method"));
+  assert_eq!(run("let n = 1; return n.x;"), Err("Not a struct"));
+  assert_eq!(run("let p = Point { x: 7, y: 2 }; return p.get();"), Ok(Value::Number(7)));
+}
+
+#[test]
+fn struct_display() {
+  let point = Value::Struct { name: "Point".to_string(), fields: vec![("x".to_string(), Value::Number(1)), ("label".to_string(), Value::String("a".to_string()))] };
+  assert_eq!(point.to_string(), r#"Point { x: 1, label: "a" }"#);
+}
+
+#[test]
+fn struct_syntax_tooling() {
+  same_tree_as_program(POINTS);
+  assert_formats("struct Point{x,y,}\nimpl Point{fn sum(self){return self.x+self.y;}}\nfn main(){let p=Point{x:1,y:2};p.x=3;return p.sum();}", r#"struct Point { x, y }
+
+impl Point {
+  fn sum(self) {
+    return self.x + self.y;
+  }
+}
+
+fn main() {
+  let p = Point { x: 1, y: 2 };
+  p.x = 3;
+  return p.sum();
+}
+"#);
+}
+
+// Enums
+const SHAPES: &str = r#"enum Shape { Circle(r), Rect(w, h), Empty }
+enum Outcome { Ok(value), Err(code) }
+
+fn area(shape) {
+  return match shape {
+    Shape::Circle(r) => 3 * r * r,
+    Shape::Rect(w, h) => w * h,
+    Shape::Empty => 0,
+  };
+}
+
+fn checked(n) {
+  if n > 100 {
+    return Outcome::Err(1);
+  } else {
+    return Outcome::Ok(n);
+  }
+}
+
+fn main() {
+  let a = area(Shape::Rect(2, 3));
+  let b = area(Shape::Circle(2));
+  let c = area(Shape::Empty);
+  let total = a + b + c;
+  if let Outcome::Ok(value) = checked(total) {
+    return value;
+  } else {
+    return 0;
+  }
+}"#;
+
+test!(enum_variants_and_destructuring, SHAPES, Ok(Value::Number(18)));
+
+test!(enum_value, r#"enum Shape { Circle(r), Empty }
+fn main() {
+  return Shape::Circle("big");
+}"#, Ok(Value::Variant { name: "Shape".to_string(), variant: "Circle".to_string(), values: vec![Value::String("big".to_string())] }));
+
+test!(if_let_no_match, r#"enum Outcome { Ok(value), Err(code) }
+fn main() {
+  let result = Outcome::Err(7);
+  if let Outcome::Ok(value) = result {
+    return value;
+  } else if let Outcome::Err(7) = result {
+    return 70;
+  } else {
+    return 0;
+  }
+}"#, Ok(Value::Number(70)));
+
+#[test]
+fn enum_checks() {
+  let run = |body: &str| {
+    let source = format!("enum Shape {{ Circle(r), Empty }} fn main() {{ {} }}", body);
+    let (rest, tree) = program(&source).unwrap();
+    assert_eq!(rest, "");
+    start_interpreter(&tree)
+  };
+  assert_eq!(run("return Shapes::Empty;"), Err("Undefined enum"));
+  assert_eq!(run("return Shape::Square(1);"), Err("Undefined variant"));
+  assert_eq!(run("return Shape::Circle(1, 2);"), Err("Wrong number of arguments"));
+  assert_eq!(run("return Shape::Empty;"), Ok(Value::Variant { name: "Shape".to_string(), variant: "Empty".to_string(), values: vec![] }));
+  assert_eq!(Value::Variant { name: "Shape".to_string(), variant: "Circle".to_string(), values: vec![Value::Number(2)] }.to_string(), "Shape::Circle(2)");
+  assert_eq!(Value::Variant { name: "Shape".to_string(), variant: "Empty".to_string(), values: vec![] }.to_string(), "Shape::Empty");
+}
+
+#[test]
+fn enum_match_exhaustiveness() {
+  let (_, tree) = program(SHAPES).unwrap();
+  assert!(warnings(&tree).is_empty());
+  let (_, tree) = program("enum Shape { Circle(r), Empty } fn area(s) { return match s { Shape::Circle(1) => 3, Shape::Empty => 0 }; }").unwrap();
+  assert_eq!(warnings(&tree).len(), 1);
+}
+
+#[test]
+fn enum_syntax_tooling() {
+  same_tree_as_program(SHAPES);
+  assert_formats("enum Shape{Circle(r),Rect(w,h),Empty,}\nfn main(){let s=Shape::Rect(2,3);if let Shape::Rect(w,_)=s{return w;}else{return 0;}}", r#"enum Shape { Circle(r), Rect(w, h), Empty }
+
+fn main() {
+  let s = Shape::Rect(2, 3);
+  if let Shape::Rect(w, _) = s {
+    return w;
+  } else {
+    return 0;
+  }
+}
+"#);
+}
+
+// Errors
+const ERRORS: &str = r#"fn divide(a, b) {
+  return a / b;
+}
+
+fn checked(n) {
+  if n > 10 {
+    throw Error { kind: "Range", message: "too big" };
+  }
+  return n;
+}
+
+fn main() {
+  try {
+    let q = divide(1, 0);
+  } catch e {
+    let first = e.kind;
+  }
+  try {
+    let n = checked(50);
+  } catch e {
+    if e.kind == "Range" {
+      return first;
+    } else {
+      throw e;
+    }
+  }
+}"#;
+
+test!(catch_runtime_and_thrown_errors, ERRORS, Ok(Value::String("ArithmeticError".to_string())));
+
+test!(catch_any_value, r#"fn main() {
+  try {
+    throw 42;
+  } catch e {
+    let code = e + 1;
+  }
+}"#, Ok(Value::Number(43)));
+
+test!(catch_undefined_variable, r#"fn main() {
+  try {
+    return missing + 1;
+  } catch e {
+    return e.message;
+  }
+}"#, Ok(Value::String("Undefined variable".to_string())));
+
+test!(try_without_error, r#"fn main() {
+  try {
+    let x = 5;
+  } catch e {
+    let x = 0;
+  }
+}"#, Ok(Value::Number(5)));
+
+test!(arithmetic_errors, r#"fn main() {
+  try {
+    let big = 2 ^ 40;
+  } catch e {
+    return e;
+  }
+}"#, Ok(Value::Struct { name: "Error".to_string(), fields: vec![
+  ("kind".to_string(), Value::String("ArithmeticError".to_string())),
+  ("message".to_string(), Value::String("Number out of range".to_string())),
+] }));
+
+#[test]
+fn uncaught_errors_keep_the_call_stack() {
+  let (_, tree) = program(r#"fn inner(n) { throw n; }
+fn outer(n) { return inner(n); }
+fn main() { return outer(7); }"#).unwrap();
+  let mut runtime = asalang::Runtime::new();
+  assert_eq!(runtime.start(&tree), Err(asalang::interpreter::THROWN));
+  assert_eq!(runtime.thrown(), Some(&Value::Number(7)));
+  assert_eq!(runtime.error_stack(), ["main", "outer", "inner"]);
+  // A later call starts over.
+  assert_eq!(runtime.call("inner", &[Value::Bool(true)]), Err(asalang::interpreter::THROWN));
+  assert_eq!(runtime.error_stack(), ["inner"]);
+}
+
+#[test]
+fn exit_cannot_be_caught() {
+  let Run { result, io, .. } = run_with(r#"fn main() {
+  try {
+    exit(2);
+  } catch e {
+    return 1;
+  }
+}"#, Default::default(), |_, _| ());
+  assert_eq!(result, Err("Program exited"));
+  assert_eq!(io.exit_code, Some(2));
+}
+
+#[test]
+fn error_syntax_tooling() {
+  same_tree_as_program(ERRORS);
+  assert_formats("fn main(){try{throw \"bad\";}catch e{return e;}}", r#"fn main() {
+  try {
+    throw "bad";
+  } catch e {
+    return e;
+  }
+}
+"#);
+}
+
+// Tests in Asa
+const TESTS: &str = r#"fn add(a, b) {
+  return a + b;
+}
+
+test "adds numbers" {
+  let test = add(1, 2);
+  assert_eq(test, 3);
+  assert(test > 2);
+}
+
+test "wrong sum" {
+  assert_eq(add(1, 1), "two");
+}
+
+test "catches failures" {
+  try {
+    assert(false);
+  } catch e {
+    assert_eq(e.kind, "AssertionError");
+  }
+}
+"#;
+
+#[test]
+fn test_blocks_parse_and_format() {
+  same_tree_as_program(TESTS);
+  assert_formats(TESTS, TESTS);
+  let (_, tree) = program(TESTS).unwrap();
+  assert!(matches!(&tree.items[1], Item::Test { name, body } if name == "adds numbers" && body.len() == 3));
+  // Without a string after it, `test` is a name like any other.
+  let (_, tree) = program("fn test(n) { return n; } fn main() { let test = test(1); return test; }").unwrap();
+  assert_eq!(start_interpreter(&tree), Ok(Value::Number(1)));
+}
+
+#[test]
+fn run_test_blocks() {
+  let (_, tree) = program(TESTS).unwrap();
+  let run = |name: &str| {
+    let mut runtime = asalang::Runtime::new();
+    runtime.load(&tree).unwrap();
+    let result = runtime.run_test(&tree, name);
+    (result, runtime.thrown().cloned(), runtime.error_stack().to_vec())
+  };
+  assert_eq!(run("adds numbers").0, Ok(Value::Bool(true)));
+  assert_eq!(run("catches failures").0, Ok(Value::Bool(true)));
+  assert_eq!(run("missing").0, Err("Undefined test"));
+  let (result, thrown, stack) = run("wrong sum");
+  assert_eq!(result, Err(asalang::interpreter::THROWN));
+  assert_eq!(thrown, Some(Value::Struct { name: "Error".to_string(), fields: vec![
+    ("kind".to_string(), Value::String("AssertionError".to_string())),
+    ("message".to_string(), Value::String("assert_eq failed: left: 2, right: \"two\"".to_string())),
+  ] }));
+  assert_eq!(stack, ["test \"wrong sum\"", "assert_eq"]);
+  // Tests don't run with the rest of the program.
+  assert_eq!(start_interpreter(&tree), Err("Undefined function"));
+}
+
+#[test]
+fn duplicate_test_names_fail() {
+  let source = r#"test "same" { assert(true); }
+test "same" { assert(false); }
+"#;
+  let (_, tree) = program(source).unwrap();
+  assert_eq!(asalang::Runtime::new().load(&tree), Err("Duplicate test name"));
+  let path = std::env::temp_dir().join(format!("asa-duplicate-tests-{}.asa", std::process::id()));
+  std::fs::write(&path, source).unwrap();
+  let run = std::process::Command::new(env!("CARGO_BIN_EXE_asa")).arg("test").arg(&path).output().unwrap();
+  let _ = std::fs::remove_file(&path);
+  assert_eq!(run.status.code(), Some(1));
+  assert!(String::from_utf8(run.stdout).unwrap().ends_with("0 passed, 2 failed\n"));
+}
+
+#[test]
+fn assert_needs_a_boolean() {
+  let result = run_with("fn main() { return assert(1); }", Default::default(), |_, _| ()).result;
+  assert_eq!(result, Err("Expected a boolean"));
+}
+
+// C backend
+// Compile `source` to C, build it with the system C compiler and run it. Returns its exit code and
+// stdout, or None without a C compiler.
+fn run_compiled_c(test: &str, source: &str) -> Option<(i32, String)> {
+  let (_, tree) = program(source).unwrap();
+  let c = asalang::codegen_c::compile(&tree).unwrap();
+  let dir = std::env::temp_dir().join(format!("asa-c-{}-{}", test, std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("main.c"), c).unwrap();
+  let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+  let built = std::process::Command::new(cc).current_dir(&dir).args(["-std=c99", "-Wall", "-Werror", "-o", "main", "main.c"]).output();
+  let built = match built {
+    Ok(built) => built,
+    Err(_) => {
+      eprintln!("no C compiler; skipping");
+      return None;
+    },
+  };
+  assert!(built.status.success(), "{}", String::from_utf8_lossy(&built.stderr));
+  let run = std::process::Command::new(dir.join("main")).output().unwrap();
+  let _ = std::fs::remove_dir_all(&dir);
+  Some((run.status.code().unwrap(), String::from_utf8(run.stdout).unwrap()))
+}
+
+// What the compiled program should do: exit with 0 and print what the interpreter prints and returns,
+// or exit with 1 after printing what the interpreter printed before failing.
+fn interpreted_like_c(source: &str) -> (i32, String) {
+  let run = std::thread::Builder::new().stack_size(64 << 20).spawn({
+    let source = source.to_string();
+    move || {
+      let Run { result, io, .. } = run_with(&source, Default::default(), |_, _| ());
+      match result {
+        Ok(value) => (0, format!("{}{:?}\n", io.stdout, value)),
+        Err(_) => (1, io.stdout),
+      }
+    }
+  });
+  run.unwrap().join().unwrap()
+}
+
+fn assert_compiles_like_interpreter(test: &str, source: &str) {
+  if let Some(compiled) = run_compiled_c(test, source) {
+    assert_eq!(compiled, interpreted_like_c(source), "{}", source);
+  }
+}
+
+#[test]
+fn c_backend_functions_and_printing() {
+  assert_compiles_like_interpreter("fib", r#"fn fib(n) {
+  if n < 2 {
+    return n;
+  } else {
+    let a = fib(n - 1);
+    let b = fib(n - 2);
+    return a + b;
+  }
+}
+
+fn main() {
+  println("fib", fib(10), true);
+  print("no newline");
+  println();
+  return fib(15);
+}"#);
+  assert_compiles_like_interpreter("strings", r#"fn main() { return "hello world"; }"#);
+  assert_compiles_like_interpreter("expression", "2 ^ 10 - 24 / 5 * 3");
+}
+
+#[test]
+fn c_backend_conditions() {
+  assert_compiles_like_interpreter("arms", r#"fn classify(n) {
+  if n > 100 {
+    return "big";
+  } else if n == 0 {
+    return false;
+  } else {
+    return "small";
+  }
+}
+
+fn main() {
+  let a = classify(500);
+  let b = classify(7);
+  println(a, b);
+  return classify(0);
+}"#);
+  assert_compiles_like_interpreter("bool_condition", r#"fn main() {
+  let flag = true;
+  if flag == true {
+    return 1;
+  } else {
+    return 2;
+  }
+}"#);
+  assert_compiles_like_interpreter("number_condition", "fn main() { let n = 2; if n { return 1; } else { return 2; } }");
+  assert_compiles_like_interpreter("comparisons", "fn main() { let a = 3 >= 3; let b = 2 != 1 + 1; let c = missing == other; println(a, b, c); return a == b; }");
+}
+
+#[test]
+fn c_backend_errors() {
+  let programs = [
+    ("divide", "fn main() { println(\"before\"); return 1 / 0; }"),
+    ("overflow", "fn main() { return 2 ^ 31; }"),
+    ("math_type", "fn main() { let t = true; return 1 + t; }"),
+    ("undefined_variable", "fn main() { return x + 1; }"),
+    ("undefined_function", "fn main() { return nope(1); }"),
+    ("arity", "fn f(a) { return a; } fn main() { return f(1, 2); }"),
+    ("deep", "fn down(n) { return down(n + 1); } fn main() { return down(0); }"),
+    ("compare_type", "fn main() { let b = true; return b > 1; }"),
+    ("compare_bool", "fn main() { return 1 == true; }"),
+    ("no_main", "fn helper() { return 1; }"),
+  ];
+  for (test, source) in programs {
+    assert_compiles_like_interpreter(test, source);
+  }
+}
+
+#[test]
+fn c_backend_arithmetic_matches_interpreter() {
+  fn agrees(arithmetic: Arithmetic) -> bool {
+    let source = format_program(&Program { items: vec![Item::Expr(arithmetic.0)] });
+    match run_compiled_c("arithmetic", &source) {
+      Some(compiled) => compiled == interpreted_like_c(&source),
+      None => true,
+    }
+  }
+  QuickCheck::new().tests(25).quickcheck(agrees as fn(Arithmetic) -> bool);
+}
+
+#[test]
+fn c_backend_rejects_what_it_cannot_compile() {
+  let compile = |source: &str| asalang::codegen_c::compile(&program(source).unwrap().1).map(|_| ()).map_err(|error| error.to_string());
+  assert_eq!(compile("struct Point { x, y } fn main() { return 1; }"), Err("the C backend does not support structs".to_string()));
+  assert_eq!(compile("fn main() { return read_line(); }"), Err("the C backend does not support builtins besides print and println".to_string()));
+  assert_eq!(compile("fn main() { try { throw 1; } catch e { return e; } }"), Err("the C backend does not support throw and try".to_string()));
+  assert_eq!(compile("fn main() { return 1; } test \"ignored\" { assert(false); }"), Ok(()));
+}
+
+// WASM backend
+// Compile `source` to a module, validate it and instantiate it in an embedded wasm interpreter.
+fn instantiate_wasm(source: &str) -> (wasmi::Store<()>, wasmi::Instance) {
+  let (_, tree) = program(source).unwrap();
+  let wasm = asalang::codegen_wasm::compile(&tree).unwrap();
+  wasmparser::Validator::new().validate_all(&wasm).unwrap();
+  let engine = wasmi::Engine::default();
+  let module = wasmi::Module::new(&engine, &wasm[..]).unwrap();
+  let mut store = wasmi::Store::new(&engine, ());
+  let instance = wasmi::Linker::<()>::new(&engine).instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+  (store, instance)
+}
+
+// What the exported `main` returns, the way `start_interpreter` would report it.
+fn run_wasm(source: &str) -> Result<Value, &'static str> {
+  let (mut store, instance) = instantiate_wasm(source);
+  match instance.get_typed_func::<(), i64>(&store, "main") {
+    Ok(main) => asalang::codegen_wasm::decode(main.call(&mut store, ()).unwrap()),
+    Err(_) => Err("Undefined function"),
+  }
+}
+
+fn assert_wasm_like_interpreter(source: &str) {
+  let run = std::thread::Builder::new().stack_size(64 << 20).spawn({
+    let source = source.to_string();
+    move || (run_wasm(&source), start_interpreter(&program(&source).unwrap().1))
+  });
+  let (compiled, interpreted) = run.unwrap().join().unwrap();
+  assert_eq!(compiled, interpreted, "{}", source);
+}
+
+#[test]
+fn wasm_backend_functions_and_conditions() {
+  assert_wasm_like_interpreter(r#"fn fib(n) {
+  if n < 2 {
+    return n;
+  } else {
+    let a = fib(n - 1);
+    let b = fib(n - 2);
+    return a + b;
+  }
+}
+
+fn main() {
+  return fib(15);
+}"#);
+  assert_wasm_like_interpreter("2 ^ 10 - 24 / 5 * 3");
+  assert_wasm_like_interpreter(r#"fn classify(n) {
+  if n > 100 {
+    return 2;
+  } else if n == 0 {
+    return false;
+  } else {
+    return 1;
+  }
+}
+
+fn main() {
+  let a = classify(500);
+  let b = classify(7);
+  return classify(0);
+}"#);
+  assert_wasm_like_interpreter("fn main() { let flag = true; if flag == true { return 1; } else { return 2; } }");
+  assert_wasm_like_interpreter("fn main() { let n = 2; if n { return 1; } else { return 2; } }");
+  assert_wasm_like_interpreter("fn main() { let a = 3 >= 3; let b = 2 != 1 + 1; let c = missing == other; return a == b; }");
+  assert_wasm_like_interpreter("fn main() { let x = 0 - 1; return x ^ 3 + 0 ^ 0 + 2 ^ 0 - 1; }");
+}
+
+#[test]
+fn wasm_backend_errors() {
+  let programs = [
+    "fn main() { return 1 / 0; }",
+    "fn main() { return 2 ^ 31; }",
+    "fn main() { let t = true; return 1 + t; }",
+    "fn main() { return x + 1; }",
+    "fn main() { return nope(1); }",
+    "fn f(a) { return a; } fn main() { return f(1, 2); }",
+    "fn down(n) { return down(n + 1); } fn main() { return down(0); }",
+    "fn main() { let b = true; return b > 1; }",
+    "fn main() { return 1 == true; }",
+    "fn main() { let b = 1; if false { return 1; } else if b { return 2; } }",
+    "fn helper() { return 1; }",
+  ];
+  for source in programs {
+    assert_wasm_like_interpreter(source);
+  }
+}
+
+#[test]
+fn wasm_backend_calls_through_table() {
+  let source = "fn score(a, b) { return a * 10 + b; } fn main() { return score(1, 2); }";
+  let (mut store, instance) = instantiate_wasm(source);
+  let table = instance.get_table(&store, asalang::codegen_wasm::TABLE).unwrap();
+  assert_eq!(table.size(&store), 2);
+  let score = table.get(&store, 0).unwrap().funcref().unwrap().func().unwrap().typed::<(i64, i64), i64>(&store).unwrap();
+  let mut runtime = run_with(source, Default::default(), |_, _| ()).runtime;
+  for (a, b) in [(3, 4), (0, -7), (300000000, 1)] {
+    let compiled = asalang::codegen_wasm::decode(score.call(&mut store, (a as i64, b as i64)).unwrap());
+    assert_eq!(compiled, runtime.call("score", &[Value::Number(a), Value::Number(b)]));
+  }
+}
+
+// `instantiate_wasm` validates the module, which fails if two exports share a name.
+#[test]
+fn wasm_backend_function_named_table() {
+  assert_wasm_like_interpreter("fn table() { return 1; } fn main() { return table(); }");
+}
+
+#[test]
+fn wasm_backend_arithmetic_matches_interpreter() {
+  fn agrees(arithmetic: Arithmetic) -> bool {
+    let tree = Program { items: vec![Item::Expr(arithmetic.0)] };
+    run_wasm(&format_program(&tree)) == start_interpreter(&tree)
+  }
+  QuickCheck::new().tests(100).quickcheck(agrees as fn(Arithmetic) -> bool);
+}
+
+#[test]
+fn wasm_backend_rejects_what_it_cannot_compile() {
+  let compile = |source: &str| asalang::codegen_wasm::compile(&program(source).unwrap().1).map(|_| ()).map_err(|error| error.to_string());
+  assert_eq!(compile("fn main() { return \"text\"; }"), Err("the wasm backend does not support strings".to_string()));
+  assert_eq!(compile("fn main() { let x = 1; if x == \"a\" { return 1; } else { return 2; } }"), Err("the wasm backend does not support strings".to_string()));
+  assert_eq!(compile("fn main() { println(1); return 1; }"), Err("the wasm backend does not support builtins".to_string()));
+  assert_eq!(compile("enum Shape { Dot } fn main() { return 1; }"), Err("the wasm backend does not support enums".to_string()));
+  assert_eq!(compile("fn main() { return 1; } test \"ignored\" { assert(false); }"), Ok(()));
+}
+
+// Optimizer
+
+fn assert_optimizes(source: &str, expected: &str) {
+  let (rest, tree) = program(source).unwrap();
+  assert_eq!(rest, "");
+  let optimized = optimize(&tree);
+  assert_eq!(format_program(&optimized), expected);
+  assert_eq!(start_interpreter(&optimized), start_interpreter(&tree));
+}
+
+#[test]
+fn optimizer_folds_constants() {
+  assert_optimizes("fn main() { let x = 2 * 3 + 1; let y = x + 1 * 2; let z = 3 > 2; return x == 7; }",
+    "fn main() {\n  let x = 7;\n  let y = x + 2;\n  let z = true;\n  return x == 7;\n}\n");
+  // Errors are left for the run to report.
+  assert_optimizes("fn main() { let x = 1 / 0; let y = 2 ^ 40; return 1; }",
+    "fn main() {\n  let x = 1 / 0;\n  let y = 2 ^ 40;\n  return 1;\n}\n");
+}
+
+#[test]
+fn optimizer_prunes_dead_branches() {
+  assert_optimizes(r#"fn main(x) {
+  if 1 > 2 {
+    let a = 1;
+  } else if x == 1 {
+    let a = 2;
+  } else if 2 + 2 == 4 {
+    let a = 3;
+  } else {
+    let a = 4;
+  }
+}"#, "fn main(x) {\n  if false {\n    let a = 1;\n  } else if x == 1 {\n    let a = 2;\n  } else if true {\n    let a = 3;\n  }\n}\n");
+  assert_optimizes("fn main() { if 1 > 2 { let a = 1; } else { let a = 4; } }",
+    "fn main() {\n  if true {\n    let a = 4;\n  }\n}\n");
+  // An arm that returns may return false, which moves on to the next arm.
+  assert_optimizes("fn main() { if true { return false; } else { return 5; } }",
+    "fn main() {\n  if true {\n    return false;\n  } else {\n    return 5;\n  }\n}\n");
+}
+
+#[test]
+fn optimizer_inlines_trivial_functions() {
+  assert_optimizes(r#"fn double(a) { return a * 2; }
+fn first(a, b) { return a; }
+fn main() {
+  let x = double(4);
+  let y = double(x);
+  return first(x, 1);
+}"#, "fn double(a) {\n  return a * 2;\n}\n\nfn first(a, b) {\n  return a;\n}\n\nfn main() {\n  let x = 8;\n  let y = x * 2;\n  return first(x, 1);\n}\n");
+}
+
+#[test]
+fn optimized_programs_run_the_same() {
+  for source in [DEBUG_SOURCE, TRACE_SOURCE, POINTS, SHAPES, ERRORS] {
+    let (_, tree) = program(source).unwrap();
+    assert_eq!(start_interpreter(&optimize(&tree)), start_interpreter(&tree));
+  }
+}
+
+// Property tests
+
+// `between(g, 2, 4)` is 2, 3 or 4.
+fn between(g: &mut Gen, low: usize, high: usize) -> usize {
+  low + usize::arbitrary(g) % (high - low + 1)
+}
+
+fn pick<T: Clone>(g: &mut Gen, choices: &[T]) -> T {
+  g.choose(choices).unwrap().clone()
+}
+
+// Names that come close to the reserved words, to keep the keyword boundaries honest.
+const NAMES: [&str; 13] = ["a", "b", "x2", "_tmp", "total", "iffy", "letter", "returns", "matches", "true_", "fnord", "elsewhere", "test"];
+const TYPES: [&str; 3] = ["Point", "Shape", "Error"];
+
+fn name(g: &mut Gen) -> String {
+  pick(g, &NAMES).to_string()
+}
+
+fn type_name(g: &mut Gen) -> String {
+  pick(g, &TYPES).to_string()
+}
+
+fn text(g: &mut Gen) -> String {
+  let words = (0..between(g, 1, 3)).map(|_| pick(g, &["hello", "World", "42", "a1b2"])).collect::<Vec<&str>>();
+  words.join(" ")
+}
+
+fn list<T>(g: &mut Gen, low: usize, high: usize, mut item: impl FnMut(&mut Gen) -> T) -> Vec<T> {
+  (0..between(g, low, high)).map(|_| item(g)).collect()
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+  Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) }
+}
+
+// A random tree the parser could have built. Each function takes the nesting depth left; at 0 only
+// leaves are generated. Calls are never math operands and if arms hold only simple statements, since
+// the grammar has no way to write either.
+fn any_expr(g: &mut Gen, depth: usize) -> Expr {
+  if depth == 0 {
+    return any_leaf(g);
+  }
+  match between(g, 0, 7) {
+    0 => any_comparison(g, depth),
+    1 => any_math(g, depth),
+    2 => Expr::Call { name: pick(g, &["f", "print", "utils.helper", "p.dist"]).to_string(), args: list(g, 0, 3, |g| any_expr(g, depth - 1)) },
+    3 => Expr::Throw(Box::new(any_expr(g, depth - 1))),
+    4 => Expr::Match(Box::new(any_match(g, depth - 1))),
+    5 => Expr::String(text(g)),
+    6 => Expr::Bool(bool::arbitrary(g)),
+    _ => any_operand(g, depth),
+  }
+}
+
+fn any_leaf(g: &mut Gen) -> Expr {
+  match between(g, 0, 4) {
+    0 => Expr::Number(i32::arbitrary(g).saturating_abs()),
+    1 => Expr::Bool(bool::arbitrary(g)),
+    2 => Expr::String(text(g)),
+    3 => any_field(g),
+    _ => Expr::Identifier(name(g)),
+  }
+}
+
+fn any_field(g: &mut Gen) -> Expr {
+  let object = Expr::Identifier(name(g));
+  list(g, 1, 2, name).into_iter().fold(object, |object, field| Expr::Field { object: Box::new(object), field })
+}
+
+// Anything but a `throw`, which would take the rest of a comparison or sum as its value.
+fn any_comparison(g: &mut Gen, depth: usize) -> Expr {
+  let op = pick(g, &[BinaryOp::Eq, BinaryOp::Ne, BinaryOp::Lt, BinaryOp::Gt, BinaryOp::Le, BinaryOp::Ge]);
+  let side = |g: &mut Gen| match any_expr(g, depth - 1) {
+    Expr::Throw(value) => *value,
+    side => side,
+  };
+  let lhs = side(g);
+  binary(op, lhs, side(g))
+}
+
+fn any_math(g: &mut Gen, depth: usize) -> Expr {
+  let op = pick(g, &[BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div, BinaryOp::Pow]);
+  binary(op, any_operand(g, depth - 1), any_operand(g, depth - 1))
+}
+
+// What can stand on either side of `+`: numbers, variables, fields, structs, variants and anything
+// in parentheses.
+fn any_operand(g: &mut Gen, depth: usize) -> Expr {
+  if depth == 0 {
+    return match between(g, 0, 2) {
+      0 => Expr::Number(between(g, 0, 1000) as i32),
+      1 => any_field(g),
+      _ => Expr::Identifier(name(g)),
+    };
+  }
+  match between(g, 0, 5) {
+    0 => any_math(g, depth),
+    1 => any_comparison(g, depth),
+    2 => Expr::Struct { name: type_name(g), fields: list(g, 0, 2, |g| (name(g), any_expr(g, depth - 1))) },
+    3 => Expr::Variant { name: type_name(g), variant: name(g), args: list(g, 0, 2, |g| any_expr(g, depth - 1)) },
+    4 => Expr::Match(Box::new(any_match(g, depth - 1))),
+    _ => any_operand(g, 0),
+  }
+}
+
+fn any_pattern(g: &mut Gen, depth: usize) -> Pattern {
+  match between(g, 0, if depth == 0 { 4 } else { 5 }) {
+    0 => Pattern::Wildcard,
+    1 => Pattern::Number(between(g, 0, 100) as i32),
+    2 => Pattern::Bool(bool::arbitrary(g)),
+    3 => Pattern::String(text(g)),
+    4 => Pattern::Binding(name(g)),
+    _ => Pattern::Variant { name: type_name(g), variant: name(g), fields: list(g, 0, 2, |g| any_pattern(g, depth - 1)) },
+  }
+}
+
+fn any_condition(g: &mut Gen, depth: usize) -> Expr {
+  match between(g, 0, 2) {
+    0 => Expr::Let { pattern: any_pattern(g, 1), value: Box::new(any_expr(g, depth)) },
+    1 => any_comparison(g, depth.max(1)),
+    _ => any_expr(g, depth),
+  }
+}
+
+fn any_match(g: &mut Gen, depth: usize) -> Match {
+  let arms = list(g, 1, 3, |g| MatchArm {
+    patterns: list(g, 1, 2, |g| any_pattern(g, 1)),
+    guard: match bool::arbitrary(g) {
+      true => Some(any_condition(g, depth)),
+      false => None,
+    },
+    body: match depth > 0 && bool::arbitrary(g) {
+      true => ArmBody::Block(any_block(g, depth - 1)),
+      false => ArmBody::Expr(any_expr(g, depth)),
+    },
+  });
+  Match { scrutinee: any_expr(g, depth), arms }
+}
+
+// let, return, throw, a call or a field assignment.
+fn any_statement(g: &mut Gen, depth: usize) -> Stmt {
+  match between(g, 0, 4) {
+    0 => Stmt::Let { name: name(g), value: any_expr(g, depth) },
+    1 => Stmt::Return(any_expr(g, depth)),
+    2 => Stmt::Throw(any_expr(g, depth)),
+    3 => Stmt::Expr(Expr::Call { name: pick(g, &["f", "print", "utils.helper", "p.dist"]).to_string(), args: list(g, 0, 2, |g| any_expr(g, depth)) }),
+    _ => Stmt::Assign { target: any_field(g), value: any_expr(g, depth) },
+  }
+}
+
+fn any_if(g: &mut Gen, depth: usize) -> If {
+  let arm = |g: &mut Gen| list(g, 1, 2, |g| any_statement(g, depth));
+  If {
+    cond: any_condition(g, depth),
+    then: arm(g),
+    elifs: list(g, 0, 2, |g| (any_condition(g, depth), arm(g))),
+    else_: match bool::arbitrary(g) {
+      true => Some(arm(g)),
+      false => None,
+    },
+  }
+}
+
+// The statements of a function body, try block or match arm block.
+fn any_block(g: &mut Gen, depth: usize) -> Vec<Stmt> {
+  list(g, 1, 3, |g| match between(g, 0, if depth == 0 { 0 } else { 5 }) {
+    0..=2 => any_statement(g, depth),
+    3 => Stmt::If(any_if(g, depth - 1)),
+    4 => Stmt::Match(any_match(g, depth - 1)),
+    _ => Stmt::Try { body: any_block(g, depth - 1), variable: name(g), handler: any_block(g, depth - 1) },
+  })
+}
+
+fn any_function(g: &mut Gen) -> FnDecl {
+  FnDecl {
+    doc: list(g, 0, 2, |g| pick(g, &["", "Adds things up.", " indented"])).join("\n"),
+    name: name(g),
+    params: list(g, 0, 3, name),
+    body: any_block(g, 2),
+  }
+}
+
+fn any_item(g: &mut Gen) -> Item {
+  match between(g, 0, 8) {
+    0 => Item::Import { name: "utils".to_string(), path: pick(g, &[None, Some("lib/utils.asa".to_string())]) },
+    1 => Item::Struct { name: type_name(g), fields: list(g, 0, 3, name) },
+    2 => Item::Enum { name: type_name(g), variants: list(g, 0, 3, |g| VariantDecl { name: name(g), fields: list(g, 0, 2, name) }) },
+    3 => Item::Impl { name: type_name(g), methods: list(g, 0, 2, any_function) },
+    4 => Item::Stmt(Stmt::If(any_if(g, 1))),
+    5 => Item::Stmt(any_statement(g, 2)),
+    6 => Item::Test { name: text(g), body: any_block(g, 2) },
+    _ => Item::Fn(any_function(g)),
+  }
+}
+
+#[derive(Debug, Clone)]
+struct AnyProgram(Program);
+
+impl Arbitrary for AnyProgram {
+  fn arbitrary(g: &mut Gen) -> AnyProgram {
+    let mut items = list(g, 1, 4, any_item);
+    // A top-level expression has no `;` to end it, so it can only come last.
+    if bool::arbitrary(g) {
+      items.push(Item::Expr(any_expr(g, 2)));
+    }
+    AnyProgram(Program { items })
+  }
+}
+
+#[test]
+fn formatted_programs_parse_to_the_same_tree() {
+  fn round_trip(AnyProgram(tree): AnyProgram) -> TestResult {
+    let source = format_program(&tree);
+    match program(&source) {
+      Ok(("", reparsed)) if reparsed == tree => (),
+      other => return TestResult::error(format!("{}\nparsed as {:?}", source, other)),
+    }
+    let (recovered, errors) = program_with_recovery(&source);
+    match errors.is_empty() && recovered == tree {
+      true => TestResult::passed(),
+      false => TestResult::error(format!("{}\nrecovered as {:?}, {:?}", source, recovered, errors)),
+    }
+  }
+  QuickCheck::new().tests(300).quickcheck(round_trip as fn(AnyProgram) -> TestResult);
+}
+
+// Sums of small numbers, with the occasional large one to overflow.
+#[derive(Debug, Clone)]
+struct Arithmetic(Expr);
+
+impl Arithmetic {
+  fn expr(g: &mut Gen, depth: usize) -> Expr {
+    if depth == 0 || between(g, 0, 3) == 0 {
+      return match between(g, 0, 9) {
+        0 => Expr::Number(i32::arbitrary(g).saturating_abs()),
+        _ => Expr::Number(between(g, 0, 12) as i32),
+      };
+    }
+    let op = pick(g, &[BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div, BinaryOp::Pow]);
+    binary(op, Arithmetic::expr(g, depth - 1), Arithmetic::expr(g, depth - 1))
+  }
+
+  // What the interpreter should make of it, worked out with Rust's checked integer operations.
+  fn reference(expr: &Expr) -> Result<Value, &'static str> {
+    let (op, lhs, rhs) = match expr {
+      Expr::Number(n) => return Ok(Value::Number(*n)),
+      Expr::Binary { op, lhs, rhs } => (op, lhs, rhs),
+      _ => unreachable!(),
+    };
+    let (lhs, rhs) = match (Arithmetic::reference(lhs)?, Arithmetic::reference(rhs)?) {
+      (Value::Number(lhs), Value::Number(rhs)) => (lhs, rhs),
+      _ => unreachable!(),
+    };
+    let result = match op {
+      BinaryOp::Add => lhs.checked_add(rhs),
+      BinaryOp::Sub => lhs.checked_sub(rhs),
+      BinaryOp::Mul => lhs.checked_mul(rhs),
+      BinaryOp::Div if rhs == 0 => return Err("Division by zero"),
+      BinaryOp::Div => lhs.checked_div(rhs),
+      BinaryOp::Pow if rhs < 0 => Some(1),
+      BinaryOp::Pow => lhs.checked_pow(rhs as u32),
+      _ => unreachable!(),
+    };
+    result.map(Value::Number).ok_or("Number out of range")
+  }
+}
+
+impl Arbitrary for Arithmetic {
+  fn arbitrary(g: &mut Gen) -> Arithmetic {
+    Arithmetic(Arithmetic::expr(g, 4))
+  }
+}
+
+#[test]
+fn arithmetic_matches_a_reference_evaluator() {
+  fn evaluates(Arithmetic(expr): Arithmetic) -> TestResult {
+    let source = format_program(&Program { items: vec![Item::Expr(expr.clone())] });
+    let (_, tree) = program(&source).unwrap();
+    let expected = Arithmetic::reference(&expr);
+    match (start_interpreter(&tree), start_interpreter(&optimize(&tree))) {
+      (result, optimized) if result == expected && optimized == expected => TestResult::passed(),
+      (result, optimized) => TestResult::error(format!("{}expected {:?}, got {:?} and {:?} optimized", source, expected, result, optimized)),
+    }
+  }
+  QuickCheck::new().tests(500).quickcheck(evaluates as fn(Arithmetic) -> TestResult);
+}
+
+// Stops a program after a fixed number of statements, so that mangled programs that never finish
+// still end.
+struct StepLimit(usize);
+
+impl asalang::Debugger for StepLimit {
+  fn before_statement(&mut self, _runtime: &asalang::Runtime, _statement: &Stmt) -> Result<(), &'static str> {
+    self.0 = self.0.checked_sub(1).ok_or("Step limit reached")?;
+    Ok(())
+  }
+}
+
+// One of the test programs above with random cuts, copies and stray tokens.
+#[derive(Debug, Clone)]
+struct Mangled(String);
+
+impl Arbitrary for Mangled {
+  fn arbitrary(g: &mut Gen) -> Mangled {
+    let tokens = ["(", ")", "{", "}", ";", ",", ".", "::", "=>", "|", "^", "/", "\"", "/*", "//", "_", "0", "99999999999", "fn", "let", "if", "else", "match", "try", "catch", "throw", "return"];
+    let mut source: Vec<char> = pick(g, &[DEBUG_SOURCE, TRACE_SOURCE, POINTS, SHAPES, ERRORS]).chars().collect();
+    for _ in 0..between(g, 1, 4) {
+      let at = between(g, 0, source.len());
+      let len = between(g, 0, (source.len() - at).min(12));
+      match between(g, 0, 2) {
+        0 => {
+          source.drain(at..at + len);
+        },
+        1 => {
+          let copy: Vec<char> = source[at..at + len].to_vec();
+          source.splice(at..at, copy);
+        },
+        _ => {
+          source.splice(at..at, pick(g, &tokens).chars());
+        },
+      }
+    }
+    Mangled(source.into_iter().collect())
+  }
+}
+
+// Parse, format and run `source` every way there is, with its I/O kept in memory.
+fn exercise(source: &str) {
+  let _ = program_with_recovery(source);
+  let _ = format_source(source);
+  if let Ok((_, tree)) = program(source) {
+    let _ = warnings(&tree);
+    for tree in [optimize(&tree), tree] {
+      let mut runtime = asalang::Runtime::new();
+      runtime.set_io(std::rc::Rc::new(std::cell::RefCell::new(asalang::stdlib::MemoryIo::default())));
+      runtime.set_debugger(Box::new(StepLimit(10_000)));
+      let _ = runtime.start(&tree);
+    }
+  }
+}
+
+#[test]
+fn arbitrary_input_does_not_panic() {
+  fn survives(Mangled(source): Mangled, noise: String) -> bool {
+    // A deep recursion would overflow the small stack of a test thread before the runtime stops it.
+    let run = std::thread::Builder::new().stack_size(64 << 20).spawn(move || {
+      exercise(&source);
+      exercise(&noise);
+    });
+    run.unwrap().join().is_ok()
+  }
+  QuickCheck::new().tests(300).quickcheck(survives as fn(Mangled, String) -> bool);
+}
+
+#[test]
+fn out_of_range_numbers_do_not_parse() {
+  assert!(program("2147483648").is_err());
+  assert_eq!(program("2147483647").map(|(rest, _)| rest), Ok(""));
+}
+
+#[test]
+fn runaway_recursion_is_an_error() {
+  let run = std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+    let (_, tree) = program("fn down(n) { return down(n + 1); } fn main() { return down(0); }").unwrap();
+    start_interpreter(&tree)
+  });
+  assert_eq!(run.unwrap().join().unwrap(), Err("Call stack too deep"));
+}
+
+// Golden files
+// Every tests/programs/*.asa is parsed and run with its I/O kept in memory; modules it imports load
+// from beside it, as with `asa run`. What it prints, followed by the value it returns, is compared
+// against the .out file beside it, and its syntax, module or runtime error against the .err file; a
+// missing file means nothing is expected. Modules only imported go in a subdirectory. Run with BLESS=1
+// to write the files from what the programs do now.
+#[test]
+fn golden_programs() {
+  let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+  let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(&dir).unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|extension| extension == "asa"))
+    .collect();
+  paths.sort();
+  assert!(!paths.is_empty(), "no programs in {}", dir.display());
+  let bless = std::env::var_os("BLESS").is_some_and(|bless| bless == "1");
+  let mut failures = String::new();
+  for path in paths {
+    // Deep recursion needs more stack than a test thread has.
+    let program = path.clone();
+    let run = std::thread::Builder::new().stack_size(64 << 20).spawn(move || run_golden(&program));
+    let (out, err) = run.unwrap().join().unwrap();
+    for (extension, actual) in [("out", out), ("err", err)] {
+      let sidecar = path.with_extension(extension);
+      let expected = std::fs::read_to_string(&sidecar).unwrap_or_default();
+      if actual == expected {
+        continue;
+      }
+      if bless && actual.is_empty() {
+        std::fs::remove_file(&sidecar).unwrap();
+      } else if bless {
+        std::fs::write(&sidecar, &actual).unwrap();
+      } else {
+        failures += &format!("{}:\n{}\n", sidecar.display(), line_diff(&expected, &actual));
+      }
+    }
+  }
+  assert!(failures.is_empty(), "output differs from the golden files (run with BLESS=1 to update them)\n\n{}", failures);
+}
+
+// The stdout and stderr of the program at `path`, as `asa run` would print them without the file name.
+fn run_golden(path: &std::path::Path) -> (String, String) {
+  let source = std::fs::read_to_string(path).unwrap();
+  let tree = match program(&source) {
+    Ok(("", tree)) => tree,
+    _ => {
+      let errors: Vec<String> = program_with_recovery(&source).1.iter().map(|error| format!("{}\n", error)).collect();
+      return match errors.is_empty() {
+        true => (String::new(), "could not parse program\n".to_string()),
+        false => (String::new(), errors.concat()),
+      };
+    },
+  };
+  let io = std::rc::Rc::new(std::cell::RefCell::new(asalang::stdlib::MemoryIo::default()));
+  let mut runtime = asalang::Runtime::new();
+  runtime.set_io(io.clone());
+  let result = runtime.run_file(&tree, path);
+  let out = io.borrow().stdout.clone();
+  match result {
+    Ok(value) => (format!("{}{:?}\n", out, value), String::new()),
+    Err(error) => (out, format!("{}\n", error)),
+  }
+}
+
+// `expected` and `actual` line by line, with lines only in one marked `-` or `+`.
+fn line_diff(expected: &str, actual: &str) -> String {
+  let expected: Vec<&str> = expected.lines().collect();
+  let actual: Vec<&str> = actual.lines().collect();
+  // common[i][j] is the length of the longest common subsequence of expected[i..] and actual[j..].
+  let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+  for i in (0..expected.len()).rev() {
+    for j in (0..actual.len()).rev() {
+      common[i][j] = match expected[i] == actual[j] {
+        true => common[i + 1][j + 1] + 1,
+        false => common[i + 1][j].max(common[i][j + 1]),
+      };
+    }
+  }
+  let (mut i, mut j, mut diff) = (0, 0, String::new());
+  while i < expected.len() || j < actual.len() {
+    if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+      diff += &format!("  {}\n", expected[i]);
+      i += 1;
+      j += 1;
+    } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1]) {
+      diff += &format!("- {}\n", expected[i]);
+      i += 1;
+    } else {
+      diff += &format!("+ {}\n", actual[j]);
+      j += 1;
+    }
+  }
+  diff
+}
+
+#[test]
+fn golden_diff_marks_changed_lines() {
+  assert_eq!(line_diff("a\nb\nc\n", "a\nx\nc\nd\n"), "  a\n- b\n+ x\n  c\n+ d\n");
+}
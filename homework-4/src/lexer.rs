@@ -1,32 +1,33 @@
-
 use nom::{
     IResult,
     branch::alt,
-    combinator::{opt, cut},
-    multi::{many1, many0},
-    bytes::complete::{tag},
-    character::complete::{alpha1, alphanumeric1, digit1, space1, line_ending},
-
+    combinator::{map, map_res, not, opt, recognize},
+    multi::many0,
+    bytes::complete::{tag, take_till},
+    character::complete::{alpha1, alphanumeric1, anychar, digit1, multispace1},
+    sequence::{pair, preceded, tuple},
 };
 
-#[derive(Debug, Clone)]
-pub enum Token {
-    //Alpha(char),
-    // Number(i32),
-    // Boolean(bool),
-    // Identifier(String),
-    // String(String),
+// Words with a meaning of their own in the grammar. `true` and `false` are lexed as BoolVal instead.
+pub const KEYWORDS: [&str; 7] = ["fn", "let", "return", "if", "else", "match", "_"];
 
-    // LeftSquareBracket { value: char},      // [
-    // RightSquareBracket { value: char},     // ]
-    // Slash { value: char},                  // /
+// Byte offsets of a token in the source text; `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
 
-    Keyword { value: String},              // let, fn, pub
-    Identifier { value: String}, 
-    Equals,                 // =
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword { value: String},              // let, fn, return, if, else, match, _
+    Identifier { value: String},
+    Equals,                                // =
     NumVal { value: i32},
     BoolVal { value: bool},
-    Semicolon,              // ;
+    StringVal { value: String},            // "..."
+    DocComment { value: String},           // /// ... directly in front of `fn`
+    Semicolon,                             // ;
 
     LeftParen,
     RightParen,
@@ -34,117 +35,120 @@ pub enum Token {
     RightCurly,
 
     Comma,
-    Operator{ value: String},
-     
-
+    Pipe,                                  // |
+    FatArrow,                              // =>
+    Operator{ value: String},              // + - * / ^
+    Comparison{ value: String},            // == != < > <= >=
 }
 
-// pub fn tokenize(input: &str) -> IResult<&str, Vec<Token>> {
-//          let tokens: Vec<Token> = many0(token(input))?;
-//          Ok((input, tokens))
-//     }
-
-pub fn keyword(input: &str) -> IResult<&str, Token> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, keyword) = alt((tag("let "),tag("fn "),tag("return ")))(input)?;
-    //let (input, _) = many0(space1(input))?;
-    Ok((input, Token::Keyword{ value: keyword.to_string() } ))
-}
-pub fn identifier(input: &str) -> IResult<&str, Token> {
-    let (input, identifier) = alphanumeric1(input)?;
-
-    Ok((input, Token::Identifier{ value: identifier.to_string() }))
-}
-pub fn equals(input: &str) -> IResult<&str,Token> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, eq) = tag("=")(input)?;
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    Ok((input, Token::Equals))
-}
-pub fn num_val(input: &str) -> IResult<&str, Token> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, val) = digit1(input)?;                     // Consume at least 1 digit 0-9
-    let number = val.parse::<i32>().unwrap();              // Parse the string result into a usize
-    Ok((input, Token::NumVal{ value: number}))                 // Return the now partially consumed input with a number as well
-}
-pub fn bool_val(input: &str) -> IResult<&str, Token> {
-    let (input, result) = alt((tag("true"),tag("false")))(input)?;
-    let bool_value = if result == "true" {true} else {false};
-    Ok((input, Token::BoolVal{ value: bool_value}))
-  }
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
 
-pub fn semicolon(input: &str) -> IResult<&str,Token> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, eq) = tag(";")(input)?;
-    Ok((input, Token::Semicolon))
-}
-pub fn left_paren(input: &str) -> IResult<&str,Token> {
-    let (input, paren) = tag("(")(input)?;
-    Ok((input, Token::LeftParen))
-}
-pub fn right_paren(input: &str) -> IResult<&str,Token> {
-    let (input, paren) = tag(")")(input)?;
-    Ok((input, Token::LeftParen))
-}
-pub fn left_curly(input: &str) -> IResult<&str,Token> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, paren) = tag("{")(input)?;
-    Ok((input, Token::LeftCurly))
-}
-pub fn right_curly(input: &str) -> IResult<&str,Token> {
-    let (input, _) = many0(alt((space1,line_ending)))(input)?;
-    let (input, paren) = tag("}")(input)?;
-    Ok((input, Token::RightCurly))
-}
-pub fn comma(input: &str) -> IResult<&str, Token> {
-    let (input, paren) = tag(",")(input)?;
-    Ok((input, Token::Comma))
-}
-pub fn operator(input: &str) -> IResult<&str, Token> {
-    let (input, op) = alt((tag("+"),tag("-"),tag("*"),tag("/")))(input)?;
-    Ok((input, Token::Operator{ value: op.to_string()}))
-    
+// trivia = {whitespace | line_comment | block_comment}
+pub fn trivia(input: &str) -> IResult<&str, ()> {
+    let (input, _) = many0(alt((multispace1, block_comment, preceded(not(tag("///")), line_comment))))(input)?;
+    Ok((input, ()))
+}
+// A comment runs to the next newline; a lone `\r` doesn't end it.
+pub fn line_comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(tag("//"), take_till(|c| c == '\n')))(input)
+}
+// Block comments nest, so `/* a /* b */ c */` is a single comment.
+pub fn block_comment(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        tag("/*"),
+        many0(alt((block_comment, recognize(preceded(not(tag("*/")), anychar))))),
+        tag("*/"),
+    )))(input)
+}
+pub fn doc_comment(input: &str) -> IResult<&str, TokenKind> {
+    let (input, _) = tag("///")(input)?;
+    let (input, text) = take_till(|c| c == '\n')(input)?;
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    Ok((input, TokenKind::DocComment{ value: text.strip_prefix(' ').unwrap_or(text).to_string() }))
 }
 
-pub fn tokenize(input: &str) -> IResult<&str, Vec<Token>> {
-    let (input, result) = many1(alt((keyword,num_val,bool_val,identifier,equals,semicolon,left_paren,right_paren,left_curly,
-                                    right_curly,comma,operator)))(input)?;
-    Ok((input, result))
-  }
-/*
-    Below: Parser
-*/
-#[derive(Debug, Clone)]
-pub enum Node {
-  Program { children: Vec<Node> },
-  Statement { children: Vec<Node> },
-  FunctionReturn { children: Vec<Node> },
-  FunctionDefine { children: Vec<Node> },
-  FunctionArguments { children: Vec<Node> },
-  FunctionStatements { children: Vec<Node> },
-  //LogicExpression { name: String, children: Vec<Node> },
-  IfExpression { children: Vec<Node> },
-  Expression { children: Vec<Node> },
-  MathExpression {name: String, children: Vec<Node> },
-  MathAdd {children: Vec<Node> },
-  FunctionCall { name: String, children: Vec<Node> },
-  VariableDefine { children: Vec<Node> },
-  Number { value: i32 },
-  Bool { value: bool },
-  Identifier { value: String },
-  String { value: String },
-  Null,
+// Keywords, booleans and identifiers share one rule so that e.g. `letter` isn't lexed as `let` + `ter`.
+pub fn word(input: &str) -> IResult<&str, TokenKind> {
+    let (input, word) = recognize(pair(alt((alpha1, tag("_"))), many0(alt((alphanumeric1, tag("_"))))))(input)?;
+    let token = match word {
+        "true" => TokenKind::BoolVal{ value: true },
+        "false" => TokenKind::BoolVal{ value: false },
+        _ if KEYWORDS.contains(&word) => TokenKind::Keyword{ value: word.to_string() },
+        _ => TokenKind::Identifier{ value: word.to_string() },
+    };
+    Ok((input, token))
+}
+pub fn num_val(input: &str) -> IResult<&str, TokenKind> {
+    let (input, number) = map_res(digit1, |val: &str| val.parse::<i32>())(input)?;   // Consume at least 1 digit 0-9 that fits in an i32
+    Ok((input, TokenKind::NumVal{ value: number}))
+}
+pub fn string_val(input: &str) -> IResult<&str, TokenKind> {
+    let (input, _) = tag("\"")(input)?;
+    let (input, string) = take_till(|c| c == '"')(input)?;
+    let (input, _) = tag("\"")(input)?;
+    Ok((input, TokenKind::StringVal{ value: string.to_string()}))
+}
+pub fn comparison(input: &str) -> IResult<&str, TokenKind> {
+    let (input, op) = alt((tag("=="),tag("!="),tag("<="),tag(">="),tag("<"),tag(">")))(input)?;
+    Ok((input, TokenKind::Comparison{ value: op.to_string()}))
+}
+pub fn fat_arrow(input: &str) -> IResult<&str, TokenKind> {
+    let (input, _) = tag("=>")(input)?;
+    Ok((input, TokenKind::FatArrow))
+}
+pub fn equals(input: &str) -> IResult<&str, TokenKind> {
+    let (input, _) = tag("=")(input)?;
+    Ok((input, TokenKind::Equals))
+}
+pub fn operator(input: &str) -> IResult<&str, TokenKind> {
+    let (input, op) = alt((tag("+"),tag("-"),tag("*"),tag("/"),tag("^")))(input)?;
+    Ok((input, TokenKind::Operator{ value: op.to_string()}))
+}
+pub fn punctuation(input: &str) -> IResult<&str, TokenKind> {
+    alt((
+        map(tag(";"), |_| TokenKind::Semicolon),
+        map(tag("("), |_| TokenKind::LeftParen),
+        map(tag(")"), |_| TokenKind::RightParen),
+        map(tag("{"), |_| TokenKind::LeftCurly),
+        map(tag("}"), |_| TokenKind::RightCurly),
+        map(tag(","), |_| TokenKind::Comma),
+        map(tag("|"), |_| TokenKind::Pipe),
+    ))(input)
 }
 
-pub fn function_definition(input: Vec<Token>) -> IResult<Vec<Token>, Node> {
-  let keyword = input[0];
+// Order matters: `==` and `=>` must be tried before `=`, and `///` before the `/` operator.
+pub fn token(input: &str) -> IResult<&str, TokenKind> {
+    alt((doc_comment, word, num_val, string_val, comparison, fat_arrow, equals, operator, punctuation))(input)
 }
-pub fn statement(input: Vec<Token>) -> IResult<Vec<Token>, Node> {
-    
+
+// Lex as much of the input as possible; whatever can't be lexed is returned unconsumed.
+pub fn tokenize(source: &str) -> IResult<&str, Vec<Token>> {
+    let mut tokens = vec![];
+    let (mut input, _) = trivia(source)?;
+    while let (rest, Some(kind)) = opt(token)(input)? {
+        let span = Span{ start: source.len() - input.len(), end: source.len() - rest.len() };
+        tokens.push(Token{ kind, span });
+        input = trivia(rest)?.0;
+    }
+    Ok((input, attach_doc_comments(tokens)))
 }
-pub fn expression(input: Vec<Token>) -> IResult<Vec<Token>, Node> {
-    
+
+// Doc comments only mean something in front of a function definition, so drop any others.
+fn attach_doc_comments(tokens: Vec<Token>) -> Vec<Token> {
+    let mut kept: Vec<Token> = vec![];
+    for (ix, token) in tokens.iter().enumerate() {
+        if let TokenKind::DocComment{..} = token.kind {
+            let next = tokens[ix..].iter().find(|t| !matches!(t.kind, TokenKind::DocComment{..}));
+            let before_fn = matches!(next, Some(Token{ kind: TokenKind::Keyword{ value }, .. }) if value == "fn");
+            if !before_fn {
+                continue;
+            }
+        }
+        kept.push(token.clone());
+    }
+    kept
 }
-pub fn program(input: Vec<Token>) -> IResult<Vec<Token>, Node> {
-    
-}
\ No newline at end of file
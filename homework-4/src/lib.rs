@@ -3,5 +3,5 @@ extern crate nom;
 mod lexer;
 mod parser;
 
-pub use self::lexer::{tokenize, Token};
-pub use self::parser::{program, Node};
\ No newline at end of file
+pub use self::lexer::{tokenize, Span, Token, TokenKind};
+pub use self::parser::{program, Node};
@@ -1,33 +1,45 @@
 extern crate nom;
 extern crate asalang_parser;
 
-use asalang_parser::{tokenize, program, Token, Node};
+use asalang_parser::{tokenize, program};
 
-fn main() -> Result<(), nom::Err<(&'static str, nom::error::ErrorKind)>> {
-  let token_vec = tokenize(r#"let x = 5;"#);
-  match token_vec {
-    Ok((unparsed,tokens)) => {
-      println!("Unparsed Text: {:?}", unparsed);
+fn main() {
+  let source = r#"
+  fn foo(a,b,c) {
+    let x = a + 1;
+    // This is a comment
+    let y = bar(c - b);
+    return x * y;
+  }
+
+  fn bar(a) {
+    return a * 3;
+  }
+
+  fn main() {
+    return foo(1,2,3);
+  }
+  "#;
+
+  let tokens = match tokenize(source) {
+    Ok((unlexed, tokens)) => {
+      println!("Unlexed Text: {:?}", unlexed);
       println!("Tokens:\n {:#?}", tokens);
+      tokens
     }
     Err(error) => {
       println!("ERROR {:?}", error);
-      return 10;
+      return;
     }
-  }
+  };
 
-  let result = program(token_vec);
-  match result {
-    Ok((unparsed,tokens)) => {
-      println!("Unparsed Text: {:?}", unparsed);
-      println!("Tokens:\n {:#?}", tokens);
+  match program(&tokens) {
+    Ok((unparsed, tree)) => {
+      println!("Unparsed Tokens: {:?}", unparsed);
+      println!("Parse Tree:\n {:#?}", tree);
     }
     Err(error) => {
       println!("ERROR {:?}", error);
     }
   }
-
-
-    
-  Ok(())
 }
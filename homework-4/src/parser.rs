@@ -1,29 +1,39 @@
-// Here is where the various combinators are imported. You can find all the combinators here:
-// If you want to use it in your parser, you need to import it here. I've already imported a couple.
+// The parser works on the tokens produced by the lexer rather than on raw text, so it never has to deal
+// with whitespace or comments. It builds the same `Node` tree as the string-based Asa parser.
 
 use nom::{
     IResult,
+    Err,
     branch::alt,
-    combinator::opt,
+    combinator::{map, opt},
+    error::{Error, ErrorKind},
     multi::{many1, many0},
-    bytes::complete::{tag},
-    character::complete::{alphanumeric1, digit1, space1, line_ending},
   };
-  
+
+use crate::lexer::{Token, TokenKind};
+
+  pub type Tokens<'a> = &'a [Token];
+
   // Here are the different node types. You will use these to make your parser and your grammar.
   // You may add other nodes as you see fit, but these are expected by the runtime.
-  
+
   #[derive(Debug, Clone)]
   pub enum Node {
     Program { children: Vec<Node> },
     Statement { children: Vec<Node> },
     FunctionReturn { children: Vec<Node> },
-    FunctionDefine { children: Vec<Node> },
+    FunctionDefine { doc: String, children: Vec<Node> },
     FunctionArguments { children: Vec<Node> },
     FunctionStatements { children: Vec<Node> },
-    //LogicExpression { name: String, children: Vec<Node> },
     IfExpression { children: Vec<Node> },
+    IfBlock { condition: Vec<Node>, children: Vec<Node> },
+    ElseIfBlock { condition: Vec<Node>, children: Vec<Node> },
+    ElseBlock { children: Vec<Node> },
+    MatchExpression { children: Vec<Node> },
+    MatchArm { pattern: Vec<Node>, guard: Vec<Node>, children: Vec<Node> },
+    Wildcard,
     Expression { children: Vec<Node> },
+    ComparisonOperator { operator: String, children: Vec<Node> },
     MathExpression {name: String, children: Vec<Node> },
     MathAdd {children: Vec<Node> },
     FunctionCall { name: String, children: Vec<Node> },
@@ -34,10 +44,268 @@ use nom::{
     String { value: String },
     Null,
   }
-  
-  
-  
-  pub fn program(input: Vec<Token>) -> IResult<Vec<Token>, Node> {
-     let (input, result) = many1(alt((function_definition,statement,expression)))(input)?;
-     Ok((input, Node::Program{ children: result}))
-   }
\ No newline at end of file
+
+  // Consume one token if it satisfies `test`.
+  fn token_if<'a>(test: impl Fn(&TokenKind) -> bool) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token> {
+    move |input: Tokens<'a>| match input.split_first() {
+      Some((token, rest)) if test(&token.kind) => Ok((rest, token)),
+      _ => Err(Err::Error(Error::new(input, ErrorKind::Tag))),
+    }
+  }
+
+  // Consume exactly the token `kind`.
+  fn token<'a>(kind: TokenKind) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token> {
+    token_if(move |k| *k == kind)
+  }
+
+  fn keyword<'a>(word: &'static str) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token> {
+    token_if(move |k| matches!(k, TokenKind::Keyword{ value } if value == word))
+  }
+
+  // Here is the grammar, for your reference:
+
+  pub fn identifier(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, token) = token_if(|k| matches!(k, TokenKind::Identifier{..}))(input)?;
+    match &token.kind {
+      TokenKind::Identifier{ value } => Ok((input, Node::Identifier{ value: value.clone() })),
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn number(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, token) = token_if(|k| matches!(k, TokenKind::NumVal{..}))(input)?;
+    match token.kind {
+      TokenKind::NumVal{ value } => Ok((input, Node::Number{ value })),
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn boolean(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, token) = token_if(|k| matches!(k, TokenKind::BoolVal{..}))(input)?;
+    match token.kind {
+      TokenKind::BoolVal{ value } => Ok((input, Node::Bool{ value })),
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn string(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, token) = token_if(|k| matches!(k, TokenKind::StringVal{..}))(input)?;
+    match &token.kind {
+      TokenKind::StringVal{ value } => Ok((input, Node::String{ value: value.clone() })),
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn function_call(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, name) = identifier(input)?;
+    let call_name = match name {
+      Node::Identifier{ value } => value,
+      _ => String::from(""),
+    };
+    let (input, _) = token(TokenKind::LeftParen)(input)?;
+    let (input, args) = many0(arguments)(input)?;
+    let (input, _) = token(TokenKind::RightParen)(input)?;
+    Ok((input, Node::FunctionCall{name: call_name, children: args}))
+  }
+
+  // function_definition = {doc_comment}, "fn", identifier, "(", [arguments], ")", "{", {statement}, "}"
+  pub fn function_definition(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, docs) = many0(token_if(|k| matches!(k, TokenKind::DocComment{..})))(input)?;
+    let (input, _) = keyword("fn")(input)?;
+    let (input, function_name) = identifier(input)?;
+    let (input, _) = token(TokenKind::LeftParen)(input)?;
+    let (input, mut args) = many0(arguments)(input)?;
+    let (input, _) = token(TokenKind::RightParen)(input)?;
+    let (input, _) = token(TokenKind::LeftCurly)(input)?;
+    let (input, mut statements) = many1(alt((if_expression,match_expression,statement)))(input)?;
+    let (input, _) = token(TokenKind::RightCurly)(input)?;
+    let doc: Vec<String> = docs.iter().filter_map(|t| match &t.kind {
+      TokenKind::DocComment{ value } => Some(value.clone()),
+      _ => None,
+    }).collect();
+    let mut children = vec![function_name];
+    children.append(&mut args);
+    children.append(&mut statements);
+    Ok((input, Node::FunctionDefine{ doc: doc.join("\n"), children }))
+  }
+
+  pub fn function_return(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = keyword("return")(input)?;
+    let (input, return_value) = alt((function_call, expression))(input)?;
+    Ok((input, Node::FunctionReturn{ children: vec![return_value]}))
+  }
+
+  pub fn l4_infix(input: Tokens) -> IResult<Tokens, Node>{ // parenthesis
+    let(input, _) = token(TokenKind::LeftParen)(input)?;
+    let(input, expr) = expression(input)?;
+    let(input, _) = token(TokenKind::RightParen)(input)?;
+    Ok((input, expr))
+  }
+  pub fn l4(input: Tokens) -> IResult<Tokens, Node>{
+    alt((l4_infix, number, identifier, function_call))(input)
+  }
+
+  // Parse `next (op next)*` and fold it into left-associative MathExpressions.
+  fn left_assoc<'a>(input: Tokens<'a>, ops: &'static [&'static str], next: fn(Tokens) -> IResult<Tokens, Node>) -> IResult<Tokens<'a>, Node> {
+    let (mut input, mut head) = next(input)?;
+    while let Ok((rest, op)) = token_if(|k| matches!(k, TokenKind::Operator{ value } if ops.contains(&value.as_str())))(input) {
+      let (rest, rhs) = match next(rest) {
+        Ok(result) => result,
+        Err(_) => break,
+      };
+      let name = match &op.kind {
+        TokenKind::Operator{ value } => value.clone(),
+        _ => unreachable!(),
+      };
+      head = Node::MathExpression{name, children: vec![head, rhs]};
+      input = rest;
+    }
+    Ok((input, head))
+  }
+  pub fn l3(input: Tokens) -> IResult<Tokens, Node>{ // exponents
+    left_assoc(input, &["^"], l4)
+  }
+  pub fn l2(input: Tokens) -> IResult<Tokens, Node>{ // multiplication, division
+    left_assoc(input, &["*", "/"], l3)
+  }
+  pub fn l1(input: Tokens) -> IResult<Tokens, Node>{ // addition, subtraction
+    left_assoc(input, &["+", "-"], l2)
+  }
+
+  // math_expression = value , { ("+" | "-") , value } ;
+  pub fn math_expression(input: Tokens) -> IResult<Tokens, Node> {
+    l1(input)
+  }
+  pub fn expression(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, result) = alt((match_expression,comparison_operator,boolean,function_call, math_expression, number, string, identifier))(input)?;
+    Ok((input, Node::Expression{ children: vec![result]}))
+  }
+
+  pub fn statement(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, result) = alt((function_return,function_call,variable_define))(input)?;
+    let (input, _) = token(TokenKind::Semicolon)(input)?;
+    Ok((input, Node::Statement{ children: vec![result]}))
+  }
+
+  pub fn variable_define(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = keyword("let")(input)?;
+    let (input, variable) = identifier(input)?;
+    let (input, _) = token(TokenKind::Equals)(input)?;
+    let (input, expression) = expression(input)?;
+    Ok((input, Node::VariableDefine{ children: vec![variable, expression]}))
+  }
+
+  pub fn arguments(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, arg) = expression(input)?;
+    let (input, mut others) = many0(other_arg)(input)?;
+    let mut args = vec![arg];
+    args.append(&mut others);
+    Ok((input, Node::FunctionArguments{children: args}))
+  }
+
+  pub fn other_arg(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = token(TokenKind::Comma)(input)?;
+    expression(input)
+  }
+
+  // expression, ("==", ">", "<", ">=", "<=", "!="), expression
+  pub fn comparison_operator(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, exp1) = alt((boolean,function_call, math_expression, number, string, identifier))(input)?;
+    let (input, op) = token_if(|k| matches!(k, TokenKind::Comparison{..}))(input)?;
+    let (input, exp2) = alt((boolean,function_call, math_expression, number, string, identifier))(input)?;
+    let operator = match &op.kind {
+      TokenKind::Comparison{ value } => value.clone(),
+      _ => unreachable!(),
+    };
+    Ok((input, Node::ComparisonOperator{ operator, children: vec![exp1, exp2]}))
+  }
+
+  // condition = comparison_operator | boolean | "(", expression, ")" | identifier
+  pub fn condition(input: Tokens) -> IResult<Tokens, Node> {
+    alt((comparison_operator, boolean, l4_infix, identifier))(input)
+  }
+
+  // if_block, [{elseif_block}], [else_block]
+  pub fn if_expression(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, if_blk) = if_block(input)?;
+    let (input, elseif_blk) = many0(else_if_block)(input)?;
+    let (input, else_blk) = opt(else_block)(input)?;
+    let mut blocks = vec![if_blk];
+    blocks.extend(elseif_blk);
+    blocks.extend(else_blk);
+    Ok((input, Node::IfExpression{ children: blocks }))
+  }
+  // if_block = "if", condition, "{", {statement}, "}"
+  pub fn if_block(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = keyword("if")(input)?;
+    let (input, boolval) = condition(input)?;
+    let (input, statements) = statement_block(input)?;
+    Ok((input, Node::IfBlock{ condition: vec![boolval], children: statements}))
+  }
+  // elseif_block = "else", "if", condition, "{", {statement}, "}"
+  pub fn else_if_block(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = keyword("else")(input)?;
+    let (input, _) = keyword("if")(input)?;
+    let (input, boolval) = condition(input)?;
+    let (input, statements) = statement_block(input)?;
+    Ok((input, Node::ElseIfBlock{ condition: vec![boolval], children: statements}))
+  }
+  // else_block = "else", "{", {statement}, "}"
+  pub fn else_block(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = keyword("else")(input)?;
+    let (input, statements) = statement_block(input)?;
+    Ok((input, Node::ElseBlock{ children: statements}))
+  }
+  fn statement_block(input: Tokens) -> IResult<Tokens, Vec<Node>> {
+    let (input, _) = token(TokenKind::LeftCurly)(input)?;
+    let (input, statements) = many1(statement)(input)?;
+    let (input, _) = token(TokenKind::RightCurly)(input)?;
+    Ok((input, statements))
+  }
+
+  // match_expression = "match", expression, "{", match_arm, {match_arm}, "}"
+  pub fn match_expression(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, _) = keyword("match")(input)?;
+    let (input, scrutinee) = expression(input)?;
+    let (input, _) = token(TokenKind::LeftCurly)(input)?;
+    let (input, arms) = many1(match_arm)(input)?;
+    let (input, _) = token(TokenKind::RightCurly)(input)?;
+    let mut children = vec![scrutinee];
+    children.extend(arms);
+    Ok((input, Node::MatchExpression{ children }))
+  }
+  // match_arm = pattern, {"|", pattern}, ["if", condition], "=>", (match_block | expression), [","]
+  pub fn match_arm(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, first) = pattern(input)?;
+    let (input, others) = many0(|input| {
+      let (input, _) = token(TokenKind::Pipe)(input)?;
+      pattern(input)
+    })(input)?;
+    let (input, guard) = opt(|input| {
+      let (input, _) = keyword("if")(input)?;
+      condition(input)
+    })(input)?;
+    let (input, _) = token(TokenKind::FatArrow)(input)?;
+    let (input, children) = alt((match_block, map(expression, |e| vec![e])))(input)?;
+    let (input, _) = opt(token(TokenKind::Comma))(input)?;
+    let mut patterns = vec![first];
+    patterns.extend(others);
+    Ok((input, Node::MatchArm{ pattern: patterns, guard: guard.into_iter().collect(), children }))
+  }
+  // pattern = "_" | number | boolean | string | identifier
+  pub fn pattern(input: Tokens) -> IResult<Tokens, Node> {
+    alt((map(keyword("_"), |_| Node::Wildcard), number, boolean, string, identifier))(input)
+  }
+  // match_block = "{", {statement}, "}"
+  pub fn match_block(input: Tokens) -> IResult<Tokens, Vec<Node>> {
+    let (input, _) = token(TokenKind::LeftCurly)(input)?;
+    let (input, statements) = many1(alt((if_expression, match_expression, statement)))(input)?;
+    let (input, _) = token(TokenKind::RightCurly)(input)?;
+    Ok((input, statements))
+  }
+
+  // program = function_definition+ ;
+  pub fn program(input: Tokens) -> IResult<Tokens, Node> {
+    let (input, result) = many1(alt((if_expression,function_definition,statement,expression)))(input)?;
+    Ok((input, Node::Program{ children: result}))
+  }
@@ -0,0 +1,189 @@
+extern crate asalang_parser;
+extern crate nom;
+
+use asalang_parser::{tokenize, program, Node};
+
+macro_rules! test {
+  ($func:ident, $test:tt, $expected:expr) => (
+    #[test]
+    fn $func() -> Result<(),String> {
+      let tokens = match tokenize($test) {
+        Ok((input, tokens)) => {
+          assert_eq!(input, "");
+          tokens
+        },
+        Err(e) => return Err(format!("{:?}",e)),
+      };
+      match program(&tokens) {
+        Ok((input, p)) => {
+          assert!(input.is_empty());
+          assert_eq!(tree(&p), $expected);
+          Ok(())
+        },
+        Err(e) => Err(format!("{:?}",e)),
+      }
+    }
+  )
+}
+
+// A parse tree as an s-expression, one list per node, to keep the expected trees readable.
+fn tree(node: &Node) -> String {
+  let list = |name: &str, children: &[Node]| {
+    let mut out = format!("({}", name);
+    for child in children {
+      out += " ";
+      out += &tree(child);
+    }
+    out + ")"
+  };
+  match node {
+    Node::Program{ children } => list("program", children),
+    Node::Statement{ children } => list("stmt", children),
+    Node::FunctionReturn{ children } => list("return", children),
+    Node::FunctionDefine{ children, .. } => list("fn", children),
+    Node::FunctionArguments{ children } => list("args", children),
+    Node::FunctionStatements{ children } => list("stmts", children),
+    Node::IfExpression{ children } => list("if", children),
+    Node::IfBlock{ condition, children } => format!("({} {})", list("if-block", condition), list("then", children)),
+    Node::ElseIfBlock{ condition, children } => format!("({} {})", list("else-if", condition), list("then", children)),
+    Node::ElseBlock{ children } => list("else", children),
+    Node::MatchExpression{ children } => list("match", children),
+    Node::MatchArm{ pattern, guard, children } => format!("(arm {} {} {})", list("patterns", pattern), list("guard", guard), list("body", children)),
+    Node::Wildcard => "_".to_string(),
+    Node::Expression{ children } => list("expr", children),
+    Node::ComparisonOperator{ operator, children } => list(operator, children),
+    Node::MathExpression{ name, children } => list(name, children),
+    Node::MathAdd{ children } => list("add", children),
+    Node::FunctionCall{ name, children } => list(&format!("call {}", name), children),
+    Node::VariableDefine{ children } => list("let", children),
+    Node::Number{ value } => value.to_string(),
+    Node::Bool{ value } => value.to_string(),
+    Node::Identifier{ value } => value.clone(),
+    Node::String{ value } => format!("{:?}", value),
+    Node::Null => "null".to_string(),
+  }
+}
+
+test!(numeric, r#"123"#, "(program (expr 123))");
+test!(identifier, r#"x"#, "(program (expr x))");
+test!(string, r#""hello world""#, r#"(program (expr "hello world"))"#);
+test!(bool_true, r#"true"#, "(program (expr true))");
+test!(bool_false, r#"false"#, "(program (expr false))");
+test!(function_call, r#"foo()"#, "(program (expr (call foo)))");
+test!(function_call_one_arg, r#"foo(a)"#, "(program (expr (call foo (args (expr a)))))");
+test!(function_call_more_args, r#"foo(a,b,c)"#, "(program (expr (call foo (args (expr a) (expr b) (expr c)))))");
+test!(variable_define, r#"let x = 123;"#, "(program (stmt (let x (expr 123))))");
+test!(variable_init, r#"let x = 1;"#, "(program (stmt (let x (expr 1))))");
+test!(variable_bool, r#"let bool = true;"#, "(program (stmt (let bool (expr true))))");
+test!(variable_string, r#"let string = "Hello World";"#, r#"(program (stmt (let string (expr "Hello World"))))"#);
+test!(variable_init_no_space, r#"let x=1;"#, "(program (stmt (let x (expr 1))))");
+test!(math, r#"1 + 1"#, "(program (expr (+ 1 1)))");
+test!(math_no_space, r#"1+1"#, "(program (expr (+ 1 1)))");
+test!(math_subtraction, r#"1 - 1"#, "(program (expr (- 1 1)))");
+test!(math_multiply, r#"2 * 4"#, "(program (expr (* 2 4)))");
+test!(math_divide, r#"6 / 2"#, "(program (expr (/ 6 2)))");
+test!(math_exponent, r#"2 ^ 4"#, "(program (expr (^ 2 4)))");
+test!(math_more_terms, r#"10 + 2*6"#, "(program (expr (+ 10 (* 2 6))))");
+test!(math_more_terms_paren, r#"((10+2)*6)/4"#, "(program (expr (/ (expr (* (expr (+ 10 2)) 6)) 4)))");
+test!(assign_math, r#"let x = 1 + 1;"#, "(program (stmt (let x (expr (+ 1 1)))))");
+test!(assign_function, r#"let x = foo();"#, "(program (stmt (let x (expr (call foo)))))");
+test!(assign_function_arguments, r#"let x = foo(a,b,c);"#, "(program (stmt (let x (expr (call foo (args (expr a) (expr b) (expr c)))))))");
+test!(define_function, r#"fn main(){return foo();} fn foo(){return 5;}"#, "(program (fn main (stmt (return (call foo)))) (fn foo (stmt (return (expr 5)))))");
+test!(define_function_args, r#"fn main(){return foo(1,2,3);} fn foo(a,b,c){return a+b+c;}"#, "(program (fn main (stmt (return (call foo (args (expr 1) (expr 2) (expr 3)))))) (fn foo (args (expr a) (expr b) (expr c)) (stmt (return (expr (+ (+ a b) c))))))");
+test!(define_function_more_statement, r#"fn main() {
+  return foo();
+}
+fn foo(){
+  let x = 5;
+  return x;
+}"#, "(program (fn main (stmt (return (call foo)))) (fn foo (stmt (let x (expr 5))) (stmt (return (expr x)))))");
+test!(define_full_program, r#"fn foo(a,b,c) {
+  let x = a + 1;
+  let y = bar(c - b);
+  return x * y;
+}
+
+fn bar(a) {
+  return a * 3;
+}
+
+fn main() {
+  return foo(1,2,3);  
+}"#, "(program (fn foo (args (expr a) (expr b) (expr c)) (stmt (let x (expr (+ a 1)))) (stmt (let y (expr (call bar (args (expr (- c b))))))) (stmt (return (expr (* x y))))) (fn bar (args (expr a)) (stmt (return (expr (* a 3))))) (fn main (stmt (return (call foo (args (expr 1) (expr 2) (expr 3)))))))");
+
+
+// Token pipeline
+use asalang_parser::{Span, TokenKind};
+
+test!(comparisons_and_if, r#"
+fn main() {
+  let x = 10;
+  if x <= 5 {
+    return 1;
+  } else if x != 10 {
+    return 2;
+  } else {
+    return 3;
+  }
+}
+"#, "(program (fn main (stmt (let x (expr 10))) (if ((if-block (<= x 5)) (then (stmt (return (expr 1))))) ((else-if (!= x 10)) (then (stmt (return (expr 2))))) (else (stmt (return (expr 3)))))))");
+test!(comments_are_skipped, r#"
+/* header /* nested */ */
+fn main() {
+  // a line comment
+  return 2 ^ 3; // trailing
+}
+"#, "(program (fn main (stmt (return (expr (^ 2 3))))))");
+test!(match_over_tokens, r#"
+fn main() {
+  return match 3 { 1 => 10, 2 | 3 => 23, n if n > 5 => n, _ => 0 };
+}
+"#, "(program (fn main (stmt (return (expr (match (expr 3) (arm (patterns 1) (guard) (body (expr 10))) (arm (patterns 2 3) (guard) (body (expr 23))) (arm (patterns n) (guard (> n 5)) (body (expr n))) (arm (patterns _) (guard) (body (expr 0)))))))))");
+
+#[test]
+fn tokens_have_spans() {
+  let (rest, tokens) = tokenize(r#"let s = "hi there";"#).unwrap();
+  assert_eq!(rest, "");
+  let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+  assert_eq!(kinds, vec![
+    TokenKind::Keyword{ value: "let".to_string() },
+    TokenKind::Identifier{ value: "s".to_string() },
+    TokenKind::Equals,
+    TokenKind::StringVal{ value: "hi there".to_string() },
+    TokenKind::Semicolon,
+  ]);
+  assert_eq!(tokens[3].span, Span{ start: 8, end: 18 });
+}
+
+#[test]
+fn comparison_operators_lex_greedily() {
+  let (_, tokens) = tokenize("a >= b => c == d = e").unwrap();
+  let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+  assert_eq!(kinds[1], TokenKind::Comparison{ value: ">=".to_string() });
+  assert_eq!(kinds[3], TokenKind::FatArrow);
+  assert_eq!(kinds[5], TokenKind::Comparison{ value: "==".to_string() });
+  assert_eq!(kinds[7], TokenKind::Equals);
+}
+
+#[test]
+fn doc_comments_attach_to_functions() {
+  let (_, tokens) = tokenize("/// Doubles a.\nfn double(a) { return a * 2; }\n/// stray\nlet x = 1;").unwrap();
+  let (rest, tree) = program(&tokens).unwrap();
+  assert!(rest.is_empty());
+  match tree {
+    Node::Program{ children } => match &children[0] {
+      Node::FunctionDefine{ doc, .. } => assert_eq!(doc, "Doubles a."),
+      other => panic!("expected a function, got {:?}", other),
+    },
+    _ => unreachable!(),
+  }
+}
+
+#[test]
+fn comments_end_only_at_newlines() {
+  // A lone `\r` stays inside the comment, and a `\r\n` line ending isn't part of a doc comment.
+  let (rest, tokens) = tokenize("// one\r two\nlet x = 1; /// doc\r\nfn f() { return x; }").unwrap();
+  assert_eq!(rest, "");
+  assert_eq!(tokens[0].kind, TokenKind::Keyword{ value: "let".to_string() });
+  assert!(tokens.iter().any(|t| t.kind == TokenKind::DocComment{ value: "doc".to_string() }));
+}